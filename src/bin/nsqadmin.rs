@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use nsq_rs::nsqadmin::{Config, NSQAdmin};
+use tokio::{
+    select,
+    signal::{
+        self,
+        unix::{signal, SignalKind},
+    },
+};
+
+#[tokio::main]
+async fn main() {
+    let opts = Config::from_args().into_options();
+
+    // 创建一个订阅者，将格式化trace输出到stdout
+    let subscriber = tracing_subscriber::FmtSubscriber::new();
+    // 此后发生的所有trace都由这个订阅者处理
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    let (nsqadmin, token) = match NSQAdmin::new(opts).await {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("failed to instantiate nsqadmin - {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut sign_term = signal(SignalKind::terminate()).unwrap();
+
+    let handle = tokio::spawn(Arc::new(nsqadmin).start());
+
+    select! {
+        _ = signal::ctrl_c() => {
+            println!("Signint received.");
+        }
+        _ = sign_term.recv() => {
+            println!("Signterm received.");
+        }
+    }
+    token.cancel();
+    println!("Waiting NSQAdmin to shutdown!");
+    let _ = handle.await;
+    println!("NSQAdmin shutdown successfully!");
+}
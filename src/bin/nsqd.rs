@@ -1,3 +1,6 @@
+use std::sync::Arc;
+
+use nsq_rs::nsqd::{Config, NSQD};
 use tokio::{
     select,
     signal::{
@@ -5,32 +8,45 @@ use tokio::{
         unix::{signal, SignalKind},
     },
 };
-use tokio_util::sync::CancellationToken;
-use tracing::info;
 
 #[tokio::main]
 async fn main() {
-    // 创建一个订阅者，将格式化trace输出到stdout
+    // 加载配置时还不知道日志级别，先用默认的订阅者输出配置相关的警告
     let subscriber = tracing_subscriber::FmtSubscriber::new();
+    let opts = tracing::subscriber::with_default(subscriber, || Config::from_args().into_options());
+    let opts = match opts {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("failed to load options - {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // 创建一个订阅者，将格式化trace输出到stdout
+    let subscriber = tracing_subscriber::FmtSubscriber::builder()
+        .with_max_level(opts.log_level)
+        .finish();
     // 此后发生的所有trace都由这个订阅者处理
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
-    let token = CancellationToken::new();
+    let (nsqd, token) = match NSQD::new(opts).await {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("failed to instantiate nsqd - {e}");
+            std::process::exit(1);
+        }
+    };
 
     let mut sign_term = signal(SignalKind::terminate()).unwrap();
 
-    let handle = tokio::spawn(async { todo!("启动nsqd") });
+    let handle = tokio::spawn(Arc::new(nsqd).start());
 
-    loop {
-        select! {
-            _ = signal::ctrl_c() => {
-                println!("Signint received.");
-                break;
-            }
-            _ = sign_term.recv() => {
-                println!("Signterm received.");
-                break;
-            }
+    select! {
+        _ = signal::ctrl_c() => {
+            println!("Signint received.");
+        }
+        _ = sign_term.recv() => {
+            println!("Signterm received.");
         }
     }
     token.cancel();
@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use nsq_rs::nsqlookupd::{NSQLookupd, Options};
+use tokio::{
+    select,
+    signal::{
+        self,
+        unix::{signal, SignalKind},
+    },
+};
+
+#[tokio::main]
+async fn main() {
+    // 创建一个订阅者，将格式化trace输出到stdout
+    let subscriber = tracing_subscriber::FmtSubscriber::new();
+    // 此后发生的所有trace都由这个订阅者处理
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    let (nsqlookupd, token) = match NSQLookupd::new(Options::new()).await {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("failed to instantiate nsqlookupd - {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut sign_term = signal(SignalKind::terminate()).unwrap();
+
+    let handle = tokio::spawn(Arc::new(nsqlookupd).start());
+
+    select! {
+        _ = signal::ctrl_c() => {
+            println!("Signint received.");
+        }
+        _ = sign_term.recv() => {
+            println!("Signterm received.");
+        }
+    }
+    token.cancel();
+    println!("Waiting NSQLookupd to shutdown!");
+    let _ = handle.await;
+    println!("NSQLookupd shutdown successfully!");
+}
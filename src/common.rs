@@ -1,3 +1,110 @@
+use std::{
+    ffi::OsString,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::CommandFactory;
+
 use crate::errors;
 
 pub type Result<T> = std::result::Result<T, errors::NsqError>;
+
+const MAX_NAME_LENGTH: usize = 64;
+const EPHEMERAL_SUFFIX: &str = "#ephemeral";
+
+// topic名称只允许 [.a-zA-Z0-9_-]，可以带有#ephemeral后缀，长度在1~64之间
+pub fn is_valid_topic_name(name: &str) -> bool {
+    is_valid_name(name)
+}
+
+pub fn is_valid_channel_name(name: &str) -> bool {
+    is_valid_name(name)
+}
+
+// 带有#ephemeral后缀的topic/channel不会持久化到磁盘
+pub fn is_ephemeral(name: &str) -> bool {
+    name.ends_with(EPHEMERAL_SUFFIX)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return false;
+    }
+    let name = name.strip_suffix(EPHEMERAL_SUFFIX).unwrap_or(name);
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+// 当前的unix时间戳，单位纳秒
+pub fn unix_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap() // 这里不可能panic
+        .as_nanos() as i64
+}
+
+// golang的flag同时支持-flag和--flag两种写法，这里统一转换成--flag。
+// 只转换已知的长参数名，避免把-10这样的负数参数值改成--10
+pub fn normalize_args<C: CommandFactory>(
+    args: impl IntoIterator<Item = OsString>,
+) -> Vec<OsString> {
+    let command = C::command();
+    let is_long = |name: &str| command.get_arguments().any(|a| a.get_long() == Some(name));
+
+    args.into_iter()
+        .enumerate()
+        .map(|(i, arg)| match arg.to_str() {
+            Some(s) if i > 0 && s.starts_with('-') && !s.starts_with("--") => {
+                let name = s[1..].split('=').next().unwrap();
+                if is_long(name) {
+                    OsString::from(format!("-{s}"))
+                } else {
+                    arg
+                }
+            }
+            _ => arg,
+        })
+        .collect()
+}
+
+// golang格式的时间间隔，例如"1m30s"、"250ms"
+pub fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    if s == "0" {
+        return Ok(Duration::ZERO);
+    }
+    if s.is_empty() {
+        return Err("invalid duration \"\"".to_owned());
+    }
+
+    let mut nanos = 0f64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let n: f64 = rest[..end]
+            .parse()
+            .map_err(|_| format!("invalid duration {s:?}"))?;
+        rest = &rest[end..];
+
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let unit = match &rest[..end] {
+            "ns" => 1.0,
+            "us" | "µs" | "μs" => 1e3,
+            "ms" => 1e6,
+            "s" => 1e9,
+            "m" => 60e9,
+            "h" => 3600e9,
+            "" => return Err(format!("missing unit in duration {s:?}")),
+            u => return Err(format!("unknown unit {u:?} in duration {s:?}")),
+        };
+        rest = &rest[end..];
+        nanos += n * unit;
+    }
+
+    Ok(Duration::from_nanos(nanos as u64))
+}
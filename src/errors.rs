@@ -1,5 +1,6 @@
-use std::io;
+use std::{fmt, io};
 
+use axum::http::StatusCode;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -9,4 +10,256 @@ pub enum NsqError {
 
     #[error("Invalid message size")]
     InvalidMsgLength,
+
+    #[error("exiting")]
+    Exiting,
+
+    #[error("ID not in flight")]
+    MsgNotInFlight,
+
+    #[error("ID already in flight")]
+    MsgAlreadyInFlight,
+
+    #[error("client does not own message")]
+    MsgNotOwned,
+
+    #[error("invalid options - {0}")]
+    InvalidOptions(String),
+
+    #[error("TLS error - {0}")]
+    TlsError(#[from] rustls::Error),
+
+    #[error("auth error - {0}")]
+    AuthError(String),
+
+    #[error("topic does not exist")]
+    TopicNotFound,
+
+    #[error("channel does not exist")]
+    ChannelNotFound,
+
+    #[error("time has gone backwards")]
+    TimeBackwards,
+
+    #[error("sequence expired")]
+    SequenceExpired,
+
+    #[error("consumers for {0} exceeds limit of {1}")]
+    TooManyConsumers(String, isize),
+
+    // 客户端错误，以Error帧返回给客户端后连接继续保持
+    #[error("{0} {1}")]
+    ClientErr(ErrorCode, String),
+
+    // 致命的客户端错误，返回Error帧之后服务端会断开连接
+    #[error("{0} {1}")]
+    FatalClientErr(ErrorCode, String),
+}
+
+impl NsqError {
+    // 除了ClientErr之外的错误都需要断开连接
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, NsqError::ClientErr(..))
+    }
+
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            NsqError::ClientErr(code, _) | NsqError::FatalClientErr(code, _) => Some(*code),
+            _ => None,
+        }
+    }
+
+    // Error帧的内容。非客户端错误不把内部细节暴露给客户端
+    pub fn frame_body(&self) -> Vec<u8> {
+        match self {
+            NsqError::ClientErr(..) | NsqError::FatalClientErr(..) => self.to_string().into_bytes(),
+            _ => ErrorCode::Invalid.as_str().as_bytes().to_vec(),
+        }
+    }
+}
+
+// 协议中的错误码，和golang版本保持一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Invalid,
+    BadBody,
+    BadTopic,
+    BadChannel,
+    BadMessage,
+    BadProtocol,
+    PubFailed,
+    MpubFailed,
+    FinFailed,
+    ReqFailed,
+    TouchFailed,
+    IdentifyFailed,
+    AuthDisabled,
+    AuthError,
+    AuthFailed,
+    AuthFirst,
+    Unauthorized,
+    TooManyChannelConsumers,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Invalid => "E_INVALID",
+            ErrorCode::BadBody => "E_BAD_BODY",
+            ErrorCode::BadTopic => "E_BAD_TOPIC",
+            ErrorCode::BadChannel => "E_BAD_CHANNEL",
+            ErrorCode::BadMessage => "E_BAD_MESSAGE",
+            ErrorCode::BadProtocol => "E_BAD_PROTOCOL",
+            ErrorCode::PubFailed => "E_PUB_FAILED",
+            ErrorCode::MpubFailed => "E_MPUB_FAILED",
+            ErrorCode::FinFailed => "E_FIN_FAILED",
+            ErrorCode::ReqFailed => "E_REQ_FAILED",
+            ErrorCode::TouchFailed => "E_TOUCH_FAILED",
+            ErrorCode::IdentifyFailed => "E_IDENTIFY_FAILED",
+            ErrorCode::AuthDisabled => "E_AUTH_DISABLED",
+            ErrorCode::AuthError => "E_AUTH_ERROR",
+            ErrorCode::AuthFailed => "E_AUTH_FAILED",
+            ErrorCode::AuthFirst => "E_AUTH_FIRST",
+            ErrorCode::Unauthorized => "E_UNAUTHORIZED",
+            ErrorCode::TooManyChannelConsumers => "E_TOO_MANY_CHANNEL_CONSUMERS",
+        }
+    }
+
+    // HTTP接口返回的错误信息不带E_前缀
+    pub fn http_text(&self) -> &'static str {
+        self.as_str().trim_start_matches("E_")
+    }
+
+    // HTTP接口返回错误时使用的状态码
+    pub(crate) fn http_status(&self) -> StatusCode {
+        match self {
+            ErrorCode::Invalid
+            | ErrorCode::BadBody
+            | ErrorCode::BadTopic
+            | ErrorCode::BadChannel
+            | ErrorCode::BadProtocol
+            | ErrorCode::IdentifyFailed
+            | ErrorCode::AuthDisabled
+            | ErrorCode::FinFailed
+            | ErrorCode::ReqFailed
+            | ErrorCode::TouchFailed => StatusCode::BAD_REQUEST,
+            ErrorCode::BadMessage => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::AuthFirst | ErrorCode::AuthFailed | ErrorCode::Unauthorized => {
+                StatusCode::FORBIDDEN
+            }
+            ErrorCode::AuthError => StatusCode::BAD_GATEWAY,
+            ErrorCode::PubFailed | ErrorCode::MpubFailed | ErrorCode::TooManyChannelConsumers => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_codes() {
+        let table = [
+            (ErrorCode::Invalid, "E_INVALID", StatusCode::BAD_REQUEST),
+            (ErrorCode::BadBody, "E_BAD_BODY", StatusCode::BAD_REQUEST),
+            (ErrorCode::BadTopic, "E_BAD_TOPIC", StatusCode::BAD_REQUEST),
+            (
+                ErrorCode::BadChannel,
+                "E_BAD_CHANNEL",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ErrorCode::BadMessage,
+                "E_BAD_MESSAGE",
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ),
+            (
+                ErrorCode::BadProtocol,
+                "E_BAD_PROTOCOL",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ErrorCode::PubFailed,
+                "E_PUB_FAILED",
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ErrorCode::MpubFailed,
+                "E_MPUB_FAILED",
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ErrorCode::FinFailed,
+                "E_FIN_FAILED",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ErrorCode::ReqFailed,
+                "E_REQ_FAILED",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ErrorCode::TouchFailed,
+                "E_TOUCH_FAILED",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ErrorCode::IdentifyFailed,
+                "E_IDENTIFY_FAILED",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ErrorCode::AuthDisabled,
+                "E_AUTH_DISABLED",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ErrorCode::AuthError,
+                "E_AUTH_ERROR",
+                StatusCode::BAD_GATEWAY,
+            ),
+            (
+                ErrorCode::AuthFailed,
+                "E_AUTH_FAILED",
+                StatusCode::FORBIDDEN,
+            ),
+            (ErrorCode::AuthFirst, "E_AUTH_FIRST", StatusCode::FORBIDDEN),
+            (
+                ErrorCode::Unauthorized,
+                "E_UNAUTHORIZED",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                ErrorCode::TooManyChannelConsumers,
+                "E_TOO_MANY_CHANNEL_CONSUMERS",
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+        ];
+        for (code, text, status) in table {
+            assert_eq!(code.as_str(), text);
+            assert_eq!(code.http_text(), &text[2..]);
+            assert_eq!(code.http_status(), status, "{code}");
+
+            let err = NsqError::ClientErr(code, "detail".to_owned());
+            assert_eq!(err.frame_body(), format!("{text} detail").into_bytes());
+            assert!(!err.is_fatal());
+            let err = NsqError::FatalClientErr(code, "detail".to_owned());
+            assert_eq!(err.frame_body(), format!("{text} detail").into_bytes());
+            assert!(err.is_fatal());
+        }
+
+        // 内部错误不暴露细节
+        let err = NsqError::InvalidOptions("secret".to_owned());
+        assert_eq!(err.frame_body(), b"E_INVALID");
+        assert!(err.is_fatal());
+        assert_eq!(err.code(), None);
+    }
 }
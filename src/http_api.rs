@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use hyper::server::conn::http1;
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use serde_json::json;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    select,
+};
+use tracing::debug;
+
+use crate::{
+    common::{is_valid_channel_name, is_valid_topic_name},
+    errors::NsqError,
+    shutdown::Shutdown,
+};
+
+pub(crate) type HttpResult<T> = std::result::Result<T, HttpError>;
+
+// 对应golang中的http_api.Err，以{"message": "..."}的形式返回给客户端
+#[derive(Debug)]
+pub(crate) struct HttpError {
+    code: StatusCode,
+    text: String,
+}
+
+impl HttpError {
+    pub fn new(code: StatusCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        (self.code, Json(json!({ "message": self.text }))).into_response()
+    }
+}
+
+// 把内部错误转换成对应的HTTP状态码，错误码去掉E_前缀
+impl From<NsqError> for HttpError {
+    fn from(e: NsqError) -> Self {
+        match e {
+            NsqError::ClientErr(code, _) | NsqError::FatalClientErr(code, _) => {
+                HttpError::new(code.http_status(), code.http_text())
+            }
+            NsqError::TopicNotFound => HttpError::new(StatusCode::NOT_FOUND, "TOPIC_NOT_FOUND"),
+            NsqError::ChannelNotFound => HttpError::new(StatusCode::NOT_FOUND, "CHANNEL_NOT_FOUND"),
+            NsqError::Exiting => HttpError::new(StatusCode::SERVICE_UNAVAILABLE, "EXITING"),
+            _ => HttpError::new(StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR"),
+        }
+    }
+}
+
+pub(crate) async fn serve_conn<I>(conn: I, router: Router, mut shutdown: Shutdown)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = TowerToHyperService::new(router);
+    let conn = http1::Builder::new().serve_connection(TokioIo::new(conn), service);
+    tokio::pin!(conn);
+
+    let res = select! {
+        res = conn.as_mut() => res,
+        _ = shutdown.recv() => {
+            // 处理完正在进行的请求之后再关闭连接
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    if let Err(e) = res {
+        debug!("HTTP: connection error - {e}");
+    }
+}
+
+pub(crate) fn get_topic_name_from_query(params: &HashMap<String, String>) -> HttpResult<&str> {
+    let topic_name = params
+        .get("topic")
+        .ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "MISSING_ARG_TOPIC"))?;
+    if !is_valid_topic_name(topic_name) {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_TOPIC"));
+    }
+    Ok(topic_name)
+}
+
+pub(crate) fn get_topic_channel_args(params: &HashMap<String, String>) -> HttpResult<(&str, &str)> {
+    let topic_name = get_topic_name_from_query(params)?;
+
+    let channel_name = params
+        .get("channel")
+        .ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "MISSING_ARG_CHANNEL"))?;
+    if !is_valid_channel_name(channel_name) {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_CHANNEL"));
+    }
+
+    Ok((topic_name, channel_name))
+}
+
+pub(crate) fn parse_bool(v: &str) -> Option<bool> {
+    match v {
+        "1" | "t" | "T" | "true" | "TRUE" | "True" => Some(true),
+        "0" | "f" | "F" | "false" | "FALSE" | "False" => Some(false),
+        _ => None,
+    }
+}
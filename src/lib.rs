@@ -1,7 +1,10 @@
 mod common;
 mod errors;
-mod nsqadmin;
-mod nsqd;
-mod nsqlookupd;
+mod http_api;
+pub mod nsqadmin;
+pub mod nsqd;
+pub mod nsqlookupd;
+mod shutdown;
 
-// pub use common::Result;
+pub use common::Result;
+pub use errors::NsqError;
@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+
+use reqwest::{Client, Method};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::task::JoinSet;
+use tracing::warn;
+
+use super::types::{NodeStats, Producer, TopicStats};
+
+// 访问nsqd和nsqlookupd的HTTP接口。请求会同时发给所有节点，
+// 部分节点失败时继续使用其他节点的结果，错误信息追加到errs中返回给调用方
+pub(super) struct ClusterInfo {
+    client: Client,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct TopicsResponse {
+    topics: Vec<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct NodesResponse {
+    producers: Vec<Producer>,
+}
+
+impl ClusterInfo {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    // 所有nsqlookupd上注册过的topic
+    pub async fn get_lookupd_topics(
+        &self,
+        lookupd: &[String],
+        errs: &mut Vec<String>,
+    ) -> Vec<String> {
+        let urls = lookupd
+            .iter()
+            .map(|a| format!("http://{a}/topics"))
+            .collect();
+        let mut topics: Vec<String> = self
+            .get_all::<TopicsResponse>(urls, errs)
+            .await
+            .into_iter()
+            .flat_map(|(_, r)| r.topics)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        topics.sort();
+        topics
+    }
+
+    // 通过nsqlookupd发现的nsqd节点，多个nsqlookupd返回的同一个节点只保留一个
+    pub async fn get_lookupd_producers(
+        &self,
+        lookupd: &[String],
+        errs: &mut Vec<String>,
+    ) -> Vec<Producer> {
+        let urls = lookupd
+            .iter()
+            .map(|a| format!("http://{a}/nodes"))
+            .collect();
+        let mut producers: Vec<Producer> = Vec::new();
+        for (_, resp) in self.get_all::<NodesResponse>(urls, errs).await {
+            for mut p in resp.producers {
+                p.http_address = format!("{}:{}", p.broadcast_address, p.http_port);
+                if !producers.iter().any(|e| e.http_address == p.http_address) {
+                    producers.push(p);
+                }
+            }
+        }
+        producers.sort_by(|a, b| a.http_address.cmp(&b.http_address));
+        producers
+    }
+
+    // 静态指定的nsqd节点，节点信息来自/info
+    pub async fn get_nsqd_producers(
+        &self,
+        nsqd: &[String],
+        errs: &mut Vec<String>,
+    ) -> Vec<Producer> {
+        let urls = nsqd.iter().map(|a| format!("http://{a}/info")).collect();
+        self.get_all::<Producer>(urls, errs)
+            .await
+            .into_iter()
+            .map(|(i, mut p)| {
+                p.http_address = nsqd[i].clone();
+                p
+            })
+            .collect()
+    }
+
+    // 每个节点上的topic统计，没有聚合
+    pub async fn get_nsqd_stats(
+        &self,
+        producers: &[Producer],
+        topic: Option<&str>,
+        errs: &mut Vec<String>,
+    ) -> Vec<TopicStats> {
+        let urls = producers
+            .iter()
+            .map(|p| {
+                let mut url = format!("http://{}/stats?format=json", p.http_address);
+                // 合法的topic名称中只有临时topic的#需要转义
+                if let Some(topic) = topic {
+                    url.push_str("&topic=");
+                    url.push_str(&topic.replace('#', "%23"));
+                }
+                url
+            })
+            .collect();
+
+        let mut stats = Vec::new();
+        for (i, resp) in self.get_all::<NodeStats>(urls, errs).await {
+            let node = &producers[i].http_address;
+            for mut t in resp.topics {
+                t.node = node.clone();
+                for c in &mut t.channels {
+                    c.node = node.clone();
+                    c.topic_name = t.topic_name.clone();
+                    for client in &mut c.clients {
+                        client.node = node.clone();
+                    }
+                }
+                stats.push(t);
+            }
+        }
+        stats
+    }
+
+    // 向所有节点发送POST请求，例如/topic/pause?topic=xxx
+    pub async fn post_all(
+        &self,
+        addrs: &[String],
+        path: &str,
+        params: &[(&str, &str)],
+        errs: &mut Vec<String>,
+    ) {
+        let mut set = JoinSet::new();
+        for addr in addrs {
+            let url = format!("http://{addr}{path}");
+            let req = self.client.request(Method::POST, &url).query(params);
+            set.spawn(async move {
+                match req.send().await.and_then(|r| r.error_for_status()) {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(format!("POST {url} - {e}")),
+                }
+            });
+        }
+
+        while let Some(res) = set.join_next().await {
+            match res {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    warn!("{e}");
+                    errs.push(e);
+                }
+                Err(e) => errs.push(e.to_string()),
+            }
+        }
+    }
+
+    // 并发请求所有的url，返回成功的结果和对应的下标，按下标排序
+    async fn get_all<T>(&self, urls: Vec<String>, errs: &mut Vec<String>) -> Vec<(usize, T)>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let mut set = JoinSet::new();
+        for (i, url) in urls.into_iter().enumerate() {
+            let client = self.client.clone();
+            set.spawn(async move {
+                let res = async {
+                    client
+                        .get(&url)
+                        .header("Accept", "application/json")
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json::<T>()
+                        .await
+                }
+                .await;
+                (i, res.map_err(|e| format!("GET {url} - {e}")))
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(res) = set.join_next().await {
+            match res {
+                Ok((i, Ok(v))) => results.push((i, v)),
+                Ok((_, Err(e))) => {
+                    warn!("{e}");
+                    errs.push(e);
+                }
+                Err(e) => errs.push(e.to_string()),
+            }
+        }
+        results.sort_by_key(|(i, _)| *i);
+        results
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use clap::Parser;
+
+use super::options::Options;
+use crate::common::{normalize_args, parse_duration};
+
+// 命令行参数和golang版本nsqadmin的flag保持一致，每个参数也可以通过NSQADMIN_*环境变量指定
+#[derive(Parser, Default)]
+#[command(
+    name = "nsqadmin",
+    version,
+    about = "A web UI to view aggregated cluster stats"
+)]
+pub struct Config {
+    /// <addr>:<port> to listen on for HTTP clients
+    #[arg(long, env = "NSQADMIN_HTTP_ADDRESS")]
+    http_address: Option<String>,
+    /// lookupd HTTP address (may be given multiple times)
+    #[arg(
+        long = "lookupd-http-address",
+        env = "NSQADMIN_LOOKUPD_HTTP_ADDRESS",
+        value_delimiter = ','
+    )]
+    nsqlookupd_http_addresses: Option<Vec<String>>,
+    /// nsqd HTTP address (may be given multiple times)
+    #[arg(
+        long = "nsqd-http-address",
+        env = "NSQADMIN_NSQD_HTTP_ADDRESS",
+        value_delimiter = ','
+    )]
+    nsqd_http_addresses: Option<Vec<String>>,
+    /// timeout for HTTP connect
+    #[arg(long, env = "NSQADMIN_HTTP_CLIENT_CONNECT_TIMEOUT", value_parser = parse_duration)]
+    http_client_connect_timeout: Option<Duration>,
+    /// timeout for HTTP request
+    #[arg(long, env = "NSQADMIN_HTTP_CLIENT_REQUEST_TIMEOUT", value_parser = parse_duration)]
+    http_client_request_timeout: Option<Duration>,
+}
+
+impl Config {
+    pub fn from_args() -> Self {
+        Self::parse_from(normalize_args::<Self>(std::env::args_os()))
+    }
+
+    // 地址列表的校验在NSQAdmin::new中进行
+    pub fn into_options(self) -> Options {
+        let mut opts = Options::new();
+        if let Some(addr) = self.http_address {
+            opts.http_addr = addr;
+        }
+        // 静态指定了nsqd节点时不再使用默认的nsqlookupd地址
+        if let Some(addrs) = self.nsqd_http_addresses {
+            opts.nsqd_http_addrs = addrs;
+            opts.nsqlookupd_http_addrs.clear();
+        }
+        if let Some(addrs) = self.nsqlookupd_http_addresses {
+            opts.nsqlookupd_http_addrs = addrs;
+        }
+        if let Some(d) = self.http_client_connect_timeout {
+            opts.http_client_connect_timeout = d;
+        }
+        if let Some(d) = self.http_client_request_timeout {
+            opts.http_client_request_timeout = d;
+        }
+        opts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Options {
+        let args = normalize_args::<Config>(args.iter().map(OsString::from));
+        Config::try_parse_from(args).unwrap().into_options()
+    }
+
+    #[test]
+    fn into_options() {
+        let opts = parse(&["nsqadmin"]);
+        assert_eq!(opts.http_addr, "0.0.0.0:4171");
+        assert_eq!(opts.nsqlookupd_http_addrs, ["127.0.0.1:4161"]);
+        assert!(opts.nsqd_http_addrs.is_empty());
+
+        let opts = parse(&[
+            "nsqadmin",
+            "-http-address=127.0.0.1:14171",
+            "--nsqd-http-address",
+            "127.0.0.1:4151",
+            "-nsqd-http-address",
+            "127.0.0.1:5151",
+            "--http-client-request-timeout=1m30s",
+        ]);
+        assert_eq!(opts.http_addr, "127.0.0.1:14171");
+        assert_eq!(opts.nsqd_http_addrs, ["127.0.0.1:4151", "127.0.0.1:5151"]);
+        assert!(opts.nsqlookupd_http_addrs.is_empty());
+        assert_eq!(opts.http_client_request_timeout, Duration::from_secs(90));
+
+        // 同时指定时交给NSQAdmin::new报错
+        let opts = parse(&[
+            "nsqadmin",
+            "--nsqd-http-address=127.0.0.1:4151",
+            "--lookupd-http-address=127.0.0.1:4161,127.0.0.1:5161",
+        ]);
+        assert_eq!(opts.nsqd_http_addrs.len(), 1);
+        assert_eq!(opts.nsqlookupd_http_addrs.len(), 2);
+    }
+}
@@ -0,0 +1,560 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::StatusCode,
+    response::Html,
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::select;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+use super::{
+    nsqadmin::NSQAdmin,
+    types::{ChannelStats, TopicStats},
+    ui,
+};
+use crate::{
+    common::{is_valid_channel_name, is_valid_topic_name},
+    http_api::{serve_conn, HttpError, HttpResult},
+    shutdown::Shutdown,
+};
+
+pub(super) async fn serve(nsqadmin: Arc<NSQAdmin>, mut shutdown: Shutdown) {
+    let addr = nsqadmin.http_addr();
+    info!("HTTP: listening on {addr}");
+
+    let router = router(nsqadmin.clone());
+    let tracker = TaskTracker::new();
+    loop {
+        let res = select! {
+            res = nsqadmin.http_accept() => res,
+            _ = shutdown.recv() => {
+                info!("HTTP: closing {addr}");
+                break;
+            }
+        };
+        let (conn, _) = match res {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("HTTP: accept error - {e}");
+                continue;
+            }
+        };
+
+        tracker.spawn(serve_conn(conn, router.clone(), shutdown.clone()));
+    }
+
+    tracker.close();
+    tracker.wait().await;
+}
+
+fn router(nsqadmin: Arc<NSQAdmin>) -> Router {
+    Router::new()
+        .route("/ping", get(ping))
+        // 页面
+        .route("/", get(index_page))
+        .route("/topics/:topic", get(topic_page))
+        .route("/topics/:topic/:channel", get(channel_page))
+        .route("/nodes", get(nodes_page))
+        .route("/nodes/:node", get(node_page))
+        // JSON API
+        .route(
+            "/api/topics",
+            get(topics_handler).post(create_topic_channel_handler),
+        )
+        .route(
+            "/api/topics/:topic",
+            get(topic_handler)
+                .post(topic_action_handler)
+                .delete(delete_topic_handler),
+        )
+        .route(
+            "/api/topics/:topic/:channel",
+            get(channel_handler)
+                .post(channel_action_handler)
+                .delete(delete_channel_handler),
+        )
+        .route("/api/nodes", get(nodes_handler))
+        .route("/api/nodes/:node", get(node_handler))
+        .fallback(|| async { HttpError::new(StatusCode::NOT_FOUND, "NOT_FOUND") })
+        .with_state(nsqadmin)
+}
+
+async fn ping() -> &'static str {
+    "OK"
+}
+
+// 部分节点请求失败时仍然返回其他节点的数据，同时在message中带上错误信息；
+// 没有拿到任何数据时返回502
+fn api_response(v: Value, found: bool, errs: Vec<String>) -> HttpResult<Json<Value>> {
+    if errs.is_empty() {
+        return Ok(Json(v));
+    }
+    if !found {
+        return Err(upstream_error(errs));
+    }
+
+    let mut v = v;
+    if let Value::Object(m) = &mut v {
+        m.insert("message".to_owned(), Value::String(errs.join("; ")));
+    }
+    Ok(Json(v))
+}
+
+fn upstream_error(errs: Vec<String>) -> HttpError {
+    HttpError::new(
+        StatusCode::BAD_GATEWAY,
+        format!("UPSTREAM_ERROR: {}", errs.join("; ")),
+    )
+}
+
+fn to_value<T: serde::Serialize>(v: &T) -> Value {
+    serde_json::to_value(v).unwrap_or_default()
+}
+
+async fn topics_handler(State(nsqadmin): State<Arc<NSQAdmin>>) -> HttpResult<Json<Value>> {
+    let mut errs = Vec::new();
+    let topics = nsqadmin.topic_stats(None, &mut errs).await;
+    let found = !topics.is_empty();
+    api_response(json!({ "topics": topics }), found, errs)
+}
+
+async fn get_topic(nsqadmin: &NSQAdmin, topic: &str, errs: &mut Vec<String>) -> Option<TopicStats> {
+    nsqadmin
+        .topic_stats(Some(topic), errs)
+        .await
+        .into_iter()
+        .find(|t| t.topic_name == topic)
+}
+
+async fn topic_handler(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    Path(topic): Path<String>,
+) -> HttpResult<Json<Value>> {
+    let mut errs = Vec::new();
+    match get_topic(&nsqadmin, &topic, &mut errs).await {
+        Some(t) => api_response(to_value(&t), true, errs),
+        None if errs.is_empty() => Err(HttpError::new(StatusCode::NOT_FOUND, "TOPIC_NOT_FOUND")),
+        None => Err(upstream_error(errs)),
+    }
+}
+
+async fn get_channel(
+    nsqadmin: &NSQAdmin,
+    topic: &str,
+    channel: &str,
+    errs: &mut Vec<String>,
+) -> Option<ChannelStats> {
+    get_topic(nsqadmin, topic, errs)
+        .await?
+        .channels
+        .into_iter()
+        .find(|c| c.channel_name == channel)
+}
+
+async fn channel_handler(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    Path((topic, channel)): Path<(String, String)>,
+) -> HttpResult<Json<Value>> {
+    let mut errs = Vec::new();
+    match get_channel(&nsqadmin, &topic, &channel, &mut errs).await {
+        Some(c) => api_response(to_value(&c), true, errs),
+        None if errs.is_empty() => Err(HttpError::new(StatusCode::NOT_FOUND, "CHANNEL_NOT_FOUND")),
+        None => Err(upstream_error(errs)),
+    }
+}
+
+async fn nodes_handler(State(nsqadmin): State<Arc<NSQAdmin>>) -> HttpResult<Json<Value>> {
+    let mut errs = Vec::new();
+    let nodes = nsqadmin.producers(&mut errs).await;
+    let found = !nodes.is_empty();
+    api_response(json!({ "nodes": nodes }), found, errs)
+}
+
+// 单个节点上的topic统计，不做聚合
+async fn get_node(
+    nsqadmin: &NSQAdmin,
+    node: &str,
+    errs: &mut Vec<String>,
+) -> Option<Vec<TopicStats>> {
+    let producers = nsqadmin.producers(errs).await;
+    let producer = producers.into_iter().find(|p| p.http_address == node)?;
+    let mut topics = nsqadmin.ci().get_nsqd_stats(&[producer], None, errs).await;
+    topics.sort_by(|a, b| a.topic_name.cmp(&b.topic_name));
+    Some(topics)
+}
+
+async fn node_handler(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    Path(node): Path<String>,
+) -> HttpResult<Json<Value>> {
+    let mut errs = Vec::new();
+    match get_node(&nsqadmin, &node, &mut errs).await {
+        Some(topics) => api_response(json!({ "node": node, "topics": topics }), true, errs),
+        None if errs.is_empty() => Err(HttpError::new(StatusCode::NOT_FOUND, "NODE_NOT_FOUND")),
+        None => Err(upstream_error(errs)),
+    }
+}
+
+fn parse_body<'a, T: Deserialize<'a>>(body: &'a [u8]) -> HttpResult<T> {
+    serde_json::from_slice(body)
+        .map_err(|_| HttpError::new(StatusCode::BAD_REQUEST, "INVALID_BODY"))
+}
+
+#[derive(Deserialize)]
+struct CreateTopicChannel {
+    topic: String,
+    #[serde(default)]
+    channel: String,
+}
+
+async fn create_topic_channel_handler(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    body: Bytes,
+) -> HttpResult<()> {
+    let req: CreateTopicChannel = parse_body(&body)?;
+    if !is_valid_topic_name(&req.topic) {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_TOPIC"));
+    }
+    if !req.channel.is_empty() && !is_valid_channel_name(&req.channel) {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_CHANNEL"));
+    }
+
+    // 有nsqlookupd时只在nsqlookupd上创建，nsqd会在消费者连接时创建
+    let opts = nsqadmin.get_opts();
+    let addrs = if opts.nsqlookupd_http_addrs.is_empty() {
+        &opts.nsqd_http_addrs
+    } else {
+        &opts.nsqlookupd_http_addrs
+    };
+
+    info!(
+        "ADMIN: create topic({}) channel({})",
+        req.topic, req.channel
+    );
+    let mut errs = Vec::new();
+    let ci = nsqadmin.ci();
+    ci.post_all(addrs, "/topic/create", &[("topic", &req.topic)], &mut errs)
+        .await;
+    if !req.channel.is_empty() {
+        let params = [("topic", req.topic.as_str()), ("channel", &req.channel)];
+        ci.post_all(addrs, "/channel/create", &params, &mut errs)
+            .await;
+    }
+
+    action_result(errs)
+}
+
+fn action_result(errs: Vec<String>) -> HttpResult<()> {
+    if errs.is_empty() {
+        Ok(())
+    } else {
+        Err(upstream_error(errs))
+    }
+}
+
+#[derive(Deserialize)]
+struct Action {
+    action: String,
+}
+
+fn parse_action(body: &[u8]) -> HttpResult<String> {
+    let req: Action = parse_body(body)?;
+    match req.action.as_str() {
+        "pause" | "unpause" | "empty" => Ok(req.action),
+        _ => Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_ACTION")),
+    }
+}
+
+async fn topic_action_handler(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    Path(topic): Path<String>,
+    body: Bytes,
+) -> HttpResult<()> {
+    let action = parse_action(&body)?;
+    if !is_valid_topic_name(&topic) {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_TOPIC"));
+    }
+
+    info!("ADMIN: {action} topic({topic})");
+    let mut errs = Vec::new();
+    let nodes = nsqadmin.topic_nodes(&topic, &mut errs).await;
+    nsqadmin
+        .ci()
+        .post_all(
+            &nodes,
+            &format!("/topic/{action}"),
+            &[("topic", &topic)],
+            &mut errs,
+        )
+        .await;
+    action_result(errs)
+}
+
+async fn channel_action_handler(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    Path((topic, channel)): Path<(String, String)>,
+    body: Bytes,
+) -> HttpResult<()> {
+    let action = parse_action(&body)?;
+    if !is_valid_topic_name(&topic) {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_TOPIC"));
+    }
+    if !is_valid_channel_name(&channel) {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_CHANNEL"));
+    }
+
+    info!("ADMIN: {action} channel({channel}) in topic({topic})");
+    let mut errs = Vec::new();
+    let nodes = nsqadmin.topic_nodes(&topic, &mut errs).await;
+    nsqadmin
+        .ci()
+        .post_all(
+            &nodes,
+            &format!("/channel/{action}"),
+            &[("topic", &topic), ("channel", &channel)],
+            &mut errs,
+        )
+        .await;
+    action_result(errs)
+}
+
+async fn delete_topic_handler(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    Path(topic): Path<String>,
+) -> HttpResult<()> {
+    if !is_valid_topic_name(&topic) {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_TOPIC"));
+    }
+
+    info!("ADMIN: delete topic({topic})");
+    let mut errs = Vec::new();
+    let ci = nsqadmin.ci();
+    let params = [("topic", topic.as_str())];
+
+    // 先从nsqlookupd中删除，避免消费者在nsqd删除之后又重新创建
+    ci.post_all(
+        &nsqadmin.get_opts().nsqlookupd_http_addrs,
+        "/topic/delete",
+        &params,
+        &mut errs,
+    )
+    .await;
+
+    let nodes = nsqadmin.topic_nodes(&topic, &mut errs).await;
+    ci.post_all(&nodes, "/topic/delete", &params, &mut errs)
+        .await;
+    action_result(errs)
+}
+
+async fn delete_channel_handler(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    Path((topic, channel)): Path<(String, String)>,
+) -> HttpResult<()> {
+    if !is_valid_topic_name(&topic) {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_TOPIC"));
+    }
+    if !is_valid_channel_name(&channel) {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_CHANNEL"));
+    }
+
+    info!("ADMIN: delete channel({channel}) in topic({topic})");
+    let mut errs = Vec::new();
+    let ci = nsqadmin.ci();
+    let params = [("topic", topic.as_str()), ("channel", &channel)];
+
+    ci.post_all(
+        &nsqadmin.get_opts().nsqlookupd_http_addrs,
+        "/channel/delete",
+        &params,
+        &mut errs,
+    )
+    .await;
+
+    let nodes = nsqadmin.topic_nodes(&topic, &mut errs).await;
+    ci.post_all(&nodes, "/channel/delete", &params, &mut errs)
+        .await;
+    action_result(errs)
+}
+
+async fn index_page(State(nsqadmin): State<Arc<NSQAdmin>>) -> Html<String> {
+    let mut errs = Vec::new();
+    let topics = nsqadmin.topic_stats(None, &mut errs).await;
+    ui::index(&topics, &errs)
+}
+
+async fn topic_page(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    Path(topic): Path<String>,
+) -> Html<String> {
+    let mut errs = Vec::new();
+    let t = get_topic(&nsqadmin, &topic, &mut errs).await;
+    ui::topic(&topic, t.as_ref(), &errs)
+}
+
+async fn channel_page(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    Path((topic, channel)): Path<(String, String)>,
+) -> Html<String> {
+    let mut errs = Vec::new();
+    let c = get_channel(&nsqadmin, &topic, &channel, &mut errs).await;
+    ui::channel(&topic, &channel, c.as_ref(), &errs)
+}
+
+async fn nodes_page(State(nsqadmin): State<Arc<NSQAdmin>>) -> Html<String> {
+    let mut errs = Vec::new();
+    let nodes = nsqadmin.producers(&mut errs).await;
+    ui::nodes(&nodes, &errs)
+}
+
+async fn node_page(
+    State(nsqadmin): State<Arc<NSQAdmin>>,
+    Path(node): Path<String>,
+) -> Html<String> {
+    let mut errs = Vec::new();
+    let topics = get_node(&nsqadmin, &node, &mut errs).await;
+    ui::node(&node, topics.as_deref(), &errs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use reqwest::StatusCode;
+    use tokio::{net::TcpListener, task::JoinHandle};
+
+    use super::*;
+    use crate::{nsqadmin::Options, nsqd::test_util::TestNsqd};
+
+    struct TestAdmin {
+        addr: SocketAddr,
+        nsqadmin: Arc<NSQAdmin>,
+        handle: JoinHandle<crate::Result<()>>,
+    }
+
+    impl TestAdmin {
+        async fn start(nsqd: Vec<String>) -> Self {
+            let mut opts = Options::new();
+            opts.http_addr = "127.0.0.1:0".to_owned();
+            opts.nsqlookupd_http_addrs.clear();
+            opts.nsqd_http_addrs = nsqd;
+            let nsqadmin = Arc::new(NSQAdmin::new(opts).await.unwrap().0);
+            let handle = tokio::spawn(nsqadmin.clone().start());
+            Self {
+                addr: nsqadmin.http_addr(),
+                nsqadmin,
+                handle,
+            }
+        }
+
+        async fn get(&self, path: &str) -> (StatusCode, Value) {
+            let resp = reqwest::get(format!("http://{}{path}", self.addr))
+                .await
+                .unwrap();
+            (resp.status(), resp.json().await.unwrap())
+        }
+
+        async fn post(&self, path: &str, body: Value) -> StatusCode {
+            reqwest::Client::new()
+                .post(format!("http://{}{path}", self.addr))
+                .json(&body)
+                .send()
+                .await
+                .unwrap()
+                .status()
+        }
+
+        async fn stop(self) {
+            self.nsqadmin.exit();
+            self.handle.await.unwrap().unwrap();
+        }
+    }
+
+    // 一个没有监听的地址，请求会被拒绝
+    async fn dead_addr() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn topics_across_nodes() {
+        let (n1, _d1) = TestNsqd::start_default().await;
+        let (n2, _d2) = TestNsqd::start_default().await;
+        let nodes = vec![
+            n1.nsqd.http_addr().to_string(),
+            n2.nsqd.http_addr().to_string(),
+        ];
+        let dead = dead_addr().await;
+
+        let admin = TestAdmin::start(nodes.clone()).await;
+        let status = admin
+            .post("/api/topics", json!({ "topic": "t", "channel": "c" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, t) = admin.get("/api/topics/t").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(t["nodes"].as_array().unwrap().len(), 2);
+        assert_eq!(t["channels"][0]["channel_name"], "c");
+        assert_eq!(t["channels"][0]["nodes"].as_array().unwrap().len(), 2);
+        assert!(t.get("message").is_none());
+
+        // 操作会发送到拥有这个topic的所有节点
+        let status = admin
+            .post("/api/topics/t", json!({ "action": "pause" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let status = admin
+            .post("/api/topics/t/c", json!({ "action": "pause" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, t) = admin.get("/api/topics/t").await;
+        assert_eq!(t["paused"], true);
+        for n in t["nodes"].as_array().unwrap() {
+            assert_eq!(n["paused"], true, "{n}");
+        }
+        for n in t["channels"][0]["nodes"].as_array().unwrap() {
+            assert_eq!(n["paused"], true, "{n}");
+        }
+
+        let status = admin
+            .post("/api/topics/t", json!({ "action": "bad" }))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = admin.get("/api/topics/unknown").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        admin.stop().await;
+
+        // 部分节点失败时返回其他节点的数据，错误放在message中
+        let admin = TestAdmin::start(vec![nodes[0].clone(), dead.clone()]).await;
+        let (status, v) = admin.get("/api/topics").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(v["topics"][0]["topic_name"], "t");
+        assert!(v["message"].as_str().unwrap().contains(&dead), "{v}");
+        let (status, v) = admin.get("/api/nodes").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(v["nodes"].as_array().unwrap().len(), 1);
+        assert!(v["message"].as_str().unwrap().contains(&dead), "{v}");
+        admin.stop().await;
+
+        // 所有节点都失败时返回502
+        let admin = TestAdmin::start(vec![dead.clone()]).await;
+        let (status, v) = admin.get("/api/topics").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let message = v["message"].as_str().unwrap();
+        assert!(message.starts_with("UPSTREAM_ERROR: "), "{message}");
+        assert!(message.contains(&dead), "{message}");
+        let status = admin.post("/api/topics", json!({ "topic": "t" })).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        admin.stop().await;
+
+        n1.stop().await;
+        n2.stop().await;
+    }
+}
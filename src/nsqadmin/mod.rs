@@ -0,0 +1,12 @@
+mod cluster_info;
+mod config;
+mod http;
+#[allow(clippy::module_inception)]
+mod nsqadmin;
+mod options;
+mod types;
+mod ui;
+
+pub use config::Config;
+pub use nsqadmin::NSQAdmin;
+pub use options::Options;
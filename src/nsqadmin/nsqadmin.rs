@@ -0,0 +1,274 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::warn;
+
+use super::{
+    cluster_info::ClusterInfo,
+    http,
+    options::Options,
+    types::{aggregate_topics, Producer, TopicStats},
+};
+use crate::{common::Result, errors::NsqError};
+
+// 两次采样间隔太短时沿用上一次计算的速率
+const RATE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+struct RateSample {
+    at: Instant,
+    count: u64,
+    rate: f64,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct NSQAdmin {
+    http_listener: TcpListener,
+
+    exit_token: CancellationToken,
+
+    ci: ClusterInfo,
+    // 根据message_count的变化计算每秒的消息数，key为topic或者topic/channel
+    rates: Mutex<HashMap<String, RateSample>>,
+
+    opts: Options,
+}
+
+impl NSQAdmin {
+    pub async fn new(opts: Options) -> Result<(Self, CancellationToken)> {
+        if opts.nsqd_http_addrs.is_empty() && opts.nsqlookupd_http_addrs.is_empty() {
+            return Err(NsqError::InvalidOptions(
+                "--nsqd-http-address or --lookupd-http-address required".to_owned(),
+            ));
+        }
+        if !opts.nsqd_http_addrs.is_empty() && !opts.nsqlookupd_http_addrs.is_empty() {
+            return Err(NsqError::InvalidOptions(
+                "use --nsqd-http-address or --lookupd-http-address not both".to_owned(),
+            ));
+        }
+
+        let client = reqwest::Client::builder()
+            .connect_timeout(opts.http_client_connect_timeout)
+            .timeout(opts.http_client_request_timeout)
+            .build()
+            .map_err(|e| NsqError::InvalidOptions(format!("failed to build http client - {e}")))?;
+
+        let token = CancellationToken::new();
+        let http_listener = TcpListener::bind(&opts.http_addr).await?;
+
+        let nsqadmin = NSQAdmin {
+            http_listener,
+            exit_token: token.clone(),
+            ci: ClusterInfo::new(client),
+            rates: Mutex::new(HashMap::new()),
+            opts,
+        };
+
+        Ok((nsqadmin, token))
+    }
+
+    pub async fn start(self: Arc<Self>) -> Result<()> {
+        let tracker = TaskTracker::new();
+        let (tx, _) = broadcast::channel(1);
+
+        tracker.spawn(http::serve(self.clone(), (&tx).into()));
+
+        // 等待退出信号
+        self.exit_token.cancelled().await;
+        warn!("NSQAdmin exiting");
+
+        // 通知所有组件退出
+        let _ = tx.send(());
+        tracker.close();
+
+        // 等待所有组件退出
+        tracker.wait().await;
+        Ok(())
+    }
+
+    pub fn exit(&self) {
+        self.exit_token.cancel();
+    }
+
+    pub fn get_opts(&self) -> &Options {
+        &self.opts
+    }
+
+    pub(super) fn ci(&self) -> &ClusterInfo {
+        &self.ci
+    }
+
+    pub fn http_addr(&self) -> SocketAddr {
+        self.http_listener.local_addr().unwrap()
+    }
+
+    pub(super) async fn http_accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.http_listener.accept().await
+    }
+
+    // 集群中所有的nsqd节点
+    pub(super) async fn producers(&self, errs: &mut Vec<String>) -> Vec<Producer> {
+        if self.opts.nsqlookupd_http_addrs.is_empty() {
+            self.ci
+                .get_nsqd_producers(&self.opts.nsqd_http_addrs, errs)
+                .await
+        } else {
+            self.ci
+                .get_lookupd_producers(&self.opts.nsqlookupd_http_addrs, errs)
+                .await
+        }
+    }
+
+    // 聚合之后的topic统计，topic为None时返回所有的topic
+    pub(super) async fn topic_stats(
+        &self,
+        topic: Option<&str>,
+        errs: &mut Vec<String>,
+    ) -> Vec<TopicStats> {
+        let producers = self.producers(errs).await;
+        let stats = self.ci.get_nsqd_stats(&producers, topic, errs).await;
+        let mut topics = aggregate_topics(stats);
+
+        // nsqlookupd上注册过但是当前没有节点的topic也需要展示出来
+        if topic.is_none() && !self.opts.nsqlookupd_http_addrs.is_empty() {
+            for name in self
+                .ci
+                .get_lookupd_topics(&self.opts.nsqlookupd_http_addrs, errs)
+                .await
+            {
+                if !topics.iter().any(|t| t.topic_name == name) {
+                    topics.push(TopicStats {
+                        topic_name: name,
+                        ..Default::default()
+                    });
+                }
+            }
+            topics.sort_by(|a, b| a.topic_name.cmp(&b.topic_name));
+        }
+
+        for t in &mut topics {
+            t.message_rate = self.rate(t.topic_name.clone(), t.message_count);
+            for c in &mut t.channels {
+                c.message_rate = self.rate(
+                    format!("{}/{}", t.topic_name, c.channel_name),
+                    c.message_count,
+                );
+            }
+        }
+        topics
+    }
+
+    // 拥有这个topic的nsqd节点的http地址
+    pub(super) async fn topic_nodes(&self, topic: &str, errs: &mut Vec<String>) -> Vec<String> {
+        let producers = self.producers(errs).await;
+        let mut nodes: Vec<_> = self
+            .ci
+            .get_nsqd_stats(&producers, Some(topic), errs)
+            .await
+            .into_iter()
+            .filter(|t| t.topic_name == topic)
+            .map(|t| t.node)
+            .collect();
+        nodes.dedup();
+        nodes
+    }
+
+    fn rate(&self, key: String, count: u64) -> f64 {
+        self.rate_at(key, count, Instant::now())
+    }
+
+    fn rate_at(&self, key: String, count: u64, now: Instant) -> f64 {
+        let mut rates = self.rates.lock().unwrap();
+        match rates.entry(key) {
+            Entry::Vacant(e) => {
+                e.insert(RateSample {
+                    at: now,
+                    count,
+                    rate: 0.0,
+                });
+                0.0
+            }
+            Entry::Occupied(mut e) => {
+                let sample = e.get_mut();
+                let elapsed = now - sample.at;
+                if elapsed >= RATE_SAMPLE_INTERVAL {
+                    // 节点重启或者请求失败时计数可能变小
+                    sample.rate = count.saturating_sub(sample.count) as f64 / elapsed.as_secs_f64();
+                    sample.at = now;
+                    sample.count = count;
+                }
+                sample.rate
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn nsqadmin() -> NSQAdmin {
+        let mut opts = Options::new();
+        opts.http_addr = "127.0.0.1:0".to_owned();
+        opts.nsqlookupd_http_addrs.clear();
+        opts.nsqd_http_addrs = vec!["127.0.0.1:4151".to_owned()];
+        NSQAdmin::new(opts).await.unwrap().0
+    }
+
+    #[tokio::test]
+    async fn address_options() {
+        let mut opts = Options::new();
+        opts.http_addr = "127.0.0.1:0".to_owned();
+        opts.nsqd_http_addrs = vec!["127.0.0.1:4151".to_owned()];
+        let err = NSQAdmin::new(opts).await.err().unwrap();
+        assert!(err.to_string().contains("not both"), "{err}");
+
+        let mut opts = Options::new();
+        opts.http_addr = "127.0.0.1:0".to_owned();
+        opts.nsqlookupd_http_addrs.clear();
+        let err = NSQAdmin::new(opts).await.err().unwrap();
+        assert!(err.to_string().contains("required"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn rate() {
+        let nsqadmin = nsqadmin().await;
+        let start = Instant::now();
+        let key = || "t/c".to_owned();
+
+        // 第一次采样没有速率
+        assert_eq!(nsqadmin.rate_at(key(), 100, start), 0.0);
+        // 间隔不足1秒时不重新计算
+        assert_eq!(
+            nsqadmin.rate_at(key(), 150, start + Duration::from_millis(500)),
+            0.0
+        );
+        assert_eq!(
+            nsqadmin.rate_at(key(), 300, start + Duration::from_secs(2)),
+            100.0
+        );
+        assert_eq!(
+            nsqadmin.rate_at(key(), 400, start + Duration::from_millis(2500)),
+            100.0
+        );
+        // 节点重启之后计数变小，速率为0
+        assert_eq!(
+            nsqadmin.rate_at(key(), 10, start + Duration::from_secs(4)),
+            0.0
+        );
+        // 不同的key互不影响
+        assert_eq!(
+            nsqadmin.rate_at("t".to_owned(), 10, start + Duration::from_secs(4)),
+            0.0
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+pub struct Options {
+    pub http_addr: String,
+
+    // 通过nsqlookupd发现nsqd节点，和nsqd_http_addrs只能二选一
+    pub nsqlookupd_http_addrs: Vec<String>,
+    // 静态指定的nsqd节点
+    pub nsqd_http_addrs: Vec<String>,
+
+    pub http_client_connect_timeout: Duration,
+    pub http_client_request_timeout: Duration,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self {
+            http_addr: "0.0.0.0:4171".to_owned(),
+
+            nsqlookupd_http_addrs: vec!["127.0.0.1:4161".to_owned()],
+            nsqd_http_addrs: Vec::new(),
+
+            http_client_connect_timeout: Duration::from_secs(2),
+            http_client_request_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
+}
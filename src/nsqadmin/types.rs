@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+// nsqd节点的信息，来自nsqlookupd的/nodes或者nsqd的/info
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(super) struct Producer {
+    pub remote_address: String,
+    pub hostname: String,
+    pub broadcast_address: String,
+    pub tcp_port: u16,
+    pub http_port: u16,
+    pub version: String,
+    pub topics: Vec<String>,
+    pub tombstones: Vec<bool>,
+    // 访问这个节点HTTP接口的地址，同时作为节点的唯一标识
+    pub http_address: String,
+}
+
+// nsqd的/stats?format=json
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub(super) struct NodeStats {
+    pub topics: Vec<TopicStats>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(super) struct TopicStats {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub node: String,
+    pub topic_name: String,
+    pub depth: i64,
+    pub backend_depth: i64,
+    pub message_count: u64,
+    pub paused: bool,
+    pub channels: Vec<ChannelStats>,
+
+    // 以下字段只在聚合之后才有
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<TopicStats>,
+    pub message_rate: f64,
+}
+
+impl TopicStats {
+    // 合并另一个节点上同名topic的统计
+    fn add(&mut self, other: TopicStats) {
+        self.depth += other.depth;
+        self.backend_depth += other.backend_depth;
+        self.message_count += other.message_count;
+        self.paused |= other.paused;
+
+        let mut node = other;
+        for c in std::mem::take(&mut node.channels) {
+            match self
+                .channels
+                .iter_mut()
+                .find(|ch| ch.channel_name == c.channel_name)
+            {
+                Some(ch) => ch.add(c),
+                None => self.channels.push(ChannelStats::aggregated(c)),
+            }
+        }
+        self.nodes.push(node);
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(super) struct ChannelStats {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub node: String,
+    pub topic_name: String,
+    pub channel_name: String,
+    pub depth: i64,
+    pub backend_depth: i64,
+    pub in_flight_count: usize,
+    pub deferred_count: usize,
+    pub message_count: u64,
+    pub requeue_count: u64,
+    pub timeout_count: u64,
+    pub client_count: usize,
+    pub clients: Vec<ClientStats>,
+    pub paused: bool,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<ChannelStats>,
+    pub message_rate: f64,
+}
+
+impl ChannelStats {
+    fn aggregated(c: ChannelStats) -> Self {
+        let mut aggregated = ChannelStats {
+            topic_name: c.topic_name.clone(),
+            channel_name: c.channel_name.clone(),
+            ..Default::default()
+        };
+        aggregated.add(c);
+        aggregated
+    }
+
+    // 合并另一个节点上同名channel的统计
+    fn add(&mut self, other: ChannelStats) {
+        self.depth += other.depth;
+        self.backend_depth += other.backend_depth;
+        self.in_flight_count += other.in_flight_count;
+        self.deferred_count += other.deferred_count;
+        self.message_count += other.message_count;
+        self.requeue_count += other.requeue_count;
+        self.timeout_count += other.timeout_count;
+        self.client_count += other.client_count;
+        self.paused |= other.paused;
+
+        let mut node = other;
+        self.clients.append(&mut node.clients);
+        self.nodes.push(node);
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(super) struct ClientStats {
+    pub node: String,
+    pub client_id: String,
+    pub hostname: String,
+    pub version: String,
+    pub remote_address: String,
+    pub state: i32,
+    pub ready_count: i64,
+    pub in_flight_count: i64,
+    pub message_count: u64,
+    pub finish_count: u64,
+    pub requeue_count: u64,
+    pub connect_ts: i64,
+    pub user_agent: String,
+    pub tls: bool,
+    pub deflate: bool,
+    pub snappy: bool,
+    pub authed: bool,
+    pub auth_identity: String,
+}
+
+// 把各个节点上同名的topic合并成一个，结果按名称排序
+pub(super) fn aggregate_topics(stats: Vec<TopicStats>) -> Vec<TopicStats> {
+    let mut topics: BTreeMap<String, TopicStats> = BTreeMap::new();
+    for t in stats {
+        topics
+            .entry(t.topic_name.clone())
+            .or_insert_with(|| TopicStats {
+                topic_name: t.topic_name.clone(),
+                ..Default::default()
+            })
+            .add(t);
+    }
+
+    let mut topics: Vec<_> = topics.into_values().collect();
+    for t in &mut topics {
+        t.channels
+            .sort_by(|a, b| a.channel_name.cmp(&b.channel_name));
+    }
+    topics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic(node: &str, name: &str, depth: i64, channels: &[(&str, i64, usize)]) -> TopicStats {
+        TopicStats {
+            node: node.to_owned(),
+            topic_name: name.to_owned(),
+            depth,
+            backend_depth: depth / 2,
+            message_count: 10,
+            channels: channels
+                .iter()
+                .map(|&(channel, depth, clients)| ChannelStats {
+                    node: node.to_owned(),
+                    topic_name: name.to_owned(),
+                    channel_name: channel.to_owned(),
+                    depth,
+                    message_count: 10,
+                    client_count: clients,
+                    clients: vec![ClientStats::default(); clients],
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn aggregate() {
+        let mut paused = topic("n2:4151", "a", 4, &[("y", 1, 1), ("x", 2, 0)]);
+        paused.paused = true;
+        let topics = aggregate_topics(vec![
+            topic("n1:4151", "b", 1, &[]),
+            topic("n1:4151", "a", 2, &[("y", 3, 2)]),
+            paused,
+        ]);
+
+        let names: Vec<_> = topics.iter().map(|t| t.topic_name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+
+        let a = &topics[0];
+        assert!(a.node.is_empty());
+        assert_eq!((a.depth, a.backend_depth, a.message_count), (6, 3, 20));
+        assert!(a.paused);
+        let nodes: Vec<_> = a.nodes.iter().map(|n| n.node.as_str()).collect();
+        assert_eq!(nodes, ["n1:4151", "n2:4151"]);
+        // 每个节点的统计里不再包含channel，channel在聚合后的topic下
+        assert!(a.nodes.iter().all(|n| n.channels.is_empty()));
+
+        let channels: Vec<_> = a.channels.iter().map(|c| c.channel_name.as_str()).collect();
+        assert_eq!(channels, ["x", "y"]);
+        let y = &a.channels[1];
+        assert_eq!(y.topic_name, "a");
+        assert_eq!((y.depth, y.message_count, y.client_count), (4, 20, 3));
+        assert_eq!(y.clients.len(), 3);
+        assert_eq!(y.nodes.len(), 2);
+        assert!(y.nodes.iter().all(|n| n.clients.is_empty()));
+
+        let b = &topics[1];
+        assert_eq!((b.depth, b.nodes.len()), (1, 1));
+        assert!(b.channels.is_empty());
+    }
+}
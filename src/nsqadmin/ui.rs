@@ -0,0 +1,375 @@
+use std::fmt::Write;
+
+use axum::response::Html;
+
+use super::types::{ChannelStats, ClientStats, Producer, TopicStats};
+
+// 页面上的操作都是通过fetch调用/api下的接口，成功之后跳转到next
+const SCRIPT: &str = r#"
+function act(method, url, body, confirmText, next) {
+  if (confirmText && !confirm(confirmText)) return;
+  fetch(url, {
+    method: method,
+    headers: {'Content-Type': 'application/json'},
+    body: body ? JSON.stringify(body) : undefined,
+  }).then(function (r) {
+    if (r.ok) { location.href = next || location.href; return; }
+    r.json().then(function (j) { alert(j.message); });
+  });
+}
+function createTopic(form) {
+  act('POST', '/api/topics', {topic: form.topic.value, channel: form.channel.value});
+  return false;
+}
+"#;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 0 2em 2em; }
+nav { padding: 1em 0; border-bottom: 1px solid #ccc; margin-bottom: 1em; }
+nav a { margin-right: 1em; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #ddd; padding: 4px 10px; text-align: right; }
+th:first-child, td:first-child { text-align: left; }
+tr.total { font-weight: bold; background: #f5f5f5; }
+.paused { color: #fff; background: #c60; padding: 0 4px; border-radius: 3px; font-size: 80%; }
+.error { color: #a00; background: #fee; padding: 0.5em 1em; margin-bottom: 1em; }
+button { margin-right: 0.5em; }
+"#;
+
+fn page(title: &str, body: &str, errs: &[String]) -> Html<String> {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>nsqadmin - {}</title>\
+         <style>{STYLE}</style><script>{SCRIPT}</script></head><body>\
+         <nav><b>nsqadmin</b> <a href=\"/\">Topics</a><a href=\"/nodes\">Nodes</a></nav>",
+        escape(title)
+    );
+    for e in errs {
+        let _ = write!(html, "<div class=\"error\">{}</div>", escape(e));
+    }
+    html.push_str(body);
+    html.push_str("</body></html>");
+    Html(html)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// 合法的topic和channel名称中只有#需要在路径中转义
+fn path_escape(s: &str) -> String {
+    s.replace('#', "%23")
+}
+
+// 节点地址不经过校验，放进href前路径转义后还要做HTML转义
+fn node_path(s: &str) -> String {
+    escape(&path_escape(s))
+}
+
+fn paused(p: bool) -> &'static str {
+    if p {
+        " <span class=\"paused\">paused</span>"
+    } else {
+        ""
+    }
+}
+
+pub(super) fn index(topics: &[TopicStats], errs: &[String]) -> Html<String> {
+    let mut b = String::from("<h2>Topics</h2>");
+    if topics.is_empty() {
+        b.push_str("<p>No topics</p>");
+    } else {
+        b.push_str(
+            "<table><tr><th>Topic</th><th>Depth</th><th>Memory + Disk</th><th>Messages</th>\
+             <th>Rate</th><th>Channels</th><th>Nodes</th></tr>",
+        );
+        for t in topics {
+            let _ = write!(
+                b,
+                "<tr><td><a href=\"/topics/{}\">{}</a>{}</td><td>{}</td><td>{} + {}</td>\
+                 <td>{}</td><td>{:.1}/s</td><td>{}</td><td>{}</td></tr>",
+                path_escape(&t.topic_name),
+                escape(&t.topic_name),
+                paused(t.paused),
+                t.depth,
+                t.depth - t.backend_depth,
+                t.backend_depth,
+                t.message_count,
+                t.message_rate,
+                t.channels.len(),
+                t.nodes.len()
+            );
+        }
+        b.push_str("</table>");
+    }
+
+    b.push_str(
+        "<h3>Create Topic/Channel</h3><form onsubmit=\"return createTopic(this)\">\
+         <input name=\"topic\" placeholder=\"topic\"> <input name=\"channel\" placeholder=\"channel (optional)\"> \
+         <button type=\"submit\">Create</button></form>",
+    );
+    page("Topics", &b, errs)
+}
+
+pub(super) fn topic(name: &str, t: Option<&TopicStats>, errs: &[String]) -> Html<String> {
+    let mut b = String::new();
+    let _ = write!(b, "<h2>Topic: {}</h2>", escape(name));
+    let Some(t) = t else {
+        b.push_str("<p>Topic not found</p>");
+        return page(name, &b, errs);
+    };
+
+    let api = format!("/api/topics/{}", path_escape(name));
+    action_buttons(&mut b, &api, t.paused, "/");
+
+    b.push_str(
+        "<h3>Nodes</h3><table><tr><th>Node</th><th>Depth</th><th>Memory + Disk</th>\
+         <th>Messages</th><th>Channels</th></tr>",
+    );
+    for n in &t.nodes {
+        let _ = write!(
+            b,
+            "<tr><td><a href=\"/nodes/{}\">{}</a>{}</td><td>{}</td><td>{} + {}</td>\
+             <td>{}</td><td>{}</td></tr>",
+            node_path(&n.node),
+            escape(&n.node),
+            paused(n.paused),
+            n.depth,
+            n.depth - n.backend_depth,
+            n.backend_depth,
+            n.message_count,
+            t.channels
+                .iter()
+                .filter(|c| c.nodes.iter().any(|cn| cn.node == n.node))
+                .count()
+        );
+    }
+    let _ = write!(
+        b,
+        "<tr class=\"total\"><td>Total</td><td>{}</td><td>{} + {}</td><td>{}</td><td>{}</td></tr></table>",
+        t.depth,
+        t.depth - t.backend_depth,
+        t.backend_depth,
+        t.message_count,
+        t.channels.len()
+    );
+
+    b.push_str("<h3>Channels</h3>");
+    if t.channels.is_empty() {
+        b.push_str("<p>No channels</p>");
+    } else {
+        channel_header(&mut b, "Channel");
+        for c in &t.channels {
+            let link = format!(
+                "<a href=\"/topics/{}/{}\">{}</a>",
+                path_escape(name),
+                path_escape(&c.channel_name),
+                escape(&c.channel_name)
+            );
+            channel_row(&mut b, &link, c, "");
+        }
+        b.push_str("</table>");
+    }
+
+    page(name, &b, errs)
+}
+
+pub(super) fn channel(
+    topic: &str,
+    name: &str,
+    c: Option<&ChannelStats>,
+    errs: &[String],
+) -> Html<String> {
+    let mut b = String::new();
+    let topic_link = format!("/topics/{}", path_escape(topic));
+    let _ = write!(
+        b,
+        "<h2><a href=\"{topic_link}\">{}</a> / {}</h2>",
+        escape(topic),
+        escape(name)
+    );
+    let Some(c) = c else {
+        b.push_str("<p>Channel not found</p>");
+        return page(name, &b, errs);
+    };
+
+    let api = format!("/api/topics/{}/{}", path_escape(topic), path_escape(name));
+    action_buttons(&mut b, &api, c.paused, &topic_link);
+
+    b.push_str("<h3>Nodes</h3>");
+    channel_header(&mut b, "Node");
+    for n in &c.nodes {
+        let link = format!(
+            "<a href=\"/nodes/{}\">{}</a>",
+            node_path(&n.node),
+            escape(&n.node)
+        );
+        channel_row(&mut b, &link, n, "");
+    }
+    channel_row(&mut b, "Total", c, " class=\"total\"");
+    b.push_str("</table>");
+
+    b.push_str("<h3>Clients</h3>");
+    if c.clients.is_empty() {
+        b.push_str("<p>No clients</p>");
+    } else {
+        b.push_str(
+            "<table><tr><th>Client</th><th>Node</th><th>Version</th><th>Address</th>\
+             <th>Ready</th><th>In-Flight</th><th>Finished</th><th>Requeued</th>\
+             <th>Messages</th><th>Connected</th><th>Flags</th></tr>",
+        );
+        for client in &c.clients {
+            client_row(&mut b, client);
+        }
+        b.push_str("</table>");
+    }
+
+    page(name, &b, errs)
+}
+
+pub(super) fn nodes(nodes: &[Producer], errs: &[String]) -> Html<String> {
+    let mut b = String::from("<h2>Nodes</h2>");
+    if nodes.is_empty() {
+        b.push_str("<p>No nodes</p>");
+        return page("Nodes", &b, errs);
+    }
+
+    b.push_str(
+        "<table><tr><th>Node</th><th>Hostname</th><th>Broadcast Address</th>\
+         <th>TCP Port</th><th>HTTP Port</th><th>Version</th><th>Topics</th></tr>",
+    );
+    for n in nodes {
+        let _ = write!(
+            b,
+            "<tr><td><a href=\"/nodes/{}\">{}</a></td><td>{}</td><td>{}</td>\
+             <td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            node_path(&n.http_address),
+            escape(&n.http_address),
+            escape(&n.hostname),
+            escape(&n.broadcast_address),
+            n.tcp_port,
+            n.http_port,
+            escape(&n.version),
+            n.topics.len()
+        );
+    }
+    b.push_str("</table>");
+    page("Nodes", &b, errs)
+}
+
+pub(super) fn node(name: &str, topics: Option<&[TopicStats]>, errs: &[String]) -> Html<String> {
+    let mut b = String::new();
+    let _ = write!(b, "<h2>Node: {}</h2>", escape(name));
+    let Some(topics) = topics else {
+        b.push_str("<p>Node not found</p>");
+        return page(name, &b, errs);
+    };
+    if topics.is_empty() {
+        b.push_str("<p>No topics</p>");
+    }
+
+    for t in topics {
+        let _ = write!(
+            b,
+            "<h3><a href=\"/topics/{}\">{}</a>{}</h3><p>depth: {} ({} + {}) messages: {}</p>",
+            path_escape(&t.topic_name),
+            escape(&t.topic_name),
+            paused(t.paused),
+            t.depth,
+            t.depth - t.backend_depth,
+            t.backend_depth,
+            t.message_count
+        );
+        if t.channels.is_empty() {
+            continue;
+        }
+        channel_header(&mut b, "Channel");
+        for c in &t.channels {
+            channel_row(&mut b, &escape(&c.channel_name), c, "");
+        }
+        b.push_str("</table>");
+    }
+    page(name, &b, errs)
+}
+
+fn action_buttons(b: &mut String, api: &str, is_paused: bool, after_delete: &str) {
+    let (pause, label) = if is_paused {
+        ("unpause", "Unpause")
+    } else {
+        ("pause", "Pause")
+    };
+    let _ = write!(
+        b,
+        "<p><button onclick=\"act('POST', '{api}', {{action: '{pause}'}})\">{label}</button>\
+         <button onclick=\"act('POST', '{api}', {{action: 'empty'}}, 'Empty all messages?')\">Empty</button>\
+         <button onclick=\"act('DELETE', '{api}', null, 'Delete?', '{after_delete}')\">Delete</button></p>"
+    );
+}
+
+fn channel_header(b: &mut String, first: &str) {
+    let _ = write!(
+        b,
+        "<table><tr><th>{first}</th><th>Depth</th><th>Memory + Disk</th><th>In-Flight</th>\
+         <th>Deferred</th><th>Requeued</th><th>Timed Out</th><th>Messages</th><th>Rate</th>\
+         <th>Connections</th></tr>"
+    );
+}
+
+fn channel_row(b: &mut String, first: &str, c: &ChannelStats, class: &str) {
+    let _ = write!(
+        b,
+        "<tr{class}><td>{first}{}</td><td>{}</td><td>{} + {}</td><td>{}</td><td>{}</td>\
+         <td>{}</td><td>{}</td><td>{}</td><td>{:.1}/s</td><td>{}</td></tr>",
+        paused(c.paused),
+        c.depth,
+        c.depth - c.backend_depth,
+        c.backend_depth,
+        c.in_flight_count,
+        c.deferred_count,
+        c.requeue_count,
+        c.timeout_count,
+        c.message_count,
+        c.message_rate,
+        c.client_count
+    );
+}
+
+fn client_row(b: &mut String, c: &ClientStats) {
+    let mut flags = Vec::new();
+    if c.tls {
+        flags.push("TLS");
+    }
+    if c.deflate {
+        flags.push("Deflate");
+    }
+    if c.snappy {
+        flags.push("Snappy");
+    }
+    if c.authed {
+        flags.push("Authed");
+    }
+
+    let _ = write!(
+        b,
+        "<tr><td>{} {}</td><td><a href=\"/nodes/{}\">{}</a></td><td>{}</td><td>{}</td>\
+         <td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        escape(&c.hostname),
+        escape(&c.user_agent),
+        node_path(&c.node),
+        escape(&c.node),
+        escape(&c.version),
+        escape(&c.remote_address),
+        c.ready_count,
+        c.in_flight_count,
+        c.finish_count,
+        c.requeue_count,
+        c.message_count,
+        c.connect_ts,
+        flags.join(" ")
+    );
+}
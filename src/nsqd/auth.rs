@@ -0,0 +1,350 @@
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use regex::Regex;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{common::Result, errors::NsqError};
+
+const PERMISSION_SUBSCRIBE: &str = "subscribe";
+const PERMISSION_PUBLISH: &str = "publish";
+
+// auth server返回的单条授权信息，topic和channels都是正则表达式
+#[derive(Debug, Deserialize)]
+pub(super) struct Authorization {
+    pub topic: String,
+    pub channels: Vec<String>,
+    pub permissions: Vec<String>,
+
+    #[serde(skip)]
+    topic_regex: Option<Regex>,
+    #[serde(skip)]
+    channel_regexes: Vec<Regex>,
+}
+
+impl Authorization {
+    fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+
+    // channel为空表示检查发布权限，否则检查订阅权限
+    fn is_allowed(&self, topic: &str, channel: &str) -> bool {
+        let permission = if channel.is_empty() {
+            PERMISSION_PUBLISH
+        } else {
+            PERMISSION_SUBSCRIBE
+        };
+        if !self.has_permission(permission) {
+            return false;
+        }
+
+        if !self.topic_regex.as_ref().is_some_and(|r| r.is_match(topic)) {
+            return false;
+        }
+
+        self.channel_regexes.iter().any(|r| r.is_match(channel))
+    }
+
+    // 校验auth server返回的数据并预先编译正则表达式
+    fn compile(&mut self) -> Result<()> {
+        for p in &self.permissions {
+            if p != PERMISSION_SUBSCRIBE && p != PERMISSION_PUBLISH {
+                return Err(NsqError::AuthError(format!("unknown permission {p}")));
+            }
+        }
+
+        let topic_regex = Regex::new(&self.topic).map_err(|e| {
+            NsqError::AuthError(format!("unable to compile topic {:?} {e}", self.topic))
+        })?;
+        self.topic_regex = Some(topic_regex);
+
+        self.channel_regexes = self
+            .channels
+            .iter()
+            .map(|c| {
+                Regex::new(c).map_err(|e| {
+                    NsqError::AuthError(format!("unable to compile channel {c:?} {e}"))
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(())
+    }
+}
+
+// auth server的响应，ttl秒之后需要重新查询
+#[derive(Debug, Deserialize)]
+pub(super) struct State {
+    pub ttl: i64,
+    pub authorizations: Vec<Authorization>,
+    #[serde(default)]
+    pub identity: String,
+    #[serde(default)]
+    pub identity_url: String,
+
+    #[serde(skip, default = "Instant::now")]
+    expires: Instant,
+}
+
+impl State {
+    pub fn is_allowed(&self, topic: &str, channel: &str) -> bool {
+        self.authorizations
+            .iter()
+            .any(|a| a.is_allowed(topic, channel))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires <= Instant::now()
+    }
+}
+
+// 发送给auth server的参数
+pub(super) struct Query<'a> {
+    pub remote_ip: String,
+    pub tls: bool,
+    pub common_name: String,
+    pub secret: &'a str,
+}
+
+// 从随机的一个auth server开始依次查询，直到有一个成功
+pub(super) async fn query_any_authd(
+    client: &reqwest::Client,
+    authd: &[String],
+    method: &str,
+    query: &Query<'_>,
+) -> Result<State> {
+    if authd.is_empty() {
+        return Err(NsqError::AuthError("no auth server configured".to_owned()));
+    }
+
+    let start = rand::rng().random_range(0..authd.len());
+    let mut ret_err = None;
+    for i in 0..authd.len() {
+        let addr = &authd[(start + i) % authd.len()];
+        match query_authd(client, addr, method, query).await {
+            Ok(state) => return Ok(state),
+            Err(e) => {
+                warn!("failed to auth against {addr} - {e}");
+                ret_err = Some(e);
+            }
+        }
+    }
+    Err(ret_err.unwrap())
+}
+
+async fn query_authd(
+    client: &reqwest::Client,
+    authd: &str,
+    method: &str,
+    query: &Query<'_>,
+) -> Result<State> {
+    let endpoint = if authd.contains("://") {
+        authd.to_owned()
+    } else {
+        format!("http://{authd}/auth")
+    };
+
+    let tls = if query.tls { "true" } else { "false" };
+    let params = [
+        ("remote_ip", query.remote_ip.as_str()),
+        ("tls", tls),
+        ("secret", query.secret),
+        ("common_name", query.common_name.as_str()),
+    ];
+
+    let req = if method.eq_ignore_ascii_case("post") {
+        client.post(&endpoint).form(&params)
+    } else {
+        client.get(&endpoint).query(&params)
+    };
+
+    let resp = req
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| NsqError::AuthError(e.to_string()))?;
+    let mut state: State = resp
+        .json()
+        .await
+        .map_err(|e| NsqError::AuthError(e.to_string()))?;
+
+    for a in &mut state.authorizations {
+        a.compile()?;
+    }
+
+    if state.ttl <= 0 {
+        return Err(NsqError::AuthError(format!(
+            "invalid TTL {} (must be >0)",
+            state.ttl
+        )));
+    }
+    state.expires = Instant::now() + Duration::from_secs(state.ttl as u64);
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{
+        extract::{Query as QueryParams, State as AxumState},
+        http::StatusCode,
+        routing::get,
+        Json, Router,
+    };
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    use crate::nsqd::{
+        protocol_v2::FrameType,
+        test_util::{test_options, TestClient, TestNsqd},
+    };
+
+    const SECRET: &str = "s3cret";
+
+    // 本地的auth server，每次查询都会计数
+    async fn start_authd() -> (String, Arc<AtomicUsize>) {
+        async fn auth(
+            AxumState(count): AxumState<Arc<AtomicUsize>>,
+            QueryParams(params): QueryParams<HashMap<String, String>>,
+        ) -> Result<Json<Value>, StatusCode> {
+            count.fetch_add(1, Ordering::SeqCst);
+            if params.get("secret").map(String::as_str) != Some(SECRET) {
+                return Err(StatusCode::FORBIDDEN);
+            }
+            Ok(Json(json!({
+                "ttl": 1,
+                "identity": "tester",
+                "identity_url": "http://example.com/tester",
+                "authorizations": [
+                    {"topic": "^allowed$", "channels": [".*"], "permissions": ["publish"]},
+                    {"topic": "^allowed$", "channels": ["^ch$"], "permissions": ["subscribe"]},
+                ],
+            })))
+        }
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/auth", get(auth))
+            .with_state(count.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (addr, count)
+    }
+
+    async fn authed_client(server: &TestNsqd) -> TestClient {
+        let mut client = server.connect().await;
+        client.command_with_body("AUTH", SECRET.as_bytes()).await;
+        let (ft, body) = client.read_frame().await.unwrap();
+        assert_eq!(ft, FrameType::Response as u32);
+        let resp: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(resp["identity"], "tester");
+        assert_eq!(resp["identity_url"], "http://example.com/tester");
+        assert_eq!(resp["permission_count"], 2);
+        client
+    }
+
+    async fn assert_error(client: &mut TestClient, code: &str) {
+        let (ft, body) = client.read_frame().await.unwrap();
+        assert_eq!(ft, FrameType::Error as u32);
+        assert!(
+            body.starts_with(code.as_bytes()),
+            "{}",
+            String::from_utf8_lossy(&body)
+        );
+        // 授权失败都是致命错误
+        assert!(client.read_frame().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn auth_round_trip() {
+        let (authd, count) = start_authd().await;
+        let dir = TempDir::new().unwrap();
+        let mut opts = test_options(dir.path());
+        opts.auth_http_addrs = vec![authd];
+        let server = TestNsqd::start(opts).await;
+
+        // 没有AUTH之前不能PUB
+        let mut client = server.connect().await;
+        client.command_with_body("PUB allowed", b"msg").await;
+        assert_error(&mut client, "E_AUTH_FIRST").await;
+
+        // secret错误
+        let mut client = server.connect().await;
+        client.command_with_body("AUTH", b"wrong").await;
+        assert_error(&mut client, "E_AUTH_FAILED").await;
+
+        let mut client = authed_client(&server).await;
+        client.command_with_body("PUB allowed", b"msg").await;
+        assert_eq!(
+            client.read_frame().await.unwrap(),
+            (FrameType::Response as u32, b"OK".to_vec())
+        );
+        client.command("SUB allowed ch").await;
+        assert_eq!(
+            client.read_frame().await.unwrap(),
+            (FrameType::Response as u32, b"OK".to_vec())
+        );
+
+        server.stop().await;
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn auth_ttl_expiry() {
+        let (authd, count) = start_authd().await;
+        let dir = TempDir::new().unwrap();
+        let mut opts = test_options(dir.path());
+        opts.auth_http_addrs = vec![authd];
+        let server = TestNsqd::start(opts).await;
+
+        let mut client = authed_client(&server).await;
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // ttl之内使用缓存的授权信息
+        client.command_with_body("PUB allowed", b"msg").await;
+        assert_eq!(client.read_frame().await.unwrap().1, b"OK");
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // 过期之后重新查询auth server
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        client.command_with_body("PUB allowed", b"msg").await;
+        assert_eq!(client.read_frame().await.unwrap().1, b"OK");
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn auth_unauthorized() {
+        let (authd, _) = start_authd().await;
+        let dir = TempDir::new().unwrap();
+        let mut opts = test_options(dir.path());
+        opts.auth_http_addrs = vec![authd];
+        let server = TestNsqd::start(opts).await;
+
+        let mut client = authed_client(&server).await;
+        client.command_with_body("PUB denied", b"msg").await;
+        assert_error(&mut client, "E_UNAUTHORIZED").await;
+
+        let mut client = authed_client(&server).await;
+        client.command("SUB denied ch").await;
+        assert_error(&mut client, "E_UNAUTHORIZED").await;
+
+        let mut client = authed_client(&server).await;
+        client.command("SUB allowed other").await;
+        assert_error(&mut client, "E_UNAUTHORIZED").await;
+
+        server.stop().await;
+    }
+}
@@ -1,12 +1,57 @@
-use tokio::sync::mpsc;
+use std::{future::Future, pin::Pin};
+
+use async_channel::{Receiver, Sender};
 
 use crate::common::Result;
 
-pub(super) trait BackEndQueue {
-    async fn put(&mut self, b: &[u8]) -> Result<()>;
-    fn read_chan() -> mpsc::Receiver<Vec<u8>>;
-    fn close() -> Result<()>;
-    fn delete() -> Result<()>;
-    fn depth() -> i64;
-    fn empty() -> Result<()>;
+pub(super) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// 写入、清空和关闭都可能涉及文件IO，以异步的方式执行，避免阻塞调用方所在的worker线程
+pub(super) trait BackEndQueue: Send + Sync {
+    fn put(&self, b: Vec<u8>) -> BoxFuture<'_, Result<()>>;
+    fn read_chan(&self) -> Receiver<Vec<u8>>;
+    fn close(&self) -> BoxFuture<'_, Result<()>>;
+    fn delete(&self) -> BoxFuture<'_, Result<()>>;
+    fn depth(&self) -> i64;
+    fn empty(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+// 不做任何持久化的后端队列，写入的数据直接丢弃
+pub(super) struct DummyBackendQueue {
+    // 保留发送端，使read_chan永远不会被关闭
+    _read_tx: Sender<Vec<u8>>,
+    read_rx: Receiver<Vec<u8>>,
+}
+
+impl DummyBackendQueue {
+    pub fn new() -> Self {
+        let (_read_tx, read_rx) = async_channel::bounded(1);
+        Self { _read_tx, read_rx }
+    }
+}
+
+impl BackEndQueue for DummyBackendQueue {
+    fn put(&self, _b: Vec<u8>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn read_chan(&self) -> Receiver<Vec<u8>> {
+        self.read_rx.clone()
+    }
+
+    fn close(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn depth(&self) -> i64 {
+        0
+    }
+
+    fn empty(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
 }
@@ -1 +1,569 @@
-pub struct Channel {}
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_channel::{Receiver, Sender, TrySendError};
+use tracing::{error, info};
+
+use super::{
+    backend_queue::{BackEndQueue, DummyBackendQueue},
+    client_v2::{Client, ClientV2},
+    disk_queue::DiskQueue,
+    message::{Message, MessageID},
+    nsqd::NSQD,
+    pqueue::PriorityQueue,
+};
+use crate::{
+    common::{is_ephemeral, unix_nanos, Result},
+    errors::NsqError,
+};
+
+pub(super) struct Channel {
+    topic_name: String,
+    name: String,
+
+    // mem_queue_size为0时不使用内存队列，所有消息直接写入backend
+    mem_msg_tx: Option<Sender<Message>>,
+    mem_msg_rx: Receiver<Message>,
+    backend: Box<dyn BackEndQueue>,
+
+    clients: Mutex<HashMap<i64, Arc<ClientV2>>>,
+
+    exiting: AtomicBool,
+    paused: AtomicBool,
+
+    // 已经发送给客户端，等待FIN/REQ的消息，优先级为超时时间
+    in_flight: Mutex<PriorityQueue>,
+    // 延迟投递的消息，优先级为投递时间
+    deferred: Mutex<PriorityQueue>,
+
+    message_count: AtomicU64,
+    requeue_count: AtomicU64,
+    timeout_count: AtomicU64,
+
+    nsqd: Arc<NSQD>,
+}
+
+impl Channel {
+    pub fn new(topic_name: &str, name: &str, nsqd: Arc<NSQD>) -> Self {
+        let mem_queue_size = nsqd.get_opts().mem_queue_size as usize;
+        let (mem_msg_tx, mem_msg_rx) = async_channel::bounded(mem_queue_size.max(1));
+
+        // topic或channel为ephemeral时都不需要持久化
+        let backend: Box<dyn BackEndQueue> = if is_ephemeral(topic_name) || is_ephemeral(name) {
+            Box::new(DummyBackendQueue::new())
+        } else {
+            Box::new(DiskQueue::new(
+                &format!("{topic_name}:{name}"),
+                nsqd.get_opts(),
+            ))
+        };
+
+        info!("TOPIC({topic_name}): new channel({name})");
+        Self {
+            topic_name: topic_name.to_owned(),
+            name: name.to_owned(),
+            mem_msg_tx: (mem_queue_size > 0).then_some(mem_msg_tx),
+            mem_msg_rx,
+            backend,
+            clients: Mutex::new(HashMap::new()),
+            exiting: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            in_flight: Mutex::new(PriorityQueue::new()),
+            deferred: Mutex::new(PriorityQueue::new()),
+            message_count: AtomicU64::new(0),
+            requeue_count: AtomicU64::new(0),
+            timeout_count: AtomicU64::new(0),
+            nsqd,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn topic_name(&self) -> &str {
+        &self.topic_name
+    }
+
+    pub fn memory_msg_chan(&self) -> Receiver<Message> {
+        self.mem_msg_rx.clone()
+    }
+
+    pub fn backend_msg_chan(&self) -> Receiver<Vec<u8>> {
+        self.backend.read_chan()
+    }
+
+    pub fn is_exiting(&self) -> bool {
+        self.exiting.load(Ordering::SeqCst)
+    }
+
+    pub fn depth(&self) -> i64 {
+        self.mem_msg_rx.len() as i64 + self.backend.depth()
+    }
+
+    pub fn backend_depth(&self) -> i64 {
+        self.backend.depth()
+    }
+
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    pub fn deferred_count(&self) -> usize {
+        self.deferred.lock().unwrap().len()
+    }
+
+    pub fn message_count(&self) -> u64 {
+        self.message_count.load(Ordering::SeqCst)
+    }
+
+    pub fn requeue_count(&self) -> u64 {
+        self.requeue_count.load(Ordering::SeqCst)
+    }
+
+    pub fn timeout_count(&self) -> u64 {
+        self.timeout_count.load(Ordering::SeqCst)
+    }
+
+    pub fn pause(&self) {
+        self.do_pause(true);
+    }
+
+    pub fn unpause(&self) {
+        self.do_pause(false);
+    }
+
+    fn do_pause(&self, pause: bool) {
+        self.paused.store(pause, Ordering::SeqCst);
+
+        for client in self.clients.lock().unwrap().values() {
+            if pause {
+                client.pause();
+            } else {
+                client.unpause();
+            }
+        }
+
+        self.nsqd.notify_persist();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn add_client(&self, client_id: i64, client: Arc<ClientV2>) -> Result<()> {
+        if self.is_exiting() {
+            return Err(NsqError::Exiting);
+        }
+
+        let mut clients = self.clients.lock().unwrap();
+        if clients.contains_key(&client_id) {
+            return Ok(());
+        }
+
+        let max_channel_consumers = self.nsqd.get_opts().max_channel_consumers;
+        if max_channel_consumers != 0 && clients.len() as isize >= max_channel_consumers {
+            return Err(NsqError::TooManyConsumers(
+                format!("{}:{}", self.topic_name, self.name),
+                max_channel_consumers,
+            ));
+        }
+
+        clients.insert(client_id, client);
+        Ok(())
+    }
+
+    pub fn clients(&self) -> Vec<Arc<ClientV2>> {
+        self.clients.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn remove_client(&self, client_id: i64) {
+        self.clients.lock().unwrap().remove(&client_id);
+    }
+
+    pub async fn put_message(&self, msg: Message) -> Result<()> {
+        if self.is_exiting() {
+            return Err(NsqError::Exiting);
+        }
+
+        self.put(msg).await?;
+        self.message_count.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn put_message_deferred(&self, msg: Message, timeout: Duration) -> Result<()> {
+        self.message_count.fetch_add(1, Ordering::SeqCst);
+        self.start_deferred_timeout(msg, timeout)
+    }
+
+    // 优先写入内存队列，内存队列满了之后写入backend
+    async fn put(&self, msg: Message) -> Result<()> {
+        let msg = match &self.mem_msg_tx {
+            Some(tx) => match tx.try_send(msg) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(msg) | TrySendError::Closed(msg)) => msg,
+            },
+            None => msg,
+        };
+
+        msg.write_to_backend(&*self.backend).await.map_err(|e| {
+            error!(
+                "TOPIC({}) CHANNEL({}): failed to write message to backend - {e}",
+                self.topic_name, self.name
+            );
+            e
+        })
+    }
+
+    // 消息发送给客户端之前放入in-flight队列，超时之后会重新投递
+    pub fn start_in_flight_timeout(
+        &self,
+        mut msg: Message,
+        client_id: i64,
+        timeout: Duration,
+    ) -> Result<()> {
+        let now = Instant::now();
+        msg.client_id = Some(client_id);
+        msg.delivery_ts = Some(now);
+        msg.pri = unix_nanos() + timeout.as_nanos() as i64;
+        self.in_flight.lock().unwrap().push(msg)
+    }
+
+    pub fn start_deferred_timeout(&self, mut msg: Message, timeout: Duration) -> Result<()> {
+        msg.pri = unix_nanos() + timeout.as_nanos() as i64;
+        self.deferred.lock().unwrap().push(msg)
+    }
+
+    pub fn finish_message(&self, client_id: i64, id: &MessageID) -> Result<()> {
+        self.pop_in_flight_message(client_id, id)?;
+        Ok(())
+    }
+
+    // timeout为0时消息立即重新投递，否则放入deferred队列
+    pub async fn requeue_message(
+        &self,
+        client_id: i64,
+        id: &MessageID,
+        timeout: Duration,
+    ) -> Result<()> {
+        let msg = self.pop_in_flight_message(client_id, id)?;
+        self.requeue_count.fetch_add(1, Ordering::SeqCst);
+
+        if timeout.is_zero() {
+            if self.is_exiting() {
+                return Err(NsqError::Exiting);
+            }
+            return self.put(msg).await;
+        }
+
+        self.start_deferred_timeout(msg, timeout)
+    }
+
+    // 重置消息的超时时间，但总的处理时间不能超过max_msg_timeout
+    pub fn touch_message(
+        &self,
+        client_id: i64,
+        id: &MessageID,
+        client_msg_timeout: Duration,
+    ) -> Result<()> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let mut msg = Self::check_owner(&mut in_flight, client_id, id)?;
+
+        let max_msg_timeout = self.nsqd.get_opts().max_msg_timeout;
+        let elapsed = msg.delivery_ts.map_or(Duration::ZERO, |ts| ts.elapsed());
+        let timeout = client_msg_timeout.min(max_msg_timeout.saturating_sub(elapsed));
+        msg.pri = unix_nanos() + timeout.as_nanos() as i64;
+
+        in_flight.push(msg)
+    }
+
+    fn pop_in_flight_message(&self, client_id: i64, id: &MessageID) -> Result<Message> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        Self::check_owner(&mut in_flight, client_id, id)
+    }
+
+    fn check_owner(
+        in_flight: &mut PriorityQueue,
+        client_id: i64,
+        id: &MessageID,
+    ) -> Result<Message> {
+        let msg = in_flight.get(id).ok_or(NsqError::MsgNotInFlight)?;
+        if msg.client_id != Some(client_id) {
+            return Err(NsqError::MsgNotOwned);
+        }
+        Ok(in_flight.remove(id).unwrap())
+    }
+
+    // 将已经超时的in-flight消息重新投递，返回是否有消息超时
+    pub async fn process_in_flight_queue(&self, t: i64) -> bool {
+        if self.is_exiting() {
+            return false;
+        }
+
+        let mut dirty = false;
+        loop {
+            let msg = self.in_flight.lock().unwrap().peek_and_shift(t);
+            let Some(msg) = msg else {
+                return dirty;
+            };
+            dirty = true;
+
+            self.timeout_count.fetch_add(1, Ordering::SeqCst);
+            let client = msg
+                .client_id
+                .and_then(|id| self.clients.lock().unwrap().get(&id).cloned());
+            if let Some(client) = client {
+                client.timed_out_msg();
+            }
+
+            if let Err(e) = self.put(msg).await {
+                error!(
+                    "TOPIC({}) CHANNEL({}): failed to requeue timed out message - {e}",
+                    self.topic_name, self.name
+                );
+            }
+        }
+    }
+
+    // 将已经到达投递时间的deferred消息放入队列，返回是否有消息到期
+    pub async fn process_deferred_queue(&self, t: i64) -> bool {
+        if self.is_exiting() {
+            return false;
+        }
+
+        let mut dirty = false;
+        loop {
+            let msg = self.deferred.lock().unwrap().peek_and_shift(t);
+            let Some(msg) = msg else {
+                return dirty;
+            };
+            dirty = true;
+
+            if let Err(e) = self.put(msg).await {
+                error!(
+                    "TOPIC({}) CHANNEL({}): failed to put deferred message - {e}",
+                    self.topic_name, self.name
+                );
+            }
+        }
+    }
+
+    // 丢弃channel中所有的消息，包括in-flight和deferred的消息
+    pub async fn empty(&self) -> Result<()> {
+        self.in_flight.lock().unwrap().clear();
+        self.deferred.lock().unwrap().clear();
+
+        for client in self.clients() {
+            client.empty();
+        }
+
+        while self.mem_msg_rx.try_recv().is_ok() {}
+        self.backend.empty().await
+    }
+
+    // 关闭所有客户端，将内存队列、in-flight和deferred中的消息写入backend
+    pub async fn close(&self) -> Result<()> {
+        self.exit(false).await
+    }
+
+    // 关闭所有客户端，删除所有消息
+    pub async fn delete(&self) -> Result<()> {
+        self.exit(true).await
+    }
+
+    async fn exit(&self, deleted: bool) -> Result<()> {
+        if self.exiting.swap(true, Ordering::SeqCst) {
+            return Err(NsqError::Exiting);
+        }
+
+        if deleted {
+            info!(
+                "TOPIC({}) CHANNEL({}): deleting",
+                self.topic_name, self.name
+            );
+        } else {
+            info!("TOPIC({}) CHANNEL({}): closing", self.topic_name, self.name);
+        }
+
+        for client in self.clients.lock().unwrap().values() {
+            client.close();
+        }
+
+        if deleted {
+            self.empty().await?;
+            return self.backend.delete().await;
+        }
+
+        self.flush().await;
+        self.backend.close().await
+    }
+
+    async fn flush(&self) {
+        let mut msgs = Vec::new();
+        while let Ok(msg) = self.mem_msg_rx.try_recv() {
+            msgs.push(msg);
+        }
+        msgs.extend(self.in_flight.lock().unwrap().drain());
+        msgs.extend(self.deferred.lock().unwrap().drain());
+
+        if !msgs.is_empty() {
+            info!(
+                "CHANNEL({}): flushing {} messages to backend",
+                self.name,
+                msgs.len()
+            );
+        }
+
+        for msg in msgs {
+            if let Err(e) = msg.write_to_backend(&*self.backend).await {
+                error!(
+                    "CHANNEL({}): failed to write message to backend - {e}",
+                    self.name
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsqd::test_util::TestNsqd;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    // 模拟client_id的客户端从channel中取出一条消息
+    async fn deliver(channel: &Channel, client_id: i64) -> Message {
+        let msg = channel.memory_msg_chan().recv().await.unwrap();
+        channel
+            .start_in_flight_timeout(msg.clone(), client_id, TIMEOUT)
+            .unwrap();
+        msg
+    }
+
+    #[tokio::test]
+    async fn finish_and_ownership() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let channel = server.nsqd.get_topic("t").get_channel("c");
+
+        channel
+            .put_message(Message::new([1; 16], b"a".to_vec()))
+            .await
+            .unwrap();
+        let msg = deliver(&channel, 1).await;
+        assert_eq!(channel.in_flight_count(), 1);
+
+        // 只有收到消息的客户端可以FIN/REQ/TOUCH
+        assert!(matches!(
+            channel.finish_message(2, &msg.id),
+            Err(NsqError::MsgNotOwned)
+        ));
+        assert!(matches!(
+            channel.requeue_message(2, &msg.id, Duration::ZERO).await,
+            Err(NsqError::MsgNotOwned)
+        ));
+        assert!(matches!(
+            channel.touch_message(2, &msg.id, TIMEOUT),
+            Err(NsqError::MsgNotOwned)
+        ));
+        assert!(matches!(
+            channel.finish_message(1, &[2; 16]),
+            Err(NsqError::MsgNotInFlight)
+        ));
+        // 检查失败时消息仍然在in-flight队列中
+        assert_eq!(channel.in_flight_count(), 1);
+
+        channel.finish_message(1, &msg.id).unwrap();
+        assert_eq!(channel.in_flight_count(), 0);
+        assert!(matches!(
+            channel.finish_message(1, &msg.id),
+            Err(NsqError::MsgNotInFlight)
+        ));
+        assert_eq!(channel.depth(), 0);
+        assert_eq!(channel.message_count(), 1);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn requeue() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let channel = server.nsqd.get_topic("t").get_channel("c");
+
+        channel
+            .put_message(Message::new([1; 16], b"a".to_vec()))
+            .await
+            .unwrap();
+        let msg = deliver(&channel, 1).await;
+
+        // timeout为0时立即放回队列
+        channel
+            .requeue_message(1, &msg.id, Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(channel.in_flight_count(), 0);
+        assert_eq!(channel.depth(), 1);
+        assert_eq!(channel.requeue_count(), 1);
+
+        // 否则放入deferred队列，到期之后才会重新投递
+        let msg = deliver(&channel, 1).await;
+        channel.requeue_message(1, &msg.id, TIMEOUT).await.unwrap();
+        assert_eq!(channel.in_flight_count(), 0);
+        assert_eq!(channel.deferred_count(), 1);
+        assert_eq!(channel.depth(), 0);
+        assert_eq!(channel.requeue_count(), 2);
+
+        assert!(!channel.process_deferred_queue(unix_nanos()).await);
+        let t = unix_nanos() + TIMEOUT.as_nanos() as i64;
+        assert!(channel.process_deferred_queue(t).await);
+        assert_eq!(channel.deferred_count(), 0);
+        assert_eq!(channel.depth(), 1);
+        assert_eq!(channel.message_count(), 1);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn touch() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let channel = server.nsqd.get_topic("t").get_channel("c");
+        let max_msg_timeout = server.nsqd.get_opts().max_msg_timeout;
+        let pri = |id: &MessageID| channel.in_flight.lock().unwrap().get(id).unwrap().pri;
+
+        channel
+            .put_message(Message::new([1; 16], b"a".to_vec()))
+            .await
+            .unwrap();
+        let msg = deliver(&channel, 1).await;
+        let before = pri(&msg.id);
+
+        // TOUCH重置超时时间
+        channel.touch_message(1, &msg.id, 2 * TIMEOUT).unwrap();
+        let after = pri(&msg.id);
+        assert!(after >= before + TIMEOUT.as_nanos() as i64);
+
+        // 总的处理时间不能超过max_msg_timeout
+        channel
+            .touch_message(1, &msg.id, 2 * max_msg_timeout)
+            .unwrap();
+        let capped = pri(&msg.id) - unix_nanos();
+        assert!(capped <= max_msg_timeout.as_nanos() as i64);
+        assert!(capped > (max_msg_timeout - TIMEOUT).as_nanos() as i64);
+
+        // TOUCH之后仍然可以FIN
+        assert_eq!(channel.in_flight_count(), 1);
+        channel.finish_message(1, &msg.id).unwrap();
+        assert!(matches!(
+            channel.touch_message(1, &msg.id, TIMEOUT),
+            Err(NsqError::MsgNotInFlight)
+        ));
+
+        server.stop().await;
+    }
+}
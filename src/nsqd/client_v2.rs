@@ -1,30 +1,247 @@
 use std::{
     collections::HashMap,
+    io, mem,
     net::SocketAddr,
+    pin::Pin,
     sync::{
-        atomic::{AtomicI64, AtomicU64, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
+    task::{ready, Context, Poll},
     time::{Duration, Instant},
 };
 
+use serde::Deserialize;
 use tokio::{
-    io::{BufReader, BufWriter},
-    net::{
-        tcp::{ReadHalf, WriteHalf},
-        TcpStream,
-    },
-    sync::{mpsc, oneshot},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter, ReadBuf},
+    net::TcpStream,
+    sync::{oneshot, Mutex as AsyncMutex, Notify},
 };
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    auth::{self, query_any_authd, Query},
+    channel::Channel,
+    compress::{Decoder, Encoder},
+    nsqd::NSQD,
+    stats::{ClientStats, PubCount},
+};
+use crate::common::{unix_nanos, Result};
 
-use super::{channel::Channel, nsqd::NSQD, shutdown::Shutdown};
-
-const DEFAULT_BUF_SIZE: i32 = 16 * 1024;
+pub(super) const DEFAULT_BUF_SIZE: usize = 16 * 1024;
+const MIN_OUTPUT_BUFFER_SIZE: i64 = 64;
 
 pub(super) trait Client {
-    fn close();
+    fn close(&self);
+    fn pause(&self);
+    fn unpause(&self);
+    fn empty(&self);
+}
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    // 正在升级，此时不能读写
+    Upgrading,
+}
+
+impl Stream {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self {
+            Self::Plain(s) => Pin::new(s).poll_read(cx, buf),
+            Self::Tls(s) => Pin::new(s.as_mut()).poll_read(cx, buf),
+            Self::Upgrading => Poll::Ready(Err(upgrading_err())),
+        }
+    }
+
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self {
+            Self::Plain(s) => Pin::new(s).poll_write(cx, buf),
+            Self::Tls(s) => Pin::new(s.as_mut()).poll_write(cx, buf),
+            Self::Upgrading => Poll::Ready(Err(upgrading_err())),
+        }
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            Self::Plain(s) => Pin::new(s).poll_flush(cx),
+            Self::Tls(s) => Pin::new(s.as_mut()).poll_flush(cx),
+            Self::Upgrading => Poll::Ready(Err(upgrading_err())),
+        }
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            Self::Plain(s) => Pin::new(s).poll_shutdown(cx),
+            Self::Tls(s) => Pin::new(s.as_mut()).poll_shutdown(cx),
+            Self::Upgrading => Poll::Ready(Err(upgrading_err())),
+        }
+    }
+}
+
+fn upgrading_err() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "connection is upgrading")
+}
+
+// 毫秒数限制在[min, max]之间，配置的max小于min时以max为准
+fn clamp_millis(ms: i64, min: Duration, max: Duration) -> Duration {
+    Duration::from_millis(ms.max(0) as u64).max(min).min(max)
+}
+
+// 压缩层位于TLS之上，读写两端各自维护压缩状态
+struct Codec {
+    encoder: Encoder,
+    decoder: Decoder,
+    // 已经压缩但还没有写入连接的数据
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl Codec {
+    fn new(encoder: Encoder, decoder: Decoder) -> Self {
+        Self {
+            encoder,
+            decoder,
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+
+    fn poll_write_pending(
+        &mut self,
+        stream: &mut Stream,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let n = ready!(stream.poll_write(cx, &self.pending[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct Conn {
+    stream: Stream,
+    codec: Option<Codec>,
+}
+
+// 客户端连接，读写两端共享同一个底层连接。
+// IDENTIFY协商之后可以原地升级为TLS连接或者开启压缩，不需要替换读写两端
+#[derive(Clone)]
+pub(super) struct ClientConn(Arc<Mutex<Conn>>);
+
+impl ClientConn {
+    pub fn new(conn: TcpStream) -> Self {
+        Self(Arc::new(Mutex::new(Conn {
+            stream: Stream::Plain(conn),
+            codec: None,
+        })))
+    }
+
+    pub async fn upgrade_tls(&self, acceptor: &TlsAcceptor) -> io::Result<()> {
+        let stream = mem::replace(&mut self.0.lock().unwrap().stream, Stream::Upgrading);
+        let Stream::Plain(conn) = stream else {
+            self.0.lock().unwrap().stream = stream;
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "connection already upgraded",
+            ));
+        };
+
+        let conn = acceptor.accept(conn).await?;
+        self.0.lock().unwrap().stream = Stream::Tls(Box::new(conn));
+        Ok(())
+    }
+
+    fn upgrade_codec(&self, encoder: Encoder, decoder: Decoder) -> io::Result<()> {
+        let mut conn = self.0.lock().unwrap();
+        if conn.codec.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "compression already enabled",
+            ));
+        }
+        conn.codec = Some(Codec::new(encoder, decoder));
+        Ok(())
+    }
+}
+
+impl AsyncRead for ClientConn {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut conn = self.0.lock().unwrap();
+        let Conn { stream, codec } = &mut *conn;
+        let Some(codec) = codec else {
+            return stream.poll_read(cx, buf);
+        };
+
+        // 读取压缩数据直到解压出至少一个字节，或者连接关闭
+        let mut raw = [0u8; 4096];
+        loop {
+            let output = codec.decoder.output();
+            if !output.is_empty() {
+                let n = output.len().min(buf.remaining());
+                buf.put_slice(&output[..n]);
+                output.drain(..n);
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut raw_buf = ReadBuf::new(&mut raw);
+            ready!(stream.poll_read(cx, &mut raw_buf))?;
+            if raw_buf.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            codec.decoder.decode(raw_buf.filled())?;
+        }
+    }
 }
 
+impl AsyncWrite for ClientConn {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut conn = self.0.lock().unwrap();
+        let Conn { stream, codec } = &mut *conn;
+        let Some(codec) = codec else {
+            return stream.poll_write(cx, buf);
+        };
+
+        // 上一次压缩的数据全部写入之后才接收新的数据
+        ready!(codec.poll_write_pending(stream, cx))?;
+        codec.encoder.write(buf)?;
+        codec.encoder.take_output(&mut codec.pending);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut conn = self.0.lock().unwrap();
+        let Conn { stream, codec } = &mut *conn;
+        if let Some(codec) = codec {
+            codec.encoder.flush()?;
+            codec.encoder.take_output(&mut codec.pending);
+            ready!(codec.poll_write_pending(stream, cx))?;
+        }
+        stream.poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.0.lock().unwrap().stream.poll_shutdown(cx)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(super) enum State {
     Init,
     Disconnected,
@@ -34,7 +251,7 @@ pub(super) enum State {
 }
 
 pub(super) struct ClientV2 {
-    id: i64,
+    pub id: i64,
 
     ready_count: AtomicI64,
     in_flight_count: AtomicI64,
@@ -42,64 +259,64 @@ pub(super) struct ClientV2 {
     finish_count: AtomicU64,
     requeue_count: AtomicU64,
 
-    pub_counts: HashMap<String, AtomicU64>,
+    pub_counts: Mutex<HashMap<String, u64>>,
 
-    // TODO: 需要锁？
-    //      writeLock
-    //      metaLock
     nsqd: Arc<NSQD>,
 
-    user_agent: Option<String>,
+    user_agent: Mutex<Option<String>>,
 
-    stream: TcpStream,
+    conn: ClientConn,
+    // 写入端由io_loop和message pump共享
+    pub writer: AsyncMutex<BufWriter<ClientConn>>,
 
-    // tls_conn,
-    // flate_writer,
-    output_buffer_size: i32,
-    output_buffer_timeout: Duration,
-    heartbeat_interval: Duration,
-    msg_timeout: Duration,
+    // 以下配置可以在IDENTIFY时由客户端修改
+    output_buffer_size: AtomicUsize,
+    output_buffer_timeout: Mutex<Duration>,
+    heartbeat_interval: Mutex<Duration>,
+    msg_timeout: Mutex<Duration>,
 
-    state: State,
+    state: Mutex<State>,
     connect_time: Instant,
 
-    channel: Option<Channel>,
+    channel: Mutex<Option<Arc<Channel>>>,
 
-    ready_state_tx: mpsc::Sender<isize>,
-    ready_state_rx: mpsc::Receiver<isize>,
+    // 影响能否投递消息的状态发生变化时通知message pump
+    ready_state_notify: Notify,
 
-    // shutdown: Shutdown,
-    client_id: String,
-    client_addr: SocketAddr,
-    // pub hostname: String,
-    sample_rate: i32,
+    // SUB成功之后通知message pump开始投递消息
+    sub_event_tx: Mutex<Option<oneshot::Sender<Arc<Channel>>>>,
+    sub_event_rx: Mutex<Option<oneshot::Receiver<Arc<Channel>>>>,
 
-    identify_event_tx: oneshot::Sender<IdentifyEvent>,
-    identify_event_rx: oneshot::Receiver<IdentifyEvent>,
+    // IDENTIFY成功之后通知message pump使用新的配置
+    identify_event_tx: Mutex<Option<oneshot::Sender<IdentifyEvent>>>,
+    identify_event_rx: Mutex<Option<oneshot::Receiver<IdentifyEvent>>>,
 
-    pub sub_event_tx: oneshot::Sender<Channel>,
-    pub sub_event_rx: oneshot::Receiver<Channel>,
+    client_id: Mutex<String>,
+    client_addr: SocketAddr,
+    hostname: Mutex<String>,
+    sample_rate: AtomicI32,
 
-    tls: bool,
-    snappy: bool,
-    deflate: bool,
+    tls: AtomicBool,
+    snappy: AtomicBool,
+    deflate: AtomicBool,
 
-    // 可重复使用的缓冲区，用于从报文中读取4字节长度
-    // len_buf: [u8; 4], // Vec内部就是数组，这里不需要自己维护一个数组
-    len_slice: Vec<u8>,
+    auth_secret: Mutex<String>,
+    // auth server返回的授权信息，过期之后重新查询
+    auth_state: Mutex<Option<Arc<auth::State>>>,
 
-    auth_secret: String,
-    // auth_state: auth::State,
+    // 连接关闭时取消，通知io_loop和message pump退出
+    exit_token: CancellationToken,
 }
 
 impl ClientV2 {
-    pub fn new(id: i64, stream: TcpStream, addr: SocketAddr, nsqd: Arc<NSQD>) -> Self {
+    pub fn new(id: i64, conn: ClientConn, addr: SocketAddr, nsqd: Arc<NSQD>) -> Self {
         let opts = nsqd.get_opts();
-        let (ready_state_tx, ready_state_rx) = mpsc::channel(1);
+
         let (sub_event_tx, sub_event_rx) = oneshot::channel();
         let (identify_event_tx, identify_event_rx) = oneshot::channel();
 
         let ip = addr.ip();
+        let output_buffer_size = DEFAULT_BUF_SIZE;
 
         Self {
             id,
@@ -108,80 +325,439 @@ impl ClientV2 {
             message_count: AtomicU64::new(0),
             finish_count: AtomicU64::new(0),
             requeue_count: AtomicU64::new(0),
-            pub_counts: HashMap::new(),
-            nsqd: nsqd.clone(),
-            user_agent: None,
-            stream,
-            output_buffer_size: DEFAULT_BUF_SIZE,
-            output_buffer_timeout: opts.output_buffer_timeout,
-            heartbeat_interval: opts.client_timeout / 2,
-            msg_timeout: opts.msg_timeout,
-            state: State::Init,
+            pub_counts: Mutex::new(HashMap::new()),
+            user_agent: Mutex::new(None),
+            writer: AsyncMutex::new(BufWriter::with_capacity(output_buffer_size, conn.clone())),
+            conn,
+            output_buffer_size: AtomicUsize::new(output_buffer_size),
+            output_buffer_timeout: Mutex::new(opts.output_buffer_timeout),
+            heartbeat_interval: Mutex::new(opts.client_timeout / 2),
+            msg_timeout: Mutex::new(opts.msg_timeout),
+            state: Mutex::new(State::Init),
             connect_time: Instant::now(),
-            channel: None,
-            ready_state_tx,
-            ready_state_rx,
-            // shutdown: todo!(),
-            client_id: ip.to_string(),
+            channel: Mutex::new(None),
+            ready_state_notify: Notify::new(),
+            sub_event_tx: Mutex::new(Some(sub_event_tx)),
+            sub_event_rx: Mutex::new(Some(sub_event_rx)),
+            identify_event_tx: Mutex::new(Some(identify_event_tx)),
+            identify_event_rx: Mutex::new(Some(identify_event_rx)),
+            client_id: Mutex::new(ip.to_string()),
             client_addr: addr,
-            sample_rate: 0,
-            identify_event_tx,
-            identify_event_rx,
-            sub_event_tx,
-            sub_event_rx,
-            tls: false,
-            snappy: false,
-            deflate: false,
-            len_slice: Vec::with_capacity(4),
-            auth_secret: "to do".to_owned(),
+            hostname: Mutex::new(ip.to_string()),
+            sample_rate: AtomicI32::new(0),
+            tls: AtomicBool::new(false),
+            snappy: AtomicBool::new(false),
+            deflate: AtomicBool::new(false),
+            auth_secret: Mutex::new(String::new()),
+            auth_state: Mutex::new(None),
+            exit_token: CancellationToken::new(),
+            nsqd,
         }
     }
 
     pub fn addr(&self) -> String {
         self.client_addr.to_string()
     }
+
+    pub fn state(&self) -> State {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn set_state(&self, state: State) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    pub fn channel(&self) -> Option<Arc<Channel>> {
+        self.channel.lock().unwrap().clone()
+    }
+
+    pub fn set_channel(&self, channel: Arc<Channel>) {
+        *self.channel.lock().unwrap() = Some(channel);
+    }
+
+    pub fn sub_event(&self, channel: Arc<Channel>) {
+        if let Some(tx) = self.sub_event_tx.lock().unwrap().take() {
+            let _ = tx.send(channel);
+        }
+    }
+
+    // 只能被message pump获取一次
+    pub fn take_sub_event_rx(&self) -> oneshot::Receiver<Arc<Channel>> {
+        self.sub_event_rx.lock().unwrap().take().unwrap()
+    }
+
+    pub fn try_update_ready_state(&self) {
+        self.ready_state_notify.notify_one();
+    }
+
+    pub async fn ready_state_changed(&self) {
+        self.ready_state_notify.notified().await
+    }
+
+    // 只能被message pump获取一次
+    pub fn take_identify_event_rx(&self) -> oneshot::Receiver<IdentifyEvent> {
+        self.identify_event_rx.lock().unwrap().take().unwrap()
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        *self.heartbeat_interval.lock().unwrap()
+    }
+
+    // 关闭心跳时不检查超时
+    pub fn read_timeout(&self) -> Option<Duration> {
+        let heartbeat_interval = self.heartbeat_interval();
+        if heartbeat_interval.is_zero() {
+            return None;
+        }
+        Some(heartbeat_interval * self.nsqd.get_opts().max_missed_heartbeats)
+    }
+
+    pub fn output_buffer_size(&self) -> usize {
+        self.output_buffer_size.load(Ordering::SeqCst)
+    }
+
+    pub fn sample_rate(&self) -> i32 {
+        self.sample_rate.load(Ordering::SeqCst)
+    }
+
+    pub fn output_buffer_timeout(&self) -> Duration {
+        *self.output_buffer_timeout.lock().unwrap()
+    }
+
+    pub fn msg_timeout(&self) -> Duration {
+        *self.msg_timeout.lock().unwrap()
+    }
+
+    // 根据IDENTIFY的内容更新客户端的配置，超出服务端限制的值调整到限制范围内
+    pub async fn identify(&self, data: &IdentifyData) -> std::result::Result<(), String> {
+        *self.client_id.lock().unwrap() = data.client_id.clone();
+        *self.hostname.lock().unwrap() = data.hostname.clone();
+        *self.user_agent.lock().unwrap() = Some(data.user_agent.clone());
+
+        self.set_heartbeat_interval(data.heartbeat_interval);
+        self.set_output_buffer(data.output_buffer_size, data.output_buffer_timeout)
+            .await?;
+        self.set_sample_rate(data.sample_rate);
+        self.set_msg_timeout(data.msg_timeout);
+
+        if let Some(tx) = self.identify_event_tx.lock().unwrap().take() {
+            let _ = tx.send(IdentifyEvent {
+                output_buffer_timeout: self.output_buffer_timeout(),
+                heartbeat_interval: self.heartbeat_interval(),
+                sample_rate: self.sample_rate(),
+                msg_timeout: self.msg_timeout(),
+            });
+        }
+        Ok(())
+    }
+
+    // -1表示关闭心跳，0表示使用默认值，其他值限制在[1s, max_heartbeat_interval]
+    fn set_heartbeat_interval(&self, interval: i64) {
+        let max = self.nsqd.get_opts().max_heartbeat_interval;
+        let interval = match interval {
+            -1 => Duration::ZERO,
+            0 => return,
+            v => clamp_millis(v, Duration::from_secs(1), max),
+        };
+        *self.heartbeat_interval.lock().unwrap() = interval;
+    }
+
+    // size为-1时相当于不使用缓冲区，每次写入都直接发送。timeout为-1时每次写入都立即flush
+    async fn set_output_buffer(&self, size: i64, timeout: i64) -> std::result::Result<(), String> {
+        let opts = self.nsqd.get_opts();
+        let mut writer = self.writer.lock().await;
+
+        let mut output_buffer_timeout = match timeout {
+            -1 => Duration::ZERO,
+            0 => self.output_buffer_timeout(),
+            v => clamp_millis(
+                v,
+                opts.min_output_buffer_timeout,
+                opts.max_output_buffer_timeout,
+            ),
+        };
+
+        let output_buffer_size = match size {
+            -1 => {
+                output_buffer_timeout = Duration::ZERO;
+                1
+            }
+            0 => self.output_buffer_size(),
+            v => v
+                .max(MIN_OUTPUT_BUFFER_SIZE)
+                .min(opts.max_output_buffer_size) as usize,
+        };
+
+        *self.output_buffer_timeout.lock().unwrap() = output_buffer_timeout;
+        if size != 0 {
+            writer.flush().await.map_err(|e| e.to_string())?;
+            *writer = BufWriter::with_capacity(output_buffer_size, self.conn.clone());
+            self.output_buffer_size
+                .store(output_buffer_size, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    // 取值范围[0,100)，0表示不采样
+    fn set_sample_rate(&self, sample_rate: i32) {
+        self.sample_rate
+            .store(sample_rate.clamp(0, 99), Ordering::SeqCst);
+    }
+
+    // 0表示使用默认值，其他值限制在[1s, max_msg_timeout]
+    fn set_msg_timeout(&self, msg_timeout: i64) {
+        if msg_timeout == 0 {
+            return;
+        }
+        let max = self.nsqd.get_opts().max_msg_timeout;
+        *self.msg_timeout.lock().unwrap() = clamp_millis(msg_timeout, Duration::from_secs(1), max);
+    }
+
+    pub fn set_ready_count(&self, count: i64) {
+        let old = self.ready_count.swap(count, Ordering::SeqCst);
+        if old != count {
+            self.try_update_ready_state();
+        }
+    }
+
+    // in-flight消息的数量达到RDY之后暂停投递，等客户端FIN/REQ或者消息超时之后再继续
+    pub fn is_ready_for_messages(&self) -> bool {
+        let ready_count = self.ready_count.load(Ordering::SeqCst);
+        ready_count > 0 && self.in_flight_count.load(Ordering::SeqCst) < ready_count
+    }
+
+    // 将缓冲区中的数据发送给客户端
+    pub async fn flush(&self) -> io::Result<()> {
+        self.writer.lock().await.flush().await
+    }
+
+    pub fn exit(&self) {
+        self.exit_token.cancel();
+    }
+
+    pub async fn exited(&self) {
+        self.exit_token.cancelled().await
+    }
+
+    // 收到CLS命令，不再接收新消息，等待in-flight消息处理完
+    pub fn start_close(&self) {
+        self.set_ready_count(0);
+        self.set_state(State::Closing);
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls.load(Ordering::SeqCst)
+    }
+
+    // 升级之前缓冲区中的数据必须已经全部发送
+    pub async fn upgrade_tls(&self, acceptor: &TlsAcceptor) -> io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer.flush().await?;
+        self.conn.upgrade_tls(acceptor).await?;
+        drop(writer);
+
+        self.tls.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub async fn upgrade_deflate(&self, level: u32) -> io::Result<()> {
+        self.upgrade_codec(Encoder::deflate(level), Decoder::deflate())
+            .await?;
+        self.deflate.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub async fn upgrade_snappy(&self) -> io::Result<()> {
+        self.upgrade_codec(Encoder::snappy(), Decoder::snappy())
+            .await?;
+        self.snappy.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // 和TLS一样，开启压缩之前缓冲区中的数据要以未压缩的形式发送出去
+    async fn upgrade_codec(&self, encoder: Encoder, decoder: Decoder) -> io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer.flush().await?;
+        self.conn.upgrade_codec(encoder, decoder)
+    }
+
+    pub async fn auth(&self, secret: String) -> Result<()> {
+        *self.auth_secret.lock().unwrap() = secret;
+        self.query_authd().await
+    }
+
+    async fn query_authd(&self) -> Result<()> {
+        let opts = self.nsqd.get_opts();
+        let secret = self.auth_secret.lock().unwrap().clone();
+        let query = Query {
+            remote_ip: self.client_addr.ip().to_string(),
+            tls: self.is_tls(),
+            // TODO: 从客户端证书中获取CN
+            common_name: String::new(),
+            secret: &secret,
+        };
+
+        let state = query_any_authd(
+            self.nsqd.http_client(),
+            &opts.auth_http_addrs,
+            &opts.auth_http_request_method,
+            &query,
+        )
+        .await?;
+        *self.auth_state.lock().unwrap() = Some(Arc::new(state));
+        Ok(())
+    }
+
+    pub fn auth_state(&self) -> Option<Arc<auth::State>> {
+        self.auth_state.lock().unwrap().clone()
+    }
+
+    pub fn has_authorizations(&self) -> bool {
+        self.auth_state()
+            .is_some_and(|state| !state.authorizations.is_empty())
+    }
+
+    pub async fn is_authorized(&self, topic: &str, channel: &str) -> Result<bool> {
+        let Some(mut state) = self.auth_state() else {
+            return Ok(false);
+        };
+        if state.is_expired() {
+            self.query_authd().await?;
+            state = self.auth_state().unwrap();
+        }
+        Ok(state.is_allowed(topic, channel))
+    }
+
+    pub fn is_producer(&self) -> bool {
+        !self.pub_counts.lock().unwrap().is_empty()
+    }
+
+    pub fn stats(&self) -> ClientStats {
+        let mut pub_counts: Vec<_> = self
+            .pub_counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(topic, count)| PubCount {
+                topic: topic.clone(),
+                count: *count,
+            })
+            .collect();
+        pub_counts.sort_by(|a, b| a.topic.cmp(&b.topic));
+
+        let auth_state = self.auth_state();
+
+        let connected = self.connect_time.elapsed();
+        let connect_ts = (unix_nanos() - connected.as_nanos() as i64) / 1_000_000_000;
+
+        ClientStats {
+            client_id: self.client_id.lock().unwrap().clone(),
+            hostname: self.hostname.lock().unwrap().clone(),
+            version: "V2",
+            remote_address: self.addr(),
+            state: self.state() as i32,
+            ready_count: self.ready_count.load(Ordering::SeqCst),
+            in_flight_count: self.in_flight_count.load(Ordering::SeqCst),
+            message_count: self.message_count.load(Ordering::SeqCst),
+            finish_count: self.finish_count.load(Ordering::SeqCst),
+            requeue_count: self.requeue_count.load(Ordering::SeqCst),
+            connect_ts,
+            sample_rate: self.sample_rate(),
+            deflate: self.deflate.load(Ordering::SeqCst),
+            snappy: self.snappy.load(Ordering::SeqCst),
+            user_agent: self.user_agent.lock().unwrap().clone().unwrap_or_default(),
+            tls: self.is_tls(),
+            authed: auth_state.is_some(),
+            auth_identity: auth_state
+                .as_ref()
+                .map(|s| s.identity.clone())
+                .unwrap_or_default(),
+            auth_identity_url: auth_state
+                .as_ref()
+                .map(|s| s.identity_url.clone())
+                .unwrap_or_default(),
+            pub_counts,
+            connected_secs: connected.as_secs(),
+        }
+    }
 }
 
 impl ClientV2 {
-    pub fn finished_msg(&mut self) {
+    pub fn finished_msg(&self) {
         self.finish_count.fetch_add(1, Ordering::SeqCst);
         self.in_flight_count.fetch_sub(1, Ordering::SeqCst);
-        // TODO: tryUpdateReadyState
+        self.try_update_ready_state();
     }
 
-    pub fn published_msg(&mut self, topic: &str, count: u64) {
-        self.pub_counts
-            .get_mut(topic)
+    pub fn published_msg(&self, topic: &str, count: u64) {
+        *self
+            .pub_counts
+            .lock()
             .unwrap()
-            .fetch_add(count, Ordering::SeqCst);
+            .entry(topic.to_owned())
+            .or_default() += count;
     }
 
-    pub fn requeue_msg(&mut self) {
+    pub fn requeue_msg(&self) {
         self.requeue_count.fetch_add(1, Ordering::SeqCst);
         self.in_flight_count.fetch_sub(1, Ordering::SeqCst);
-        // TODO: tryUpdateReadyState
+        self.try_update_ready_state();
     }
 
-    pub fn sending_msg(&mut self) {
+    pub fn sending_msg(&self) {
         self.in_flight_count.fetch_add(1, Ordering::SeqCst);
         self.message_count.fetch_add(1, Ordering::SeqCst);
     }
 
-    pub fn timed_out_msg(&mut self) {
+    pub fn timed_out_msg(&self) {
         self.in_flight_count.fetch_sub(1, Ordering::SeqCst);
-        // TODO: tryUpdateReadyState
+        self.try_update_ready_state();
     }
 }
 
 impl Client for ClientV2 {
-    fn close() {
-        todo!()
+    fn close(&self) {
+        self.exit();
+    }
+
+    fn pause(&self) {
+        self.try_update_ready_state();
+    }
+
+    fn unpause(&self) {
+        self.try_update_ready_state();
     }
+
+    // channel被清空之后in-flight消息也被丢弃了
+    fn empty(&self) {
+        self.in_flight_count.store(0, Ordering::SeqCst);
+        self.try_update_ready_state();
+    }
+}
+
+// IDENTIFY命令中客户端发送的json数据
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(super) struct IdentifyData {
+    pub client_id: String,
+    pub hostname: String,
+    pub user_agent: String,
+    pub feature_negotiation: bool,
+    // 以下时间的单位都是毫秒
+    pub heartbeat_interval: i64,
+    pub output_buffer_size: i64,
+    pub output_buffer_timeout: i64,
+    pub tls_v1: bool,
+    pub deflate: bool,
+    pub deflate_level: u32,
+    pub snappy: bool,
+    pub sample_rate: i32,
+    pub msg_timeout: i64,
 }
 
-struct IdentifyEvent {
-    output_buffer_timeout: Duration,
-    heartbeat_interval: Duration,
-    sample_rate: i32,
-    msg_timeout: Duration,
+pub(super) struct IdentifyEvent {
+    pub output_buffer_timeout: Duration,
+    pub heartbeat_interval: Duration,
+    pub sample_rate: i32,
+    pub msg_timeout: Duration,
 }
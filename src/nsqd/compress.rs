@@ -0,0 +1,279 @@
+use std::io::{self, Write};
+
+use flate2::{
+    write::{DeflateDecoder, DeflateEncoder},
+    Compression,
+};
+use snap::{raw, write::FrameEncoder};
+
+const SNAPPY_STREAM_IDENTIFIER: &[u8] = b"sNaPpY";
+const SNAPPY_CHUNK_COMPRESSED: u8 = 0x00;
+const SNAPPY_CHUNK_UNCOMPRESSED: u8 = 0x01;
+const SNAPPY_CHUNK_STREAM_IDENTIFIER: u8 = 0xff;
+// 解压之后每个chunk最多64KB
+const SNAPPY_MAX_BLOCK_SIZE: usize = 65536;
+
+// 压缩之后的数据写入内部的Vec，由调用方取出后发送
+pub(super) enum Encoder {
+    Deflate(DeflateEncoder<Vec<u8>>),
+    Snappy(Box<FrameEncoder<Vec<u8>>>),
+}
+
+impl Encoder {
+    pub fn deflate(level: u32) -> Self {
+        Self::Deflate(DeflateEncoder::new(Vec::new(), Compression::new(level)))
+    }
+
+    pub fn snappy() -> Self {
+        Self::Snappy(Box::new(FrameEncoder::new(Vec::new())))
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Deflate(w) => w.write_all(buf),
+            Self::Snappy(w) => w.write_all(buf),
+        }
+    }
+
+    // deflate执行sync flush，snappy将缓冲的数据编码为一个完整的chunk
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Deflate(w) => w.flush(),
+            Self::Snappy(w) => w.flush(),
+        }
+    }
+
+    // 将已经压缩好的数据追加到out中
+    pub fn take_output(&mut self, out: &mut Vec<u8>) {
+        let buf = match self {
+            Self::Deflate(w) => w.get_mut(),
+            Self::Snappy(w) => w.get_mut(),
+        };
+        out.append(buf);
+    }
+}
+
+// 输入压缩数据，解压之后的数据保存在内部的Vec中
+pub(super) enum Decoder {
+    Deflate(DeflateDecoder<Vec<u8>>),
+    Snappy(SnappyDecoder),
+}
+
+impl Decoder {
+    pub fn deflate() -> Self {
+        Self::Deflate(DeflateDecoder::new(Vec::new()))
+    }
+
+    pub fn snappy() -> Self {
+        Self::Snappy(SnappyDecoder::default())
+    }
+
+    pub fn decode(&mut self, input: &[u8]) -> io::Result<()> {
+        match self {
+            // 解压出的数据可能还留在内部缓冲区中，需要flush到output
+            Self::Deflate(d) => {
+                d.write_all(input)?;
+                d.flush()
+            }
+            Self::Snappy(d) => d.decode(input),
+        }
+    }
+
+    pub fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            Self::Deflate(d) => d.get_mut(),
+            Self::Snappy(d) => &mut d.output,
+        }
+    }
+}
+
+// snappy framing格式的增量解码，数据可能在任意位置被截断，
+// 不完整的chunk先保存起来等待后续数据
+#[derive(Default)]
+pub(super) struct SnappyDecoder {
+    input: Vec<u8>,
+    output: Vec<u8>,
+    raw: raw::Decoder,
+    identified: bool,
+}
+
+impl SnappyDecoder {
+    fn decode(&mut self, input: &[u8]) -> io::Result<()> {
+        self.input.extend_from_slice(input);
+
+        let mut pos = 0;
+        while self.input.len() - pos >= 4 {
+            let header = &self.input[pos..pos + 4];
+            let chunk_type = header[0];
+            let len = u32::from_le_bytes([header[1], header[2], header[3], 0]) as usize;
+            if len > raw::max_compress_len(SNAPPY_MAX_BLOCK_SIZE) + 4 {
+                return Err(invalid_data("snappy chunk too large"));
+            }
+            if self.input.len() - pos - 4 < len {
+                break;
+            }
+
+            let chunk = &self.input[pos + 4..pos + 4 + len];
+            pos += 4 + len;
+
+            if !self.identified && chunk_type != SNAPPY_CHUNK_STREAM_IDENTIFIER {
+                return Err(invalid_data("snappy stream identifier not found"));
+            }
+
+            match chunk_type {
+                SNAPPY_CHUNK_STREAM_IDENTIFIER => {
+                    if chunk != SNAPPY_STREAM_IDENTIFIER {
+                        return Err(invalid_data("invalid snappy stream identifier"));
+                    }
+                    self.identified = true;
+                }
+                // 前4个字节是校验和，TCP已经保证了数据完整性，这里不再校验
+                SNAPPY_CHUNK_COMPRESSED => {
+                    if len < 4 {
+                        return Err(invalid_data("snappy chunk too short"));
+                    }
+                    let data = &chunk[4..];
+                    let n = raw::decompress_len(data).map_err(io::Error::other)?;
+                    if n > SNAPPY_MAX_BLOCK_SIZE {
+                        return Err(invalid_data("snappy chunk too large"));
+                    }
+                    let start = self.output.len();
+                    self.output.resize(start + n, 0);
+                    self.raw
+                        .decompress(data, &mut self.output[start..])
+                        .map_err(io::Error::other)?;
+                }
+                SNAPPY_CHUNK_UNCOMPRESSED => {
+                    if len < 4 {
+                        return Err(invalid_data("snappy chunk too short"));
+                    }
+                    self.output.extend_from_slice(&chunk[4..]);
+                }
+                // 0x80-0xfe为可以跳过的chunk
+                0x80..=0xfe => {}
+                _ => return Err(invalid_data("unsupported snappy chunk type")),
+            }
+        }
+
+        self.input.drain(..pos);
+        Ok(())
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 伪随机数据压缩不了，snappy会输出未压缩的chunk
+    fn test_data() -> Vec<u8> {
+        let mut data = b"hello nsq ".repeat(10000);
+        let mut x: u32 = 1;
+        for _ in 0..SNAPPY_MAX_BLOCK_SIZE {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            data.push((x >> 16) as u8);
+        }
+        data
+    }
+
+    fn encode(mut encoder: Encoder, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        // 分多次写入并flush，输出多个独立的块
+        for part in data.chunks(30000) {
+            encoder.write(part).unwrap();
+            encoder.flush().unwrap();
+            encoder.take_output(&mut out);
+        }
+        out
+    }
+
+    // 按照不同的长度切分输入，每次解码之后取出已经解压的数据
+    fn decode_split(mut decoder: Decoder, input: &[u8], split: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        for part in input.chunks(split) {
+            decoder.decode(part).unwrap();
+            out.append(decoder.output());
+        }
+        out
+    }
+
+    #[test]
+    fn snappy_round_trip() {
+        let data = test_data();
+        let encoded = encode(Encoder::snappy(), &data);
+        assert!(encoded.starts_with(b"\xff\x06\x00\x00sNaPpY"));
+        let mut chunk_types = Vec::new();
+        let mut pos = 0;
+        while pos < encoded.len() {
+            chunk_types.push(encoded[pos]);
+            let len = &encoded[pos + 1..pos + 4];
+            pos += 4 + u32::from_le_bytes([len[0], len[1], len[2], 0]) as usize;
+        }
+        assert!(chunk_types.contains(&SNAPPY_CHUNK_COMPRESSED));
+        assert!(chunk_types.contains(&SNAPPY_CHUNK_UNCOMPRESSED));
+        for split in [1, 3, 4, 5, 1000, encoded.len()] {
+            let decoded = decode_split(Decoder::snappy(), &encoded, split);
+            assert!(decoded == data, "split {split}");
+        }
+    }
+
+    #[test]
+    fn snappy_chunks() {
+        // 可以跳过的chunk直接忽略
+        let mut decoder = Decoder::snappy();
+        decoder.decode(b"\xff\x06\x00\x00sNaPpY").unwrap();
+        decoder.decode(b"\x80\x02\x00\x00xx").unwrap();
+        decoder
+            .decode(b"\x01\x06\x00\x00\x00\x00\x00\x00ab")
+            .unwrap();
+        assert_eq!(decoder.output(), b"ab");
+
+        let err = |input: &[u8]| {
+            let mut decoder = Decoder::snappy();
+            decoder.decode(input).unwrap_err().to_string()
+        };
+        assert_eq!(
+            err(b"\x01\x06\x00\x00\x00\x00\x00\x00ab"),
+            "snappy stream identifier not found"
+        );
+        assert_eq!(
+            err(b"\xff\x06\x00\x00sNaPpZ"),
+            "invalid snappy stream identifier"
+        );
+        assert_eq!(
+            err(b"\xff\x06\x00\x00sNaPpY\x02\x00\x00\x00"),
+            "unsupported snappy chunk type"
+        );
+        assert_eq!(
+            err(b"\xff\x06\x00\x00sNaPpY\x01\x02\x00\x00ab"),
+            "snappy chunk too short"
+        );
+        assert_eq!(err(b"\x00\xff\xff\xff"), "snappy chunk too large");
+    }
+
+    #[test]
+    fn deflate_round_trip() {
+        let data = test_data();
+        let encoded = encode(Encoder::deflate(6), &data);
+        for split in [1, 7, 1000, encoded.len()] {
+            let decoded = decode_split(Decoder::deflate(), &encoded, split);
+            assert!(decoded == data, "split {split}");
+        }
+
+        // 每次flush之后之前写入的数据都可以完整解压
+        let mut encoder = Encoder::deflate(1);
+        let mut decoder = Decoder::deflate();
+        for msg in [&b"first"[..], b"second"] {
+            let mut out = Vec::new();
+            encoder.write(msg).unwrap();
+            encoder.flush().unwrap();
+            encoder.take_output(&mut out);
+            decoder.decode(&out).unwrap();
+            assert_eq!(decoder.output(), msg);
+            decoder.output().clear();
+        }
+    }
+}
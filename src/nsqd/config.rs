@@ -0,0 +1,651 @@
+use std::{fs, path::PathBuf, str::FromStr, time::Duration};
+
+use clap::Parser;
+use rustls::ProtocolVersion;
+use serde::{de, Deserialize, Deserializer};
+use tracing::{warn, Level};
+
+use super::options::{Options, TLS_NOT_REQUIRED, TLS_REQUIRED, TLS_REQUIRED_EXCEPT_HTTP};
+use crate::{
+    common::{normalize_args, parse_duration, Result},
+    errors::NsqError,
+    http_api::parse_bool,
+};
+
+// 命令行参数和golang版本nsqd的flag保持一致，配置文件中的key和golang版本的cfg保持一致。
+// 每个参数也可以通过NSQD_*环境变量指定，优先级：命令行参数 > 环境变量 > 配置文件 > 默认值
+#[derive(Parser, Deserialize, Default)]
+#[command(
+    name = "nsqd",
+    version,
+    about = "A realtime distributed messaging platform",
+    allow_negative_numbers = true
+)]
+#[serde(default)]
+pub struct Config {
+    /// path to config file
+    #[arg(long, env = "NSQD_CONFIG")]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+
+    /// set log level: debug, info, warn, error, or fatal
+    #[arg(long, env = "NSQD_LOG_LEVEL")]
+    log_level: Option<LogLevel>,
+
+    /// unique part for message IDs, (int) in range [0,1024) (default is hash of hostname)
+    #[arg(long = "node-id", env = "NSQD_NODE_ID")]
+    id: Option<u16>,
+
+    /// <addr>:<port> to listen on for TCP clients
+    #[arg(long, env = "NSQD_TCP_ADDRESS")]
+    tcp_address: Option<String>,
+    /// <addr>:<port> to listen on for HTTP clients
+    #[arg(long, env = "NSQD_HTTP_ADDRESS")]
+    http_address: Option<String>,
+    /// <addr>:<port> to listen on for HTTPS clients
+    #[arg(long, env = "NSQD_HTTPS_ADDRESS")]
+    https_address: Option<String>,
+    /// address that will be registered with lookupd (defaults to the OS hostname)
+    #[arg(long, env = "NSQD_BROADCAST_ADDRESS")]
+    broadcast_address: Option<String>,
+    /// TCP port that will be registered with lookupd (defaults to the TCP port that this nsqd is listening on)
+    #[arg(long, env = "NSQD_BROADCAST_TCP_PORT")]
+    broadcast_tcp_port: Option<u16>,
+    /// HTTP port that will be registered with lookupd (defaults to the HTTP port that this nsqd is listening on)
+    #[arg(long, env = "NSQD_BROADCAST_HTTP_PORT")]
+    broadcast_http_port: Option<u16>,
+    /// lookupd TCP address (may be given multiple times)
+    #[arg(
+        long = "lookupd-tcp-address",
+        env = "NSQD_LOOKUPD_TCP_ADDRESS",
+        value_delimiter = ','
+    )]
+    nsqlookupd_tcp_addresses: Option<Vec<String>>,
+    /// <addr>:<port> or a full url to query auth server (may be given multiple times)
+    #[arg(
+        long = "auth-http-address",
+        env = "NSQD_AUTH_HTTP_ADDRESS",
+        value_delimiter = ','
+    )]
+    auth_http_addresses: Option<Vec<String>>,
+    /// HTTP method to use for auth server requests: get or post
+    #[arg(long, env = "NSQD_AUTH_HTTP_REQUEST_METHOD")]
+    auth_http_request_method: Option<String>,
+    /// timeout for HTTP connect
+    #[arg(long, env = "NSQD_HTTP_CLIENT_CONNECT_TIMEOUT")]
+    http_client_connect_timeout: Option<GoDuration>,
+    /// timeout for HTTP request
+    #[arg(long, env = "NSQD_HTTP_CLIENT_REQUEST_TIMEOUT")]
+    http_client_request_timeout: Option<GoDuration>,
+
+    /// path to store disk-backed messages
+    #[arg(long, env = "NSQD_DATA_PATH")]
+    data_path: Option<PathBuf>,
+    /// number of messages to keep in memory (per topic/channel)
+    #[arg(long, env = "NSQD_MEM_QUEUE_SIZE")]
+    mem_queue_size: Option<u32>,
+    /// number of bytes per diskqueue file before rolling
+    #[arg(long, env = "NSQD_MAX_BYTES_PER_FILE")]
+    max_bytes_per_file: Option<u32>,
+    /// number of messages per diskqueue fsync
+    #[arg(long, env = "NSQD_SYNC_EVERY")]
+    sync_every: Option<u32>,
+    /// duration of time per diskqueue fsync
+    #[arg(long, env = "NSQD_SYNC_TIMEOUT")]
+    sync_timeout: Option<GoDuration>,
+
+    /// max concurrency for checking in-flight and deferred message timeouts
+    #[arg(long, env = "NSQD_QUEUE_SCAN_WORKER_POOL_MAX")]
+    queue_scan_worker_pool_max: Option<usize>,
+    /// number of channels to check per cycle (every 100ms) for in-flight and deferred timeouts
+    #[arg(long, env = "NSQD_QUEUE_SCAN_SELECTION_COUNT")]
+    queue_scan_selection_count: Option<usize>,
+
+    /// default duration to wait before auto-requeing a message
+    #[arg(long, env = "NSQD_MSG_TIMEOUT")]
+    msg_timeout: Option<GoDuration>,
+    /// maximum duration before a message will timeout
+    #[arg(long, env = "NSQD_MAX_MSG_TIMEOUT")]
+    max_msg_timeout: Option<GoDuration>,
+    /// maximum size of a single message in bytes
+    #[arg(long, env = "NSQD_MAX_MSG_SIZE")]
+    max_msg_size: Option<u32>,
+    /// maximum requeuing timeout for a message
+    #[arg(long, env = "NSQD_MAX_REQ_TIMEOUT")]
+    max_req_timeout: Option<GoDuration>,
+    /// maximum size of a single command body
+    #[arg(long, env = "NSQD_MAX_BODY_SIZE")]
+    max_body_size: Option<u32>,
+
+    /// default timeout for a client connection, heartbeats are sent every half of it
+    #[arg(long, env = "NSQD_CLIENT_TIMEOUT")]
+    client_timeout: Option<GoDuration>,
+    /// number of heartbeat intervals without any command before a client is disconnected
+    #[arg(long, env = "NSQD_MAX_MISSED_HEARTBEATS")]
+    max_missed_heartbeats: Option<u32>,
+    /// maximum client configurable duration of time between client heartbeats
+    #[arg(long, env = "NSQD_MAX_HEARTBEAT_INTERVAL")]
+    max_heartbeat_interval: Option<GoDuration>,
+    /// maximum RDY count for a client
+    #[arg(long, env = "NSQD_MAX_RDY_COUNT")]
+    max_rdy_count: Option<i64>,
+    /// maximum client configurable size (in bytes) for a client output buffer
+    #[arg(long, env = "NSQD_MAX_OUTPUT_BUFFER_SIZE")]
+    max_output_buffer_size: Option<i64>,
+    /// maximum client configurable duration of time between flushing to a client
+    #[arg(long, env = "NSQD_MAX_OUTPUT_BUFFER_TIMEOUT")]
+    max_output_buffer_timeout: Option<GoDuration>,
+    /// minimum client configurable duration of time between flushing to a client
+    #[arg(long, env = "NSQD_MIN_OUTPUT_BUFFER_TIMEOUT")]
+    min_output_buffer_timeout: Option<GoDuration>,
+    /// default duration of time between flushing data to clients
+    #[arg(long, env = "NSQD_OUTPUT_BUFFER_TIMEOUT")]
+    output_buffer_timeout: Option<GoDuration>,
+    /// maximum channel consumer connection count per nsqd instance (default 0, i.e., unlimited)
+    #[arg(long, env = "NSQD_MAX_CHANNEL_CONSUMERS")]
+    max_channel_consumers: Option<isize>,
+
+    /// enable snappy feature negotiation (client compression)
+    #[arg(long, env = "NSQD_SNAPPY", num_args = 0..=1, require_equals = true,
+          default_missing_value = "true", value_parser = parse_bool_flag)]
+    snappy: Option<bool>,
+    /// enable deflate feature negotiation (client compression)
+    #[arg(long, env = "NSQD_DEFLATE", num_args = 0..=1, require_equals = true,
+          default_missing_value = "true", value_parser = parse_bool_flag)]
+    deflate: Option<bool>,
+    /// max deflate compression level a client can negotiate (> values == > nsqd CPU usage)
+    #[arg(long, env = "NSQD_MAX_DEFLATE_LEVEL")]
+    max_deflate_level: Option<u32>,
+
+    /// path to certificate file
+    #[arg(long, env = "NSQD_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// path to key file
+    #[arg(long, env = "NSQD_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+    /// client certificate auth policy ('require' or 'require-verify')
+    #[arg(long, env = "NSQD_TLS_CLIENT_AUTH_POLICY")]
+    tls_client_auth_policy: Option<String>,
+    /// path to certificate authority file
+    #[arg(long, env = "NSQD_TLS_ROOT_CA_FILE")]
+    tls_root_ca_file: Option<PathBuf>,
+    /// require TLS for client connections (true, false, tcp-https)
+    #[arg(long, env = "NSQD_TLS_REQUIRED", num_args = 0..=1, require_equals = true,
+          default_missing_value = "true")]
+    tls_required: Option<TlsRequired>,
+    /// minimum SSL/TLS version acceptable ('ssl3.0', 'tls1.0', 'tls1.1', 'tls1.2' or 'tls1.3')
+    #[arg(long, env = "NSQD_TLS_MIN_VERSION")]
+    tls_min_version: Option<TlsVersion>,
+
+    /// UDP <addr>:<port> of a statsd daemon for pushing stats
+    #[arg(long, env = "NSQD_STATSD_ADDRESS")]
+    statsd_address: Option<String>,
+    /// prefix used for keys sent to statsd (%s for host replacement)
+    #[arg(long, env = "NSQD_STATSD_PREFIX")]
+    statsd_prefix: Option<String>,
+    /// duration between pushing to statsd
+    #[arg(long, env = "NSQD_STATSD_INTERVAL")]
+    statsd_interval: Option<GoDuration>,
+    /// toggle sending memory and GC stats to statsd
+    #[arg(long, env = "NSQD_STATSD_MEM_STATS", num_args = 0..=1, require_equals = true,
+          default_missing_value = "true", value_parser = parse_bool_flag)]
+    statsd_mem_stats: Option<bool>,
+    /// the size in bytes of statsd UDP packets
+    #[arg(long, env = "NSQD_STATSD_UDP_PACKET_SIZE")]
+    statsd_udp_packet_size: Option<usize>,
+    /// don't emit stats for ephemeral topics and channels
+    #[arg(long, env = "NSQD_STATSD_EXCLUDE_EPHEMERAL", num_args = 0..=1, require_equals = true,
+          default_missing_value = "true", value_parser = parse_bool_flag)]
+    statsd_exclude_ephemeral: Option<bool>,
+
+    // 以下参数只是为了兼容golang版本的启动参数，设置之后会被忽略
+    /// log message prefix (ignored)
+    #[arg(long, env = "NSQD_LOG_PREFIX")]
+    log_prefix: Option<String>,
+    /// [deprecated] log verbose output (ignored)
+    #[arg(long, env = "NSQD_VERBOSE", num_args = 0..=1, require_equals = true,
+          default_missing_value = "true", value_parser = parse_bool_flag)]
+    verbose: Option<bool>,
+    /// message processing time percentiles (as float (0, 1.0]) to track (ignored)
+    #[arg(
+        long,
+        env = "NSQD_E2E_PROCESSING_LATENCY_PERCENTILE",
+        value_delimiter = ','
+    )]
+    e2e_processing_latency_percentile: Option<Vec<f64>>,
+    /// calculate end to end latency quantiles for this duration of time (ignored)
+    #[arg(long, env = "NSQD_E2E_PROCESSING_LATENCY_WINDOW_TIME")]
+    e2e_processing_latency_window_time: Option<GoDuration>,
+}
+
+impl Config {
+    pub fn from_args() -> Self {
+        Self::parse_from(normalize_args::<Self>(std::env::args_os()))
+    }
+
+    fn from_file(path: &PathBuf) -> Result<Self> {
+        let data = fs::read_to_string(path).map_err(|e| {
+            NsqError::InvalidOptions(format!(
+                "failed to load config file {} - {e}",
+                path.display()
+            ))
+        })?;
+        toml::from_str(&data).map_err(|e| {
+            NsqError::InvalidOptions(format!(
+                "failed to parse config file {} - {e}",
+                path.display()
+            ))
+        })
+    }
+
+    // 合并命令行参数、环境变量和配置文件，生成校验过的Options
+    pub fn into_options(self) -> Result<Options> {
+        let file = match &self.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        let mut opts = Options::new();
+        macro_rules! set {
+            ($($field:ident => $target:ident),* $(,)?) => {
+                $(
+                    if let Some(v) = self.$field.or(file.$field) {
+                        opts.$target = v.into();
+                    }
+                )*
+            };
+        }
+        set!(
+            log_level => log_level,
+            id => id,
+            tcp_address => tcp_addr,
+            http_address => http_addr,
+            https_address => https_addr,
+            broadcast_address => broadcast_addr,
+            broadcast_tcp_port => broadcast_tcp_port,
+            broadcast_http_port => broadcast_http_port,
+            nsqlookupd_tcp_addresses => nsq_lookup_tcp_addrs,
+            auth_http_addresses => auth_http_addrs,
+            auth_http_request_method => auth_http_request_method,
+            http_client_connect_timeout => http_client_connect_timeout,
+            http_client_request_timeout => http_client_request_timeout,
+            data_path => data_path,
+            mem_queue_size => mem_queue_size,
+            max_bytes_per_file => max_bytes_per_file,
+            sync_every => sync_every,
+            sync_timeout => sync_timeout,
+            queue_scan_worker_pool_max => queue_scan_worker_pool_max,
+            queue_scan_selection_count => queue_scan_selection_count,
+            msg_timeout => msg_timeout,
+            max_msg_timeout => max_msg_timeout,
+            max_msg_size => max_msg_size,
+            max_req_timeout => max_req_timeout,
+            max_body_size => max_body_size,
+            client_timeout => client_timeout,
+            max_missed_heartbeats => max_missed_heartbeats,
+            max_heartbeat_interval => max_heartbeat_interval,
+            max_rdy_count => max_rdy_count,
+            max_output_buffer_size => max_output_buffer_size,
+            max_output_buffer_timeout => max_output_buffer_timeout,
+            min_output_buffer_timeout => min_output_buffer_timeout,
+            output_buffer_timeout => output_buffer_timeout,
+            max_channel_consumers => max_channel_consumers,
+            snappy => snappy_enabled,
+            deflate => deflate_enabled,
+            max_deflate_level => max_deflate_level,
+            tls_cert => tls_cert,
+            tls_key => tls_key,
+            tls_client_auth_policy => tls_client_auth_policy,
+            tls_root_ca_file => tls_root_ca_file,
+            tls_required => tls_required,
+            tls_min_version => tls_min_version,
+            statsd_address => statsd_address,
+            statsd_prefix => statsd_prefix,
+            statsd_interval => statsd_interval,
+            statsd_mem_stats => statsd_mem_stats,
+            statsd_udp_packet_size => statsd_udp_packet_size,
+            statsd_exclude_ephemeral => statsd_exclude_ephemeral,
+        );
+
+        macro_rules! ignore {
+            ($($field:ident => $flag:literal),* $(,)?) => {
+                $(
+                    if self.$field.is_some() || file.$field.is_some() {
+                        warn!("{} is not supported and will be ignored", $flag);
+                    }
+                )*
+            };
+        }
+        ignore!(
+            log_prefix => "--log-prefix",
+            verbose => "--verbose",
+            e2e_processing_latency_percentile => "--e2e-processing-latency-percentile",
+            e2e_processing_latency_window_time => "--e2e-processing-latency-window-time",
+        );
+
+        opts.validate()?;
+        Ok(opts)
+    }
+}
+
+fn parse_bool_flag(s: &str) -> std::result::Result<bool, String> {
+    parse_bool(s).ok_or_else(|| format!("invalid boolean value {s:?}"))
+}
+
+// 配置文件中的值可能是字符串、整数或者布尔值
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawValue {
+    Str(String),
+    Int(u64),
+    Bool(bool),
+}
+
+// golang格式的时间间隔，例如"1m30s"、"250ms"。配置文件中的整数表示毫秒
+#[derive(Clone, Copy)]
+struct GoDuration(Duration);
+
+impl FromStr for GoDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        parse_duration(s).map(GoDuration)
+    }
+}
+
+impl<'de> Deserialize<'de> for GoDuration {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        match RawValue::deserialize(d)? {
+            RawValue::Str(s) => s.parse().map_err(de::Error::custom),
+            RawValue::Int(ms) => Ok(GoDuration(Duration::from_millis(ms))),
+            RawValue::Bool(_) => Err(de::Error::custom("invalid duration")),
+        }
+    }
+}
+
+impl From<GoDuration> for Duration {
+    fn from(d: GoDuration) -> Self {
+        d.0
+    }
+}
+
+// --tls-required的取值为true、false或者tcp-https，配置文件中也可以使用整数
+#[derive(Clone, Copy)]
+struct TlsRequired(u32);
+
+impl FromStr for TlsRequired {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "tcp-https" {
+            return Ok(TlsRequired(TLS_REQUIRED_EXCEPT_HTTP));
+        }
+        match parse_bool(s) {
+            Some(true) => Ok(TlsRequired(TLS_REQUIRED)),
+            Some(false) => Ok(TlsRequired(TLS_NOT_REQUIRED)),
+            None => Err(format!("invalid tls-required {s:?}")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TlsRequired {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        match RawValue::deserialize(d)? {
+            RawValue::Str(s) => s.parse().map_err(de::Error::custom),
+            RawValue::Int(v) => Ok(TlsRequired(v as u32)),
+            RawValue::Bool(true) => Ok(TlsRequired(TLS_REQUIRED)),
+            RawValue::Bool(false) => Ok(TlsRequired(TLS_NOT_REQUIRED)),
+        }
+    }
+}
+
+impl From<TlsRequired> for u32 {
+    fn from(v: TlsRequired) -> Self {
+        v.0
+    }
+}
+
+#[derive(Clone, Copy)]
+struct TlsVersion(ProtocolVersion);
+
+impl FromStr for TlsVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let v = match s.to_lowercase().as_str() {
+            "ssl3.0" => ProtocolVersion::SSLv3,
+            "tls1.0" => ProtocolVersion::TLSv1_0,
+            "tls1.1" => ProtocolVersion::TLSv1_1,
+            "tls1.2" => ProtocolVersion::TLSv1_2,
+            "tls1.3" => ProtocolVersion::TLSv1_3,
+            _ => return Err(format!("unknown tls version {s:?}")),
+        };
+        Ok(TlsVersion(v))
+    }
+}
+
+impl<'de> Deserialize<'de> for TlsVersion {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(d)?.parse().map_err(de::Error::custom)
+    }
+}
+
+impl From<TlsVersion> for ProtocolVersion {
+    fn from(v: TlsVersion) -> Self {
+        v.0
+    }
+}
+
+// golang的日志级别中fatal对应这里的error
+#[derive(Clone, Copy)]
+struct LogLevel(Level);
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("fatal") {
+            return Ok(LogLevel(Level::ERROR));
+        }
+        s.parse()
+            .map(LogLevel)
+            .map_err(|_| format!("invalid log level {s:?}"))
+    }
+}
+
+impl<'de> Deserialize<'de> for LogLevel {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(d)?.parse().map_err(de::Error::custom)
+    }
+}
+
+impl From<LogLevel> for Level {
+    fn from(v: LogLevel) -> Self {
+        v.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, ffi::OsString};
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options> {
+        let args = normalize_args::<Config>(args.iter().map(OsString::from));
+        Config::try_parse_from(args).unwrap().into_options()
+    }
+
+    fn config_file(content: &str) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), content).unwrap();
+        file
+    }
+
+    #[test]
+    fn normalize_go_flags() {
+        let args = [
+            "nsqd",
+            "-max-rdy-count",
+            "-10",
+            "-tcp-address=0.0.0.0:4150",
+            "--http-address",
+            "0.0.0.0:4151",
+            "-unknown",
+            "-V",
+        ];
+        let args = normalize_args::<Config>(args.iter().map(OsString::from));
+        assert_eq!(
+            args,
+            [
+                "nsqd",
+                "--max-rdy-count",
+                "-10",
+                "--tcp-address=0.0.0.0:4150",
+                "--http-address",
+                "0.0.0.0:4151",
+                "-unknown",
+                "-V",
+            ]
+        );
+
+        let config = Config::try_parse_from(normalize_args::<Config>(
+            ["nsqd", "-max-rdy-count", "-10"].iter().map(OsString::from),
+        ))
+        .unwrap();
+        assert_eq!(config.max_rdy_count, Some(-10));
+    }
+
+    // 各个测试使用的环境变量互不重叠，避免并行执行时互相影响
+    #[test]
+    fn precedence() {
+        let file = config_file(
+            r#"
+            tcp_address = "127.0.0.1:1"
+            http_address = "127.0.0.1:2"
+            https_address = "127.0.0.1:3"
+            "#,
+        );
+        env::set_var("NSQD_HTTP_ADDRESS", "127.0.0.1:20");
+        env::set_var("NSQD_HTTPS_ADDRESS", "127.0.0.1:30");
+        let opts = parse(&[
+            "nsqd",
+            "--config",
+            file.path().to_str().unwrap(),
+            "-https-address=127.0.0.1:300",
+        ]);
+        env::remove_var("NSQD_HTTP_ADDRESS");
+        env::remove_var("NSQD_HTTPS_ADDRESS");
+
+        let opts = opts.unwrap();
+        assert_eq!(opts.tcp_addr, "127.0.0.1:1");
+        assert_eq!(opts.http_addr, "127.0.0.1:20");
+        assert_eq!(opts.https_addr, "127.0.0.1:300");
+        assert_eq!(opts.mem_queue_size, Options::new().mem_queue_size);
+    }
+
+    #[test]
+    fn env_overrides() {
+        env::set_var("NSQD_SNAPPY", "false");
+        env::set_var("NSQD_TLS_REQUIRED", "tcp-https");
+        env::set_var("NSQD_LOOKUPD_TCP_ADDRESS", "127.0.0.1:4160,127.0.0.1:5160");
+        env::set_var("NSQD_MSG_TIMEOUT", "1m30s");
+        let opts = parse(&["nsqd"]);
+        env::remove_var("NSQD_SNAPPY");
+        env::remove_var("NSQD_TLS_REQUIRED");
+        env::remove_var("NSQD_LOOKUPD_TCP_ADDRESS");
+        env::remove_var("NSQD_MSG_TIMEOUT");
+
+        let opts = opts.unwrap();
+        assert!(!opts.snappy_enabled);
+        assert_eq!(opts.tls_required, TLS_REQUIRED_EXCEPT_HTTP);
+        assert_eq!(
+            opts.nsq_lookup_tcp_addrs,
+            ["127.0.0.1:4160", "127.0.0.1:5160"]
+        );
+        assert_eq!(opts.msg_timeout, Duration::from_secs(90));
+    }
+
+    #[test]
+    fn toml_file() {
+        let file = config_file(
+            r#"
+            log_level = "warn"
+            id = 8
+            auth_http_addresses = ["127.0.0.1:4181", "127.0.0.1:5181"]
+            sync_timeout = 500
+            max_req_timeout = "2h"
+            deflate = false
+            max_deflate_level = 3
+            statsd_prefix = "nsq"
+            statsd_mem_stats = false
+            tls_min_version = "tls1.2"
+            # golang版本中还没有支持的配置项会被忽略
+            log_prefix = "[nsqd] "
+            e2e_processing_latency_percentile = [0.99, 0.95]
+            "#,
+        );
+        let opts = parse(&["nsqd", "--config", file.path().to_str().unwrap()]).unwrap();
+        assert_eq!(opts.log_level, Level::WARN);
+        assert_eq!(opts.id, 8);
+        assert_eq!(opts.auth_http_addrs, ["127.0.0.1:4181", "127.0.0.1:5181"]);
+        assert_eq!(opts.sync_timeout, Duration::from_millis(500));
+        assert_eq!(opts.max_req_timeout, Duration::from_secs(2 * 3600));
+        assert!(!opts.deflate_enabled);
+        assert_eq!(opts.max_deflate_level, 3);
+        assert_eq!(opts.statsd_prefix, "nsq");
+        assert!(!opts.statsd_mem_stats);
+        assert_eq!(opts.tls_min_version, ProtocolVersion::TLSv1_2);
+
+        let file = config_file("max_req_timeout = \"2 hours\"");
+        let err = parse(&["nsqd", "--config", file.path().to_str().unwrap()]);
+        let err = err.err().unwrap().to_string();
+        assert!(err.contains("failed to parse config file"), "{err}");
+
+        let err = parse(&["nsqd", "--config", "/nonexistent/nsqd.cfg"]);
+        let err = err.err().unwrap().to_string();
+        assert!(err.contains("failed to load config file"), "{err}");
+    }
+
+    #[test]
+    fn validate_rejects() {
+        for args in [
+            &["nsqd", "--node-id=1024"][..],
+            &["nsqd", "--msg-timeout=2m", "--max-msg-timeout=1m"],
+            &["nsqd", "--queue-scan-selection-count=0"],
+            &["nsqd", "--max-deflate-level=10"],
+            &["nsqd", "--auth-http-request-method=put"],
+            &["nsqd", "--tls-client-auth-policy=optional"],
+            &["nsqd", "--tls-cert=/nonexistent/cert.pem"],
+            &[
+                "nsqd",
+                "--statsd-address=127.0.0.1:8125",
+                "--statsd-interval=0",
+            ],
+        ] {
+            let err = parse(args).err();
+            assert!(
+                matches!(err, Some(NsqError::InvalidOptions(_))),
+                "{args:?} {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn ignored_go_flags() {
+        let opts = parse(&[
+            "nsqd",
+            "-log-prefix=[nsqd] ",
+            "-verbose",
+            "-e2e-processing-latency-percentile=0.99,0.95",
+            "-e2e-processing-latency-percentile",
+            "0.5",
+            "-e2e-processing-latency-window-time=10m",
+            "-max-output-buffer-size=1024",
+        ])
+        .unwrap();
+        assert_eq!(opts.max_output_buffer_size, 1024);
+    }
+}
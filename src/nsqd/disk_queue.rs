@@ -0,0 +1,906 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_channel::{Receiver, Sender};
+use tokio::{
+    select,
+    sync::Notify,
+    task::JoinHandle,
+    time::{interval, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use super::{
+    backend_queue::{BackEndQueue, BoxFuture},
+    message::MIN_VALID_MSG_LEN,
+    options::Options,
+};
+use crate::{common::Result, errors::NsqError};
+
+// 基于文件的后端队列
+//
+// 消息依次追加写入编号递增的数据文件中，每条消息的格式为 [4字节长度][N字节数据]，
+// 单个文件超过max_bytes_per_file之后切换到下一个文件，读完的文件会被删除。
+// 读写位置记录在元数据文件中，重启之后从上次停止的位置继续
+pub(super) struct DiskQueue {
+    name: String,
+
+    state: Arc<Mutex<DiskQueueState>>,
+    depth: Arc<AtomicI64>,
+
+    read_rx: Receiver<Vec<u8>>,
+
+    // 有新数据写入时唤醒读取任务
+    write_notify: Arc<Notify>,
+    // 执行empty之后通知读取任务丢弃已经读出但还没有投递的数据
+    reset_notify: Arc<Notify>,
+
+    exit_token: CancellationToken,
+    read_handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct DiskQueueState {
+    name: String,
+    data_path: PathBuf,
+    max_bytes_per_file: i64,
+    min_msg_size: u32,
+    max_msg_size: u32,
+    sync_every: i64,
+
+    read_pos: i64,
+    write_pos: i64,
+    read_file_num: i64,
+    write_file_num: i64,
+
+    // 已经读出但还没有被消费的数据的下一个位置
+    next_read_pos: i64,
+    next_read_file_num: i64,
+    // 正在读取的文件的大小，只有读完整个文件之后才会切换到下一个文件
+    max_bytes_per_file_read: i64,
+
+    read_file: Option<BufReader<File>>,
+    write_file: Option<File>,
+
+    need_sync: bool,
+    // 距离上次sync的读写次数
+    count: i64,
+
+    // 每次empty之后递增，用于丢弃empty之前读出的数据
+    generation: u64,
+    // 最后一条投递到read_chan的数据的位置。关闭时如果这条数据还没有被取走，读取位置回退到这里
+    delivered: Option<(i64, i64)>,
+    // 最后一条投递的数据所在的文件已经读完，等这条数据被取走之后再删除
+    pending_remove: Option<i64>,
+    // 元数据是否已经加载
+    loaded: bool,
+
+    depth: Arc<AtomicI64>,
+}
+
+impl DiskQueue {
+    pub fn new(name: &str, opts: &Options) -> Self {
+        let depth = Arc::new(AtomicI64::new(0));
+        let state = DiskQueueState {
+            name: name.to_owned(),
+            data_path: opts.data_path.clone(),
+            max_bytes_per_file: opts.max_bytes_per_file as i64,
+            min_msg_size: MIN_VALID_MSG_LEN as u32,
+            max_msg_size: opts.max_msg_size + MIN_VALID_MSG_LEN as u32,
+            sync_every: opts.sync_every as i64,
+            read_pos: 0,
+            write_pos: 0,
+            read_file_num: 0,
+            write_file_num: 0,
+            next_read_pos: 0,
+            next_read_file_num: 0,
+            max_bytes_per_file_read: 0,
+            read_file: None,
+            write_file: None,
+            need_sync: false,
+            count: 0,
+            generation: 0,
+            delivered: None,
+            pending_remove: None,
+            loaded: false,
+            depth: depth.clone(),
+        };
+
+        let (read_tx, read_rx) = async_channel::bounded(1);
+        let queue = Self {
+            name: name.to_owned(),
+            state: Arc::new(Mutex::new(state)),
+            depth,
+            read_rx,
+            write_notify: Arc::new(Notify::new()),
+            reset_notify: Arc::new(Notify::new()),
+            exit_token: CancellationToken::new(),
+            read_handle: Mutex::new(None),
+        };
+
+        let read_handle = tokio::spawn(read_loop(
+            queue.name.clone(),
+            queue.state.clone(),
+            read_tx,
+            queue.write_notify.clone(),
+            queue.reset_notify.clone(),
+            queue.exit_token.clone(),
+        ));
+        *queue.read_handle.lock().unwrap() = Some(read_handle);
+        tokio::spawn(sync_loop(
+            queue.state.clone(),
+            queue.exit_token.clone(),
+            opts.sync_timeout,
+        ));
+
+        queue
+    }
+
+    async fn exit(&self, deleted: bool) -> Result<()> {
+        if self.exit_token.is_cancelled() {
+            return Err(NsqError::Exiting);
+        }
+
+        if deleted {
+            info!("DISKQUEUE({}): deleting", self.name);
+        } else {
+            info!("DISKQUEUE({}): closing", self.name);
+        }
+
+        self.exit_token.cancel();
+        // 等待读取任务退出，之后读取位置不会再变化
+        let handle = self.read_handle.lock().unwrap().take();
+        if let Some(handle) = handle {
+            let _ = handle.await;
+        }
+
+        let read_rx = self.read_rx.clone();
+        with_state(&self.state, move |state| {
+            read_rx.close();
+            if !deleted {
+                match read_rx.try_recv() {
+                    // read_chan中缓存的数据还没有被消费，重启之后重新读取
+                    Ok(data) => state.rewind(&data),
+                    Err(_) => state.remove_pending_file(),
+                }
+            }
+
+            state.read_file = None;
+            if !deleted {
+                state.sync()?;
+            }
+            state.write_file = None;
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl BackEndQueue for DiskQueue {
+    fn put(&self, b: Vec<u8>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let exit_token = self.exit_token.clone();
+            with_state(&self.state, move |state| {
+                // 在锁内再检查一次，避免关闭之后还有数据写入
+                if exit_token.is_cancelled() {
+                    return Err(NsqError::Exiting);
+                }
+                state.write_one(&b)
+            })
+            .await?;
+            self.write_notify.notify_one();
+            Ok(())
+        })
+    }
+
+    fn read_chan(&self) -> Receiver<Vec<u8>> {
+        self.read_rx.clone()
+    }
+
+    fn close(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.exit(false))
+    }
+
+    fn delete(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.exit(true))
+    }
+
+    // read_chan中缓存的数据还没有被取走，也需要计入depth
+    fn depth(&self) -> i64 {
+        self.depth.load(Ordering::SeqCst) + self.read_rx.len() as i64
+    }
+
+    // 删除所有数据文件，重置读写位置
+    fn empty(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            if self.exit_token.is_cancelled() {
+                return Err(NsqError::Exiting);
+            }
+
+            info!("DISKQUEUE({}): emptying", self.name);
+            let read_rx = self.read_rx.clone();
+            with_state(&self.state, move |state| -> Result<()> {
+                state.delete_all_files()?;
+                // 丢弃read_chan中缓存的数据
+                while read_rx.try_recv().is_ok() {}
+                Ok(())
+            })
+            .await?;
+            self.reset_notify.notify_one();
+            Ok(())
+        })
+    }
+}
+
+// 文件读写和fsync都是阻塞操作，放到blocking线程池中执行，避免阻塞tokio的worker线程。
+// 元数据也在这里加载：创建之后启动的read_loop会立即触发，之后的操作都能看到加载的结果
+async fn with_state<F, T>(state: &Arc<Mutex<DiskQueueState>>, f: F) -> T
+where
+    F: FnOnce(&mut DiskQueueState) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut state = state.lock().unwrap();
+        state.load_meta_data();
+        f(&mut state)
+    })
+    .await
+    .unwrap() // 只有f panic时才会失败
+}
+
+// 依次读出数据投递到read_chan，数据被取走之后才移动读取位置
+async fn read_loop(
+    name: String,
+    state: Arc<Mutex<DiskQueueState>>,
+    read_tx: Sender<Vec<u8>>,
+    write_notify: Arc<Notify>,
+    reset_notify: Arc<Notify>,
+    exit_token: CancellationToken,
+) {
+    loop {
+        let next = with_state(&state, |state| state.read_next()).await;
+
+        // 优先检查退出信号，关闭之后不再投递新的数据
+        match next {
+            Some((data, generation)) => {
+                select! {
+                    biased;
+                    _ = exit_token.cancelled() => break,
+                    _ = reset_notify.notified() => {}
+                    res = read_tx.send(data) => {
+                        if res.is_err() {
+                            break;
+                        }
+                        with_state(&state, move |state| {
+                            // 投递期间执行过empty，读取位置已经被重置
+                            if state.generation == generation {
+                                state.move_forward();
+                            }
+                        })
+                        .await;
+                    }
+                }
+            }
+            None => {
+                select! {
+                    biased;
+                    _ = exit_token.cancelled() => break,
+                    _ = reset_notify.notified() => {}
+                    _ = write_notify.notified() => {}
+                }
+            }
+        }
+    }
+
+    info!("DISKQUEUE({name}): closing ... ioLoop");
+}
+
+// 距离上次sync有读写操作时，每隔sync_timeout执行一次sync
+async fn sync_loop(
+    state: Arc<Mutex<DiskQueueState>>,
+    exit_token: CancellationToken,
+    sync_timeout: Duration,
+) {
+    let mut sync_ticker = interval(sync_timeout);
+    sync_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        select! {
+            _ = sync_ticker.tick() => with_state(&state, |state| state.sync_if_needed()).await,
+            _ = exit_token.cancelled() => break,
+        }
+    }
+}
+
+impl DiskQueueState {
+    fn file_name(&self, file_num: i64) -> PathBuf {
+        self.data_path
+            .join(format!("{}.diskqueue.{file_num:06}.dat", self.name))
+    }
+
+    fn meta_data_file_name(&self) -> PathBuf {
+        self.data_path
+            .join(format!("{}.diskqueue.meta.dat", self.name))
+    }
+
+    // 还有没有读出的数据时，读出下一条消息。
+    // 读取出错时跳到下一个文件继续读取，后面的文件中可能还有没有读出的数据
+    fn read_next(&mut self) -> Option<(Vec<u8>, u64)> {
+        while self.read_file_num != self.write_file_num || self.read_pos != self.write_pos {
+            match self.read_one() {
+                Ok(data) => return Some((data, self.generation)),
+                Err(e) => {
+                    error!(
+                        "DISKQUEUE({}) reading at {} of {} - {e}",
+                        self.name,
+                        self.read_pos,
+                        self.file_name(self.read_file_num).display()
+                    );
+                    self.handle_read_error();
+                }
+            }
+        }
+        None
+    }
+
+    fn read_one(&mut self) -> io::Result<Vec<u8>> {
+        self.open_read_file()?;
+
+        // 上一条消息恰好是文件的最后一条，但读取时文件还没有写完
+        while self.read_file_num < self.write_file_num
+            && self.read_pos >= self.max_bytes_per_file_read
+        {
+            let old_read_file_num = self.read_file_num;
+            self.read_file = None;
+            self.read_file_num += 1;
+            self.read_pos = 0;
+            self.next_read_file_num = self.read_file_num;
+            self.next_read_pos = 0;
+            self.need_sync = true;
+            self.remove_or_defer(old_read_file_num);
+
+            self.open_read_file()?;
+        }
+
+        let reader = self.read_file.as_mut().unwrap();
+        let mut len_buf = [0u8; 4];
+        reader.read_exact(&mut len_buf)?;
+        let msg_size = u32::from_be_bytes(len_buf);
+
+        if msg_size < self.min_msg_size || msg_size > self.max_msg_size {
+            // 文件已经损坏，无法继续读取
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid message read size ({msg_size})"),
+            ));
+        }
+
+        let mut data = vec![0u8; msg_size as usize];
+        reader.read_exact(&mut data)?;
+
+        let total_bytes = 4 + msg_size as i64;
+
+        self.next_read_pos = self.read_pos + total_bytes;
+        self.next_read_file_num = self.read_file_num;
+
+        // 读完一个已经写完的文件之后切换到下一个文件
+        if self.read_file_num < self.write_file_num
+            && self.next_read_pos >= self.max_bytes_per_file_read
+        {
+            self.read_file = None;
+            self.next_read_file_num += 1;
+            self.next_read_pos = 0;
+        }
+
+        Ok(data)
+    }
+
+    fn open_read_file(&mut self) -> io::Result<()> {
+        if self.read_file.is_none() {
+            let cur_file_name = self.file_name(self.read_file_num);
+            let mut file = File::open(&cur_file_name)?;
+
+            info!(
+                "DISKQUEUE({}): readOne() opened {}",
+                self.name,
+                cur_file_name.display()
+            );
+
+            if self.read_pos > 0 {
+                file.seek(SeekFrom::Start(self.read_pos as u64))?;
+            }
+
+            // 只有读取已经写完的文件时才能确定文件的大小
+            self.max_bytes_per_file_read = self.max_bytes_per_file;
+            if self.read_file_num < self.write_file_num {
+                self.max_bytes_per_file_read = file.metadata()?.len() as i64;
+            }
+
+            self.read_file = Some(BufReader::new(file));
+        }
+        Ok(())
+    }
+
+    // 最后一条投递的数据所在的文件需要等数据被取走之后再删除
+    fn remove_or_defer(&mut self, file_num: i64) {
+        if self.delivered.map(|(num, _)| num) == Some(file_num) {
+            self.pending_remove = Some(file_num);
+        } else {
+            self.remove_data_file(file_num);
+        }
+    }
+
+    fn remove_pending_file(&mut self) {
+        if let Some(file_num) = self.pending_remove.take() {
+            self.remove_data_file(file_num);
+        }
+    }
+
+    fn remove_data_file(&self, file_num: i64) {
+        let file_name = self.file_name(file_num);
+        if let Err(e) = fs::remove_file(&file_name) {
+            error!(
+                "DISKQUEUE({}) failed to Remove({}) - {e}",
+                self.name,
+                file_name.display()
+            );
+        }
+    }
+
+    fn write_one(&mut self, data: &[u8]) -> Result<()> {
+        let data_len = data.len() as u32;
+        if data_len < self.min_msg_size || data_len > self.max_msg_size {
+            return Err(NsqError::InvalidMsgLength);
+        }
+
+        let total_bytes = 4 + data_len as i64;
+
+        // 当前文件写不下时切换到下一个文件
+        if self.write_pos > 0 && self.write_pos + total_bytes > self.max_bytes_per_file {
+            if self.read_file_num == self.write_file_num {
+                self.max_bytes_per_file_read = self.write_pos;
+            }
+
+            self.write_file_num += 1;
+            self.write_pos = 0;
+
+            // sync每个写完的文件
+            if let Err(e) = self.sync() {
+                error!("DISKQUEUE({}) failed to sync - {e}", self.name);
+            }
+            self.write_file = None;
+        }
+
+        if self.write_file.is_none() {
+            let cur_file_name = self.file_name(self.write_file_num);
+            let mut file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(false)
+                .open(&cur_file_name)?;
+
+            info!(
+                "DISKQUEUE({}): writeOne() opened {}",
+                self.name,
+                cur_file_name.display()
+            );
+
+            if self.write_pos > 0 {
+                file.seek(SeekFrom::Start(self.write_pos as u64))?;
+            }
+            self.write_file = Some(file);
+        }
+
+        // 长度和数据一次写入，保证写入的原子性
+        let mut buf = Vec::with_capacity(total_bytes as usize);
+        buf.extend_from_slice(&data_len.to_be_bytes());
+        buf.extend_from_slice(data);
+
+        if let Err(e) = self.write_file.as_mut().unwrap().write_all(&buf) {
+            self.write_file = None;
+            return Err(e.into());
+        }
+
+        self.write_pos += total_bytes;
+        self.depth.fetch_add(1, Ordering::SeqCst);
+
+        self.count += 1;
+        if self.count >= self.sync_every {
+            self.need_sync = true;
+            self.sync_if_needed();
+        }
+
+        Ok(())
+    }
+
+    // 数据被消费之后移动读取位置，删除已经读完的文件
+    fn move_forward(&mut self) {
+        // read_chan只能缓存一条数据，能投递新的数据说明上一条已经被取走了
+        self.remove_pending_file();
+        self.delivered = Some((self.read_file_num, self.read_pos));
+
+        let old_read_file_num = self.read_file_num;
+        self.read_file_num = self.next_read_file_num;
+        self.read_pos = self.next_read_pos;
+        let depth = self.depth.fetch_sub(1, Ordering::SeqCst) - 1;
+
+        if old_read_file_num != self.next_read_file_num {
+            self.need_sync = true;
+            self.remove_or_defer(old_read_file_num);
+        }
+
+        self.count += 1;
+        if self.count >= self.sync_every {
+            self.need_sync = true;
+        }
+
+        self.check_tail_corruption(depth);
+    }
+
+    // 读写位置相同但depth不为0，说明元数据和数据文件不一致
+    fn check_tail_corruption(&mut self, depth: i64) {
+        if self.read_file_num < self.write_file_num || self.read_pos < self.write_pos {
+            return;
+        }
+
+        if depth != 0 {
+            error!(
+                "DISKQUEUE({}) negative depth at tail ({depth}), metadata corruption, resetting 0...",
+                self.name
+            );
+            self.depth.store(0, Ordering::SeqCst);
+            self.need_sync = true;
+        }
+
+        if self.read_file_num != self.write_file_num || self.read_pos != self.write_pos {
+            error!(
+                "DISKQUEUE({}) readFileNum > writeFileNum or readPos > writePos, corruption, skipping to next writeFileNum and resetting 0...",
+                self.name
+            );
+            self.skip_to_next_rw_file();
+            self.need_sync = true;
+        }
+    }
+
+    // 关闭时最后一条投递的数据还没有被取走，读取位置回退到这条数据，
+    // 没有记录位置时（比如所在的文件已经损坏）重新写入到队列末尾
+    fn rewind(&mut self, data: &[u8]) {
+        let Some((file_num, pos)) = self.delivered.take() else {
+            if let Err(e) = self.write_one(data) {
+                error!(
+                    "DISKQUEUE({}) failed to write back unread data - {e}",
+                    self.name
+                );
+            }
+            return;
+        };
+
+        self.read_file = None;
+        self.read_file_num = file_num;
+        self.read_pos = pos;
+        self.next_read_file_num = file_num;
+        self.next_read_pos = pos;
+        self.pending_remove = None;
+        self.depth.fetch_add(1, Ordering::SeqCst);
+        self.need_sync = true;
+    }
+
+    // 数据文件损坏，重命名为.bad之后跳到下一个文件
+    fn handle_read_error(&mut self) {
+        if self.read_file_num == self.write_file_num {
+            // 写入也需要切换到下一个文件
+            self.write_file = None;
+            self.write_file_num += 1;
+            self.write_pos = 0;
+        }
+
+        if self.delivered.map(|(num, _)| num) == Some(self.read_file_num) {
+            self.delivered = None;
+        }
+
+        let bad_file_name = self.file_name(self.read_file_num);
+        let mut bad_renamed = bad_file_name.clone().into_os_string();
+        bad_renamed.push(".bad");
+
+        warn!(
+            "DISKQUEUE({}) jump to next file and saving bad file as {}",
+            self.name,
+            PathBuf::from(&bad_renamed).display()
+        );
+
+        if let Err(e) = fs::rename(&bad_file_name, &bad_renamed) {
+            error!(
+                "DISKQUEUE({}) failed to rename bad diskqueue file {} to {} - {e}",
+                self.name,
+                bad_file_name.display(),
+                PathBuf::from(&bad_renamed).display()
+            );
+        }
+
+        self.read_file_num += 1;
+        self.read_pos = 0;
+        self.next_read_file_num = self.read_file_num;
+        self.next_read_pos = 0;
+        self.read_file = None;
+
+        self.need_sync = true;
+        self.check_tail_corruption(self.depth.load(Ordering::SeqCst));
+    }
+
+    fn skip_to_next_rw_file(&mut self) {
+        self.read_file = None;
+        self.write_file = None;
+        self.remove_pending_file();
+        self.delivered = None;
+
+        for i in self.read_file_num..=self.write_file_num {
+            let file_name = self.file_name(i);
+            if let Err(e) = fs::remove_file(&file_name) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    error!("DISKQUEUE({}) failed to remove data file - {e}", self.name);
+                }
+            }
+        }
+
+        self.write_file_num += 1;
+        self.write_pos = 0;
+        self.read_file_num = self.write_file_num;
+        self.read_pos = 0;
+        self.next_read_file_num = self.write_file_num;
+        self.next_read_pos = 0;
+        self.depth.store(0, Ordering::SeqCst);
+    }
+
+    fn delete_all_files(&mut self) -> Result<()> {
+        self.skip_to_next_rw_file();
+        self.generation += 1;
+
+        match fs::remove_file(self.meta_data_file_name()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                error!(
+                    "DISKQUEUE({}) failed to remove metadata file - {e}",
+                    self.name
+                );
+                Err(e.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn sync_if_needed(&mut self) {
+        if !self.need_sync && self.count == 0 {
+            return;
+        }
+
+        if let Err(e) = self.sync() {
+            error!("DISKQUEUE({}) failed to sync - {e}", self.name);
+        }
+    }
+
+    // 将数据文件刷到磁盘并保存元数据
+    fn sync(&mut self) -> io::Result<()> {
+        if let Some(file) = self.write_file.as_mut() {
+            if let Err(e) = file.sync_all() {
+                self.write_file = None;
+                return Err(e);
+            }
+        }
+
+        self.persist_meta_data()?;
+
+        self.need_sync = false;
+        self.count = 0;
+        Ok(())
+    }
+
+    // 只在第一次操作之前加载一次，元数据文件不存在说明是新创建的队列
+    fn load_meta_data(&mut self) {
+        if self.loaded {
+            return;
+        }
+        self.loaded = true;
+        if let Err(e) = self.retrieve_meta_data() {
+            if e.kind() != io::ErrorKind::NotFound {
+                error!("DISKQUEUE({}) failed to retrieveMetaData - {e}", self.name);
+            }
+        }
+    }
+
+    fn retrieve_meta_data(&mut self) -> io::Result<()> {
+        let file_name = self.meta_data_file_name();
+        let content = fs::read_to_string(&file_name)?;
+
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid metadata file {}", file_name.display()),
+            )
+        };
+        let mut nums = content
+            .split(['\n', ','])
+            .filter(|s| !s.is_empty())
+            .map(|s| s.trim().parse::<i64>().map_err(|_| invalid()));
+
+        let mut next = || nums.next().unwrap_or_else(|| Err(invalid()));
+        let depth = next()?;
+        self.read_file_num = next()?;
+        self.read_pos = next()?;
+        self.write_file_num = next()?;
+        self.write_pos = next()?;
+
+        self.depth.store(depth, Ordering::SeqCst);
+        self.next_read_file_num = self.read_file_num;
+        self.next_read_pos = self.read_pos;
+
+        // 元数据中记录的写入位置落后于文件实际大小时（比如上次没有正常退出），
+        // 跳到下一个文件写入，避免覆盖已经写入的数据
+        let write_file_name = self.file_name(self.write_file_num);
+        if let Ok(meta) = fs::metadata(&write_file_name) {
+            let file_size = meta.len() as i64;
+            if self.write_pos < file_size {
+                warn!(
+                    "DISKQUEUE({}) {} metadata writePos {} < file size of {}, skipping to new file",
+                    self.name,
+                    write_file_name.display(),
+                    self.write_pos,
+                    file_size
+                );
+                self.write_file_num += 1;
+                self.write_pos = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    // 先写入临时文件再重命名，保证元数据文件的原子性
+    fn persist_meta_data(&self) -> io::Result<()> {
+        let file_name = self.meta_data_file_name();
+        let mut tmp_file_name = file_name.clone().into_os_string();
+        tmp_file_name.push(".tmp");
+
+        let mut f = File::create(&tmp_file_name)?;
+        write!(
+            f,
+            "{}\n{},{}\n{},{}\n",
+            self.depth.load(Ordering::SeqCst),
+            self.read_file_num,
+            self.read_pos,
+            self.write_file_num,
+            self.write_pos
+        )?;
+        f.sync_all()?;
+        drop(f);
+
+        fs::rename(&tmp_file_name, &file_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tempfile::TempDir;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn test_options(data_path: &Path, max_bytes_per_file: u32) -> Options {
+        let mut opts = Options::new();
+        opts.data_path = data_path.to_owned();
+        opts.max_bytes_per_file = max_bytes_per_file;
+        opts.max_msg_size = 1024;
+        opts.sync_every = 1;
+        opts
+    }
+
+    // 固定长度的消息，方便计算文件大小
+    fn msg(i: usize) -> Vec<u8> {
+        format!("{i:0width$}", width = MIN_VALID_MSG_LEN).into_bytes()
+    }
+
+    async fn recv(dq: &DiskQueue) -> Vec<u8> {
+        timeout(Duration::from_secs(5), dq.read_chan().recv())
+            .await
+            .expect("timed out waiting for message")
+            .unwrap()
+    }
+
+    fn data_files(dir: &Path, suffix: &str) -> usize {
+        fs::read_dir(dir)
+            .unwrap()
+            .filter(|e| {
+                let name = e.as_ref().unwrap().file_name();
+                name.to_string_lossy().ends_with(suffix)
+            })
+            .count()
+    }
+
+    #[tokio::test]
+    async fn resume_after_restart() {
+        let dir = TempDir::new().unwrap();
+        let opts = test_options(dir.path(), 1024 * 1024);
+
+        let dq = DiskQueue::new("resume", &opts);
+        for i in 0..10 {
+            dq.put(msg(i)).await.unwrap();
+        }
+        for i in 0..4 {
+            assert_eq!(recv(&dq).await, msg(i));
+        }
+        dq.close().await.unwrap();
+
+        // 重启之后从上次停止的位置继续，不重复也不丢失
+        let dq = DiskQueue::new("resume", &opts);
+        // 元数据在blocking线程中加载，等待加载完成
+        with_state(&dq.state, |_| ()).await;
+        assert_eq!(dq.depth(), 6);
+        for i in 4..10 {
+            assert_eq!(recv(&dq).await, msg(i));
+        }
+        dq.put(msg(10)).await.unwrap();
+        assert_eq!(recv(&dq).await, msg(10));
+        dq.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn roll_at_max_bytes_per_file() {
+        let dir = TempDir::new().unwrap();
+        // 每个文件正好可以放下3条消息
+        let msg_bytes = 4 + MIN_VALID_MSG_LEN as u32;
+        let opts = test_options(dir.path(), msg_bytes * 3);
+
+        let dq = DiskQueue::new("roll", &opts);
+        for i in 0..10 {
+            dq.put(msg(i)).await.unwrap();
+        }
+        assert_eq!(data_files(dir.path(), ".dat") - 1, 4);
+
+        for i in 0..10 {
+            assert_eq!(recv(&dq).await, msg(i));
+        }
+        // 读完的文件会被删除，只剩下正在写入的文件和元数据文件
+        dq.close().await.unwrap();
+        assert_eq!(data_files(dir.path(), ".dat") - 1, 1);
+        assert_eq!(dq.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn skip_corrupt_file() {
+        let dir = TempDir::new().unwrap();
+        let msg_bytes = 4 + MIN_VALID_MSG_LEN as u32;
+        let opts = test_options(dir.path(), msg_bytes * 3);
+
+        let dq = DiskQueue::new("corrupt", &opts);
+        for i in 0..9 {
+            dq.put(msg(i)).await.unwrap();
+        }
+        dq.close().await.unwrap();
+
+        // 把第二个文件中第一条消息的长度改成非法的值
+        let file_name = dir.path().join("corrupt.diskqueue.000001.dat");
+        let mut data = fs::read(&file_name).unwrap();
+        data[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        fs::write(&file_name, data).unwrap();
+
+        // 跳过损坏的文件之后，不需要等待新的写入就能读到后面文件中的消息
+        let dq = DiskQueue::new("corrupt", &opts);
+        for i in [0, 1, 2, 6, 7, 8] {
+            assert_eq!(recv(&dq).await, msg(i));
+        }
+        assert_eq!(data_files(dir.path(), ".bad"), 1);
+
+        dq.put(msg(9)).await.unwrap();
+        assert_eq!(recv(&dq).await, msg(9));
+        dq.close().await.unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::message::{MessageID, MSG_ID_LENGTH};
+use crate::{
+    common::{unix_nanos, Result},
+    errors::NsqError,
+};
+
+// 和golang版本的guid保持一致：41位时间戳 + 10位节点ID + 12位序列号
+const NODE_ID_BITS: u64 = 10;
+const SEQUENCE_BITS: u64 = 12;
+const NODE_ID_SHIFT: u64 = SEQUENCE_BITS;
+const TIMESTAMP_SHIFT: u64 = SEQUENCE_BITS + NODE_ID_BITS;
+const SEQUENCE_MASK: u64 = (1 << SEQUENCE_BITS) - 1;
+const NODE_ID_MASK: u64 = (1 << NODE_ID_BITS) - 1;
+
+// 2010-11-04 01:42:54 UTC，单位是2^20纳秒(约1毫秒)
+const TWEPOCH: i64 = 1288834974288;
+
+pub(super) struct Guid(u64);
+
+impl Guid {
+    // 8字节大端序的十六进制编码，正好16个ASCII字符
+    pub fn hex(&self) -> MessageID {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        let mut id = [0u8; MSG_ID_LENGTH];
+        for (i, b) in self.0.to_be_bytes().iter().enumerate() {
+            id[i * 2] = HEX[(b >> 4) as usize];
+            id[i * 2 + 1] = HEX[(b & 0x0f) as usize];
+        }
+        id
+    }
+}
+
+pub(super) struct GuidFactory {
+    node_id: u64,
+    // 上一次生成ID时的时间戳和序列号：timestamp << SEQUENCE_BITS | sequence。
+    // 通过CAS更新，不需要加锁
+    last: AtomicU64,
+}
+
+impl GuidFactory {
+    pub fn new(node_id: u16) -> Self {
+        Self {
+            node_id: node_id as u64 & NODE_ID_MASK,
+            last: AtomicU64::new(0),
+        }
+    }
+
+    // 同一个时间戳内序列号用完或者系统时钟回拨时返回错误，由调用方稍后重试
+    pub fn new_guid(&self) -> Result<Guid> {
+        self.next_guid(((unix_nanos() >> 20) - TWEPOCH) as u64)
+    }
+
+    fn next_guid(&self, ts: u64) -> Result<Guid> {
+        let mut last = self.last.load(Ordering::Acquire);
+        loop {
+            let last_ts = last >> SEQUENCE_BITS;
+
+            let next = if ts < last_ts {
+                return Err(NsqError::TimeBackwards);
+            } else if ts == last_ts {
+                if last & SEQUENCE_MASK == SEQUENCE_MASK {
+                    return Err(NsqError::SequenceExpired);
+                }
+                last + 1
+            } else {
+                ts << SEQUENCE_BITS
+            };
+
+            match self
+                .last
+                .compare_exchange_weak(last, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    let ts = next >> SEQUENCE_BITS;
+                    let seq = next & SEQUENCE_MASK;
+                    return Ok(Guid(
+                        (ts << TIMESTAMP_SHIFT) | (self.node_id << NODE_ID_SHIFT) | seq,
+                    ));
+                }
+                // 其他线程已经生成了新的ID，基于最新的状态重试
+                Err(actual) => last = actual,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_and_increasing() {
+        let factory = GuidFactory::new(1);
+        let mut last = 0;
+        let mut count = 0;
+        while count < 10000 {
+            match factory.new_guid() {
+                Ok(Guid(id)) => {
+                    assert!(id > last, "{id} <= {last}");
+                    assert_eq!((id >> NODE_ID_SHIFT) & NODE_ID_MASK, 1);
+                    last = id;
+                    count += 1;
+                }
+                // 同一毫秒内序列号用完，等待下一个时间戳
+                Err(NsqError::SequenceExpired) => {}
+                Err(e) => panic!("{e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn sequence_rollover() {
+        let factory = GuidFactory::new(0);
+        let ts = 1000;
+        for seq in 0..=SEQUENCE_MASK {
+            let Guid(id) = factory.next_guid(ts).unwrap();
+            assert_eq!(id, ts << TIMESTAMP_SHIFT | seq);
+        }
+        assert!(matches!(
+            factory.next_guid(ts),
+            Err(NsqError::SequenceExpired)
+        ));
+
+        // 下一个时间戳从0开始
+        let Guid(id) = factory.next_guid(ts + 1).unwrap();
+        assert_eq!(id, (ts + 1) << TIMESTAMP_SHIFT);
+    }
+
+    #[test]
+    fn time_backwards() {
+        let factory = GuidFactory::new(0);
+        factory.next_guid(1000).unwrap();
+        assert!(matches!(
+            factory.next_guid(999),
+            Err(NsqError::TimeBackwards)
+        ));
+        assert!(factory.next_guid(1000).is_ok());
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(&Guid(0x0123456789abcdef).hex(), b"0123456789abcdef");
+    }
+}
@@ -0,0 +1,1078 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Query, State},
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Serialize;
+use serde_json::json;
+use tokio::{io::BufReader, select};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+use super::{
+    channel::Channel,
+    message::Message,
+    nsqd::NSQD,
+    options::TLS_REQUIRED,
+    protocol_v2::read_mpub,
+    stats::{ChannelStats, ClientStats, TopicStats},
+    topic::Topic,
+};
+use crate::{
+    http_api::{
+        get_topic_channel_args, get_topic_name_from_query, parse_bool, serve_conn, HttpError,
+        HttpResult,
+    },
+    shutdown::Shutdown,
+};
+
+// tls不为空时在https_addr上提供HTTPS服务，否则在http_addr上提供HTTP服务
+pub(super) async fn serve(nsqd: Arc<NSQD>, tls: Option<TlsAcceptor>, mut shutdown: Shutdown) {
+    let (name, addr) = match tls {
+        Some(_) => ("HTTPS", nsqd.https_addr().unwrap()),
+        None => ("HTTP", nsqd.http_addr()),
+    };
+    info!("{name}: listening on {addr}");
+
+    let router = router(nsqd.clone(), tls.is_some());
+    let tracker = TaskTracker::new();
+    loop {
+        let res = select! {
+            res = nsqd.https_accept(), if tls.is_some() => res,
+            res = nsqd.http_accept(), if tls.is_none() => res,
+            _ = shutdown.recv() => {
+                info!("{name}: closing {addr}");
+                break;
+            }
+        };
+        let (conn, client_addr) = match res {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("{name}: accept error - {e}");
+                continue;
+            }
+        };
+
+        let router = router.clone();
+        let shutdown = shutdown.clone();
+        match tls.clone() {
+            Some(acceptor) => tracker.spawn(async move {
+                match acceptor.accept(conn).await {
+                    Ok(conn) => serve_conn(conn, router, shutdown).await,
+                    Err(e) => warn!("{name}: TLS handshake with {client_addr} failed - {e}"),
+                }
+            }),
+            None => tracker.spawn(serve_conn(conn, router, shutdown)),
+        };
+    }
+
+    tracker.close();
+    tracker.wait().await;
+}
+
+fn router(nsqd: Arc<NSQD>, tls_enabled: bool) -> Router {
+    // 要求使用TLS时，明文的HTTP接口拒绝所有请求
+    if !tls_enabled && nsqd.get_opts().tls_required == TLS_REQUIRED {
+        return Router::new()
+            .fallback(|State(nsqd): State<Arc<NSQD>>| async move {
+                let https_port = nsqd.https_addr().map_or(0, |addr| addr.port());
+                (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({ "message": "TLS_REQUIRED", "https_port": https_port })),
+                )
+            })
+            .with_state(nsqd);
+    }
+
+    Router::new()
+        .route("/ping", get(ping))
+        .route("/info", get(info))
+        .route("/stats", get(do_stats))
+        .route("/metrics", get(do_metrics))
+        .route("/pub", post(do_pub))
+        .route("/mpub", post(do_mpub))
+        .route("/dpub", post(do_dpub))
+        .route("/topic/create", post(do_create_topic))
+        .route("/topic/delete", post(do_delete_topic))
+        .route("/topic/empty", post(do_empty_topic))
+        .route("/topic/pause", post(do_pause_topic))
+        .route("/topic/unpause", post(do_pause_topic))
+        .route("/channel/create", post(do_create_channel))
+        .route("/channel/delete", post(do_delete_channel))
+        .route("/channel/empty", post(do_empty_channel))
+        .route("/channel/pause", post(do_pause_channel))
+        .route("/channel/unpause", post(do_pause_channel))
+        .fallback(|| async { HttpError::new(StatusCode::NOT_FOUND, "NOT_FOUND") })
+        .with_state(nsqd)
+}
+
+async fn ping() -> &'static str {
+    "OK"
+}
+
+#[derive(Serialize)]
+struct InfoResponse {
+    version: &'static str,
+    broadcast_address: String,
+    hostname: String,
+    http_port: u16,
+    tcp_port: u16,
+    start_time: u64,
+}
+
+async fn info(State(nsqd): State<Arc<NSQD>>) -> Json<InfoResponse> {
+    let hostname = hostname::get()
+        .map(|h| h.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Json(InfoResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        broadcast_address: nsqd.get_opts().broadcast_addr.clone(),
+        hostname,
+        http_port: nsqd.http_addr().port(),
+        tcp_port: nsqd.tcp_addr().port(),
+        start_time: start_time(&nsqd),
+    })
+}
+
+#[derive(Serialize)]
+struct StatsResponse {
+    version: &'static str,
+    health: &'static str,
+    start_time: u64,
+    topics: Vec<TopicStats>,
+    producers: Vec<ClientStats>,
+}
+
+async fn do_stats(
+    State(nsqd): State<Arc<NSQD>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<Response> {
+    let format = params.get("format").map_or("text", |f| f.as_str());
+    let topic = params.get("topic").map(|t| t.as_str());
+    let channel = params.get("channel").map(|c| c.as_str());
+    let include_clients = match params.get("include_clients") {
+        Some(v) => parse_bool(v)
+            .ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "INVALID_REQUEST"))?,
+        None => true,
+    };
+
+    // 指定了topic时只输出这个topic上的生产者
+    let mut producers = if include_clients {
+        nsqd.get_producer_stats()
+    } else {
+        Vec::new()
+    };
+    if let Some(topic) = topic {
+        producers.retain(|p| p.pub_counts.iter().any(|c| c.topic == topic));
+    }
+
+    let stats = StatsResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        health: "OK",
+        start_time: start_time(&nsqd),
+        topics: nsqd.get_stats(topic, channel, include_clients),
+        producers,
+    };
+
+    if format == "json" {
+        return Ok(Json(stats).into_response());
+    }
+
+    Ok(format_stats(&stats, &nsqd).into_response())
+}
+
+// 文本格式的统计信息，方便直接用curl查看
+fn format_stats(stats: &StatsResponse, nsqd: &NSQD) -> String {
+    let mut w = String::new();
+    let _ = writeln!(w, "nsqd v{}", stats.version);
+    let _ = writeln!(w, "start_time {}", stats.start_time);
+    let _ = writeln!(w, "uptime {}s", nsqd.uptime().as_secs());
+    let _ = writeln!(w, "\nHealth: {}", stats.health);
+
+    if stats.topics.is_empty() {
+        let _ = writeln!(w, "\nTopics: None");
+    } else {
+        let _ = write!(w, "\nTopics:");
+        for t in &stats.topics {
+            let prefix = if t.paused { "*P " } else { "   " };
+            let _ = writeln!(
+                w,
+                "\n{prefix}[{:<15}] depth: {:<5} be-depth: {:<5} msgs: {:<8}",
+                t.topic_name, t.depth, t.backend_depth, t.message_count
+            );
+            for c in &t.channels {
+                let prefix = if c.paused { "   *P " } else { "      " };
+                let _ = writeln!(
+                    w,
+                    "{prefix}[{:<25}] depth: {:<5} be-depth: {:<5} inflt: {:<4} def: {:<4} re-q: {:<5} timeout: {:<5} msgs: {:<8}",
+                    c.channel_name,
+                    c.depth,
+                    c.backend_depth,
+                    c.in_flight_count,
+                    c.deferred_count,
+                    c.requeue_count,
+                    c.timeout_count,
+                    c.message_count
+                );
+                for client in &c.clients {
+                    let _ = writeln!(w, "        {client}");
+                }
+            }
+        }
+    }
+
+    if stats.producers.is_empty() {
+        let _ = writeln!(w, "\nProducers: None");
+    } else {
+        let _ = writeln!(w, "\nProducers:");
+        for client in &stats.producers {
+            let _ = writeln!(w, "   {client}");
+        }
+    }
+
+    w
+}
+
+// Prometheus文本格式的指标，计数类的指标由Prometheus计算速率
+async fn do_metrics(State(nsqd): State<Arc<NSQD>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        format_metrics(&nsqd),
+    )
+        .into_response()
+}
+
+fn format_metrics(nsqd: &NSQD) -> String {
+    let topics = nsqd.get_stats(None, None, false);
+    let channels: Vec<_> = topics
+        .iter()
+        .flat_map(|t| t.channels.iter().map(move |c| (t, c)))
+        .collect();
+
+    let mut w = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+        let _ = writeln!(w, "# HELP {name} {help}");
+        let _ = writeln!(w, "# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            if labels.is_empty() {
+                let _ = writeln!(w, "{name} {value}");
+            } else {
+                let _ = writeln!(w, "{name}{{{labels}}} {value}");
+            }
+        }
+    };
+    let topic = |f: fn(&TopicStats) -> f64| {
+        topics
+            .iter()
+            .map(|t| (format!("topic=\"{}\"", escape_label(&t.topic_name)), f(t)))
+            .collect()
+    };
+    let channel = |f: fn(&ChannelStats) -> f64| {
+        channels
+            .iter()
+            .map(|(t, c)| {
+                let labels = format!(
+                    "topic=\"{}\",channel=\"{}\"",
+                    escape_label(&t.topic_name),
+                    escape_label(&c.channel_name)
+                );
+                (labels, f(c))
+            })
+            .collect()
+    };
+
+    let version = format!("version=\"{}\"", escape_label(env!("CARGO_PKG_VERSION")));
+    metric("nsqd_info", "gauge", "nsqd version", vec![(version, 1.0)]);
+    metric(
+        "nsqd_start_time_seconds",
+        "gauge",
+        "Start time of nsqd since unix epoch in seconds",
+        vec![(String::new(), start_time(nsqd) as f64)],
+    );
+    metric(
+        "nsqd_uptime_seconds",
+        "gauge",
+        "Number of seconds since nsqd started",
+        vec![(String::new(), nsqd.uptime().as_secs_f64())],
+    );
+    metric(
+        "nsqd_clients",
+        "gauge",
+        "Number of connected TCP clients",
+        vec![(String::new(), nsqd.client_count() as f64)],
+    );
+    metric(
+        "nsqd_topics",
+        "gauge",
+        "Number of topics",
+        vec![(String::new(), topics.len() as f64)],
+    );
+    metric(
+        "nsqd_channels",
+        "gauge",
+        "Number of channels",
+        vec![(String::new(), channels.len() as f64)],
+    );
+
+    metric(
+        "nsqd_topic_depth",
+        "gauge",
+        "Number of messages queued in the topic (memory and disk)",
+        topic(|t| t.depth as f64),
+    );
+    metric(
+        "nsqd_topic_backend_depth",
+        "gauge",
+        "Number of messages queued on disk for the topic",
+        topic(|t| t.backend_depth as f64),
+    );
+    metric(
+        "nsqd_topic_messages_total",
+        "counter",
+        "Number of messages published to the topic",
+        topic(|t| t.message_count as f64),
+    );
+    metric(
+        "nsqd_topic_message_bytes_total",
+        "counter",
+        "Number of message bytes published to the topic",
+        topic(|t| t.message_bytes as f64),
+    );
+    metric(
+        "nsqd_topic_paused",
+        "gauge",
+        "Whether the topic is paused",
+        topic(|t| t.paused as u8 as f64),
+    );
+
+    metric(
+        "nsqd_channel_depth",
+        "gauge",
+        "Number of messages queued in the channel (memory and disk)",
+        channel(|c| c.depth as f64),
+    );
+    metric(
+        "nsqd_channel_backend_depth",
+        "gauge",
+        "Number of messages queued on disk for the channel",
+        channel(|c| c.backend_depth as f64),
+    );
+    metric(
+        "nsqd_channel_in_flight",
+        "gauge",
+        "Number of messages sent to clients and not yet finished",
+        channel(|c| c.in_flight_count as f64),
+    );
+    metric(
+        "nsqd_channel_deferred",
+        "gauge",
+        "Number of deferred messages in the channel",
+        channel(|c| c.deferred_count as f64),
+    );
+    metric(
+        "nsqd_channel_messages_total",
+        "counter",
+        "Number of messages delivered to the channel",
+        channel(|c| c.message_count as f64),
+    );
+    metric(
+        "nsqd_channel_requeued_total",
+        "counter",
+        "Number of messages requeued in the channel",
+        channel(|c| c.requeue_count as f64),
+    );
+    metric(
+        "nsqd_channel_timed_out_total",
+        "counter",
+        "Number of in-flight messages that timed out in the channel",
+        channel(|c| c.timeout_count as f64),
+    );
+    metric(
+        "nsqd_channel_clients",
+        "gauge",
+        "Number of clients subscribed to the channel",
+        channel(|c| c.client_count as f64),
+    );
+    metric(
+        "nsqd_channel_paused",
+        "gauge",
+        "Whether the channel is paused",
+        channel(|c| c.paused as u8 as f64),
+    );
+
+    w
+}
+
+// 标签值中的反斜杠、双引号和换行需要转义。topic和channel的名称虽然已经校验过，
+// 这里仍然转义，避免以后放宽名称规则时输出非法的指标
+fn escape_label(v: &str) -> String {
+    let mut escaped = String::with_capacity(v.len());
+    for c in v.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn start_time(nsqd: &NSQD) -> u64 {
+    (SystemTime::now() - nsqd.uptime())
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+async fn do_pub(
+    State(nsqd): State<Arc<NSQD>>,
+    Query(params): Query<HashMap<String, String>>,
+    body: Body,
+) -> HttpResult<&'static str> {
+    let defer = match params.get("defer") {
+        Some(defer) => parse_defer(&nsqd, defer)?,
+        None => None,
+    };
+    publish(&nsqd, &params, body, defer).await
+}
+
+async fn do_dpub(
+    State(nsqd): State<Arc<NSQD>>,
+    Query(params): Query<HashMap<String, String>>,
+    body: Body,
+) -> HttpResult<&'static str> {
+    let defer = params
+        .get("defer")
+        .ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "MISSING_ARG_DEFER"))?;
+    let defer = parse_defer(&nsqd, defer)?;
+    publish(&nsqd, &params, body, defer).await
+}
+
+async fn publish(
+    nsqd: &Arc<NSQD>,
+    params: &HashMap<String, String>,
+    body: Body,
+    defer: Option<Duration>,
+) -> HttpResult<&'static str> {
+    let max_msg_size = nsqd.get_opts().max_msg_size as usize;
+    let body = to_bytes(body, max_msg_size)
+        .await
+        .map_err(|_| HttpError::new(StatusCode::PAYLOAD_TOO_LARGE, "MSG_TOO_BIG"))?;
+    if body.is_empty() {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "MSG_EMPTY"));
+    }
+
+    let topic = get_topic_from_query(nsqd, params)?;
+
+    let mut msg = Message::new(topic.generate_id().await, body.to_vec());
+    msg.deferred = defer;
+    topic
+        .put_message(msg)
+        .await
+        .map_err(|_| HttpError::new(StatusCode::SERVICE_UNAVAILABLE, "EXITING"))?;
+
+    Ok("OK")
+}
+
+async fn do_mpub(
+    State(nsqd): State<Arc<NSQD>>,
+    Query(params): Query<HashMap<String, String>>,
+    body: Body,
+) -> HttpResult<&'static str> {
+    let opts = nsqd.get_opts();
+    let body = to_bytes(body, opts.max_body_size as usize)
+        .await
+        .map_err(|_| HttpError::new(StatusCode::PAYLOAD_TOO_LARGE, "BODY_TOO_BIG"))?;
+
+    let topic = get_topic_from_query(&nsqd, &params)?;
+
+    let binary = params
+        .get("binary")
+        .is_some_and(|v| parse_bool(v) == Some(true));
+
+    let msgs = if binary {
+        let mut reader = BufReader::new(&body[..]);
+        read_mpub(&mut reader, opts.max_msg_size, body.len() as u32, &topic)
+            .await
+            .map_err(|e| match e.code() {
+                // 消息体格式错误都当作消息过大处理，和golang版本保持一致
+                Some(code) => HttpError::new(StatusCode::PAYLOAD_TOO_LARGE, code.http_text()),
+                None => e.into(),
+            })?
+    } else {
+        // 每行是一条消息，忽略空行
+        let mut msgs = Vec::new();
+        for block in body.split(|&b| b == b'\n') {
+            if block.is_empty() {
+                continue;
+            }
+            if block.len() > opts.max_msg_size as usize {
+                return Err(HttpError::new(StatusCode::PAYLOAD_TOO_LARGE, "MSG_TOO_BIG"));
+            }
+            msgs.push(Message::new(topic.generate_id().await, block.to_vec()));
+        }
+        msgs
+    };
+
+    if msgs.is_empty() {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "MSG_EMPTY"));
+    }
+
+    topic
+        .put_messages(msgs)
+        .await
+        .map_err(|_| HttpError::new(StatusCode::SERVICE_UNAVAILABLE, "EXITING"))?;
+
+    Ok("OK")
+}
+
+async fn do_create_topic(
+    State(nsqd): State<Arc<NSQD>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    get_topic_from_query(&nsqd, &params)?;
+    Ok(())
+}
+
+async fn do_delete_topic(
+    State(nsqd): State<Arc<NSQD>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let topic_name = get_topic_name_from_query(&params)?;
+    nsqd.delete_existing_topic(topic_name)
+        .await
+        .map_err(HttpError::from)
+}
+
+async fn do_empty_topic(
+    State(nsqd): State<Arc<NSQD>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let topic = get_existing_topic_from_query(&nsqd, &params)?;
+    topic.empty().await.map_err(HttpError::from)
+}
+
+async fn do_pause_topic(
+    State(nsqd): State<Arc<NSQD>>,
+    uri: Uri,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let topic = get_existing_topic_from_query(&nsqd, &params)?;
+    if uri.path().contains("unpause") {
+        topic.unpause();
+    } else {
+        topic.pause();
+    }
+    Ok(())
+}
+
+async fn do_create_channel(
+    State(nsqd): State<Arc<NSQD>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let (topic_name, channel_name) = get_topic_channel_args(&params)?;
+    nsqd.get_topic(topic_name).get_channel(channel_name);
+    Ok(())
+}
+
+async fn do_delete_channel(
+    State(nsqd): State<Arc<NSQD>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let (topic, channel_name) = get_existing_topic_channel_args(&nsqd, &params)?;
+    topic
+        .delete_existing_channel(channel_name)
+        .await
+        .map_err(HttpError::from)
+}
+
+async fn do_empty_channel(
+    State(nsqd): State<Arc<NSQD>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let channel = get_existing_channel_from_query(&nsqd, &params)?;
+    channel.empty().await.map_err(HttpError::from)
+}
+
+async fn do_pause_channel(
+    State(nsqd): State<Arc<NSQD>>,
+    uri: Uri,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let channel = get_existing_channel_from_query(&nsqd, &params)?;
+    if uri.path().contains("unpause") {
+        channel.unpause();
+    } else {
+        channel.pause();
+    }
+    Ok(())
+}
+
+fn get_topic_from_query(
+    nsqd: &Arc<NSQD>,
+    params: &HashMap<String, String>,
+) -> HttpResult<Arc<Topic>> {
+    let topic_name = get_topic_name_from_query(params)?;
+    Ok(nsqd.get_topic(topic_name))
+}
+
+fn get_existing_topic_from_query(
+    nsqd: &NSQD,
+    params: &HashMap<String, String>,
+) -> HttpResult<Arc<Topic>> {
+    let topic_name = get_topic_name_from_query(params)?;
+    nsqd.get_existing_topic(topic_name)
+        .ok_or_else(|| HttpError::new(StatusCode::NOT_FOUND, "TOPIC_NOT_FOUND"))
+}
+
+fn get_existing_channel_from_query(
+    nsqd: &NSQD,
+    params: &HashMap<String, String>,
+) -> HttpResult<Arc<Channel>> {
+    let (topic, channel_name) = get_existing_topic_channel_args(nsqd, params)?;
+    topic
+        .get_existing_channel(channel_name)
+        .ok_or_else(|| HttpError::new(StatusCode::NOT_FOUND, "CHANNEL_NOT_FOUND"))
+}
+
+fn get_existing_topic_channel_args<'a>(
+    nsqd: &NSQD,
+    params: &'a HashMap<String, String>,
+) -> HttpResult<(Arc<Topic>, &'a str)> {
+    let (topic_name, channel_name) = get_topic_channel_args(params)?;
+    let topic = nsqd
+        .get_existing_topic(topic_name)
+        .ok_or_else(|| HttpError::new(StatusCode::NOT_FOUND, "TOPIC_NOT_FOUND"))?;
+    Ok((topic, channel_name))
+}
+
+// defer的单位为毫秒，不能超过max_req_timeout
+fn parse_defer(nsqd: &NSQD, defer: &str) -> HttpResult<Option<Duration>> {
+    let defer: u64 = defer
+        .parse()
+        .map_err(|_| HttpError::new(StatusCode::BAD_REQUEST, "INVALID_DEFER"))?;
+    let defer = Duration::from_millis(defer);
+    if defer > nsqd.get_opts().max_req_timeout {
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "INVALID_DEFER"));
+    }
+
+    Ok((!defer.is_zero()).then_some(defer))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use serde_json::Value;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::nsqd::test_util::{test_options, TestNsqd};
+
+    async fn post(
+        server: &TestNsqd,
+        path: &str,
+        body: impl Into<reqwest::Body>,
+    ) -> (StatusCode, String) {
+        let url = format!("http://{}{path}", server.nsqd.http_addr());
+        let resp = reqwest::Client::new()
+            .post(url)
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        (resp.status(), resp.text().await.unwrap())
+    }
+
+    async fn get(server: &TestNsqd, path: &str) -> (StatusCode, String) {
+        let resp = reqwest::get(format!("http://{}{path}", server.nsqd.http_addr()))
+            .await
+            .unwrap();
+        (resp.status(), resp.text().await.unwrap())
+    }
+
+    async fn get_json(server: &TestNsqd, path: &str) -> Value {
+        let (status, body) = get(server, path).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        serde_json::from_str(&body).unwrap()
+    }
+
+    // 错误以{"message": "..."}的形式返回
+    fn message(body: &str) -> String {
+        let v: Value = serde_json::from_str(body).unwrap();
+        v["message"].as_str().unwrap().to_owned()
+    }
+
+    // 等待条件成立，消息由topic的message pump异步复制到channel
+    async fn wait_for(cond: impl Fn() -> bool) {
+        let start = std::time::Instant::now();
+        while !cond() {
+            assert!(start.elapsed() < Duration::from_secs(3), "timed out");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn recv_bodies(channel: &Channel, n: usize) -> Vec<Vec<u8>> {
+        let mut bodies = Vec::new();
+        for _ in 0..n {
+            bodies.push(channel.memory_msg_chan().recv().await.unwrap().body);
+        }
+        bodies
+    }
+
+    #[tokio::test]
+    async fn publish() {
+        let dir = TempDir::new().unwrap();
+        let mut opts = test_options(dir.path());
+        opts.max_msg_size = 4;
+        opts.max_body_size = 16;
+        let server = TestNsqd::start(opts).await;
+        let channel = server.nsqd.get_topic("t").get_channel("c");
+
+        assert_eq!(
+            post(&server, "/pub?topic=t", "abcd").await,
+            (StatusCode::OK, "OK".to_owned())
+        );
+        assert_eq!(recv_bodies(&channel, 1).await, [b"abcd"]);
+
+        let (status, body) = post(&server, "/pub?topic=t", "abcde").await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(message(&body), "MSG_TOO_BIG");
+        let (status, body) = post(&server, "/pub?topic=t", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "MSG_EMPTY");
+        let (status, body) = post(&server, "/pub", "a").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "MISSING_ARG_TOPIC");
+        let (status, body) = post(&server, "/pub?topic=a!", "a").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "INVALID_TOPIC");
+
+        // 文本格式每行一条消息，忽略空行
+        let (status, _) = post(&server, "/mpub?topic=t", "a\n\nbc\nd\n").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(recv_bodies(&channel, 3).await, [&b"a"[..], b"bc", b"d"]);
+
+        let (status, body) = post(&server, "/mpub?topic=t", "a\nabcde").await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(message(&body), "MSG_TOO_BIG");
+        let (status, body) = post(&server, "/mpub?topic=t", "a\nb\nc\nd\ne\nf\ng\nh\ni").await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(message(&body), "BODY_TOO_BIG");
+        let (status, body) = post(&server, "/mpub?topic=t", "\n\n").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "MSG_EMPTY");
+
+        // 二进制格式：消息数量，然后是每条消息的长度和内容
+        let binary = |msgs: &[&[u8]]| {
+            let mut body = (msgs.len() as u32).to_be_bytes().to_vec();
+            for msg in msgs {
+                body.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+                body.extend_from_slice(msg);
+            }
+            body
+        };
+        let (status, _) = post(&server, "/mpub?topic=t&binary=true", binary(&[b"x", b"yz"])).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(recv_bodies(&channel, 2).await, [&b"x"[..], b"yz"]);
+
+        let (status, body) = post(&server, "/mpub?topic=t&binary=true", binary(&[b"abcde"])).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(message(&body), "BAD_MESSAGE");
+        let (status, body) = post(&server, "/mpub?topic=t&binary=true", binary(&[])).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(message(&body), "BAD_BODY");
+        assert_eq!(channel.message_count(), 6);
+
+        // defer的单位为毫秒
+        let (status, _) = post(&server, "/dpub?topic=t&defer=60000", "d").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = post(&server, "/pub?topic=t&defer=60000", "d").await;
+        assert_eq!(status, StatusCode::OK);
+        wait_for(|| channel.deferred_count() == 2).await;
+        assert_eq!(channel.depth(), 0);
+
+        let (status, body) = post(&server, "/dpub?topic=t", "d").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "MISSING_ARG_DEFER");
+        for defer in ["-1", "x", "3600001"] {
+            let (status, body) = post(&server, &format!("/dpub?topic=t&defer={defer}"), "d").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(message(&body), "INVALID_DEFER");
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn topic_and_channel_admin() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let nsqd = &server.nsqd;
+
+        for path in [
+            "/topic/delete",
+            "/topic/empty",
+            "/topic/pause",
+            "/topic/unpause",
+        ] {
+            let (status, body) = post(&server, &format!("{path}?topic=t"), "").await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{path}");
+            assert_eq!(message(&body), "TOPIC_NOT_FOUND");
+        }
+        let (status, body) = post(&server, "/topic/create", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "MISSING_ARG_TOPIC");
+
+        assert_eq!(
+            post(&server, "/topic/create?topic=t", "").await.0,
+            StatusCode::OK
+        );
+        let topic = nsqd.get_existing_topic("t").unwrap();
+
+        // 没有channel时消息留在topic中
+        post(&server, "/pub?topic=t", "a").await;
+        assert_eq!(topic.depth(), 1);
+        assert_eq!(
+            post(&server, "/topic/empty?topic=t", "").await.0,
+            StatusCode::OK
+        );
+        assert_eq!(topic.depth(), 0);
+
+        assert_eq!(
+            post(&server, "/topic/pause?topic=t", "").await.0,
+            StatusCode::OK
+        );
+        assert!(topic.is_paused());
+        assert_eq!(
+            post(&server, "/topic/unpause?topic=t", "").await.0,
+            StatusCode::OK
+        );
+        assert!(!topic.is_paused());
+
+        for path in [
+            "/channel/delete",
+            "/channel/empty",
+            "/channel/pause",
+            "/channel/unpause",
+        ] {
+            let (status, body) = post(&server, &format!("{path}?topic=t&channel=c"), "").await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{path}");
+            assert_eq!(message(&body), "CHANNEL_NOT_FOUND");
+            let (status, body) = post(&server, &format!("{path}?topic=x&channel=c"), "").await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{path}");
+            assert_eq!(message(&body), "TOPIC_NOT_FOUND");
+        }
+        let (status, body) = post(&server, "/channel/create?topic=t", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "MISSING_ARG_CHANNEL");
+        let (status, body) = post(&server, "/channel/create?topic=t&channel=c!", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "INVALID_CHANNEL");
+
+        let (status, _) = post(&server, "/channel/create?topic=t&channel=c", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let channel = topic.get_existing_channel("c").unwrap();
+
+        post(&server, "/pub?topic=t", "a").await;
+        wait_for(|| channel.depth() == 1).await;
+        let (status, _) = post(&server, "/channel/empty?topic=t&channel=c", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(channel.depth(), 0);
+
+        let (status, _) = post(&server, "/channel/pause?topic=t&channel=c", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(channel.is_paused());
+        let (status, _) = post(&server, "/channel/unpause?topic=t&channel=c", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!channel.is_paused());
+
+        let (status, _) = post(&server, "/channel/delete?topic=t&channel=c", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(topic.get_existing_channel("c").is_none());
+        assert!(channel.is_exiting());
+
+        assert_eq!(
+            post(&server, "/topic/delete?topic=t", "").await.0,
+            StatusCode::OK
+        );
+        assert!(nsqd.get_existing_topic("t").is_none());
+        assert!(topic.is_exiting());
+
+        // 未知的路径
+        assert_eq!(
+            post(&server, "/topic/foo?topic=t", "").await.0,
+            StatusCode::NOT_FOUND
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let (server, _dir) = TestNsqd::start_default().await;
+
+        let (status, text) = get(&server, "/stats").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            text.starts_with(&format!("nsqd v{}\n", env!("CARGO_PKG_VERSION"))),
+            "{text}"
+        );
+        assert!(text.contains("\nHealth: OK\n"), "{text}");
+        assert!(text.contains("\nTopics: None\n"), "{text}");
+        assert!(text.contains("\nProducers: None\n"), "{text}");
+
+        let topic = server.nsqd.get_topic("a");
+        topic.get_channel("y");
+        server.nsqd.get_topic("b");
+
+        let mut producer = server.connect().await;
+        producer.command_with_body("PUB a", b"m").await;
+        assert_eq!(producer.read_frame().await.unwrap().1, b"OK");
+        // x在发布之后才创建，只有y收到了消息
+        let mut consumer = server.connect().await;
+        consumer.command("SUB a x").await;
+        assert_eq!(consumer.read_frame().await.unwrap().1, b"OK");
+
+        // topic和channel按名称排序
+        let stats = get_json(&server, "/stats?format=json").await;
+        assert_eq!(stats["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(stats["health"], "OK");
+        let topics = stats["topics"].as_array().unwrap();
+        assert_eq!(topics.len(), 2);
+        assert_eq!(topics[0]["topic_name"], "a");
+        assert_eq!(topics[0]["message_count"], 1);
+        assert_eq!(topics[1]["topic_name"], "b");
+        let channels = topics[0]["channels"].as_array().unwrap();
+        assert_eq!(channels[0]["channel_name"], "x");
+        assert_eq!(channels[0]["client_count"], 1);
+        assert_eq!(channels[0]["clients"].as_array().unwrap().len(), 1);
+        assert_eq!(channels[1]["channel_name"], "y");
+        let producers = stats["producers"].as_array().unwrap();
+        assert_eq!(producers.len(), 1);
+        assert_eq!(producers[0]["pub_counts"][0]["topic"], "a");
+        assert_eq!(producers[0]["pub_counts"][0]["count"], 1);
+
+        // 只输出指定topic上的生产者
+        let stats = get_json(&server, "/stats?format=json&topic=b").await;
+        assert_eq!(stats["topics"].as_array().unwrap().len(), 1);
+        assert_eq!(stats["topics"][0]["topic_name"], "b");
+        assert!(stats["producers"].as_array().unwrap().is_empty());
+
+        let stats = get_json(&server, "/stats?format=json&topic=a&channel=y").await;
+        let channels = stats["topics"][0]["channels"].as_array().unwrap();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0]["channel_name"], "y");
+        assert_eq!(stats["producers"].as_array().unwrap().len(), 1);
+
+        let stats = get_json(&server, "/stats?format=json&topic=none").await;
+        assert!(stats["topics"].as_array().unwrap().is_empty());
+
+        // 不输出客户端时仍然统计客户端数量
+        let stats = get_json(&server, "/stats?format=json&include_clients=false").await;
+        let channel = &stats["topics"][0]["channels"][0];
+        assert_eq!(channel["client_count"], 1);
+        assert!(channel["clients"].as_array().unwrap().is_empty());
+        assert!(stats["producers"].as_array().unwrap().is_empty());
+
+        let (status, body) = get(&server, "/stats?include_clients=maybe").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "INVALID_REQUEST");
+
+        let (_, text) = get(&server, "/stats?topic=a").await;
+        assert!(
+            text.contains("\nTopics:\n   [a              ] depth: 0"),
+            "{text}"
+        );
+        assert!(
+            text.contains("      [x                        ] depth: 0"),
+            "{text}"
+        );
+        assert!(
+            text.contains("      [y                        ] depth: 1"),
+            "{text}"
+        );
+        assert!(!text.contains("[b "), "{text}");
+        let consumer_port = consumer.local_addr().port();
+        assert!(text.contains(&format!(":{consumer_port} ")), "{text}");
+        assert!(text.contains("\nProducers:\n"), "{text}");
+        assert!(text.contains("topics: a=1 "), "{text}");
+
+        let (_, text) = get(&server, "/stats?topic=a&include_clients=false").await;
+        assert!(!text.contains(&format!(":{consumer_port} ")), "{text}");
+        assert!(text.contains("\nProducers: None\n"), "{text}");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let topic = server.nsqd.get_topic("t");
+        let channel = topic.get_channel("c");
+        server.nsqd.get_topic("e#ephemeral").pause();
+        post(&server, "/mpub?topic=t", "ab\ncd").await;
+        wait_for(|| channel.depth() == 2).await;
+
+        let resp = reqwest::get(format!("http://{}/metrics", server.nsqd.http_addr()))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            "text/plain; version=0.0.4"
+        );
+        let text = resp.text().await.unwrap();
+        let lines: Vec<_> = text.lines().collect();
+
+        // 每个指标先输出HELP和TYPE，然后是样本
+        let version = env!("CARGO_PKG_VERSION");
+        for expected in [
+            "# HELP nsqd_info nsqd version",
+            "# TYPE nsqd_info gauge",
+            &format!("nsqd_info{{version=\"{version}\"}} 1"),
+            "# TYPE nsqd_topics gauge",
+            "nsqd_topics 2",
+            "nsqd_channels 1",
+            "# HELP nsqd_topic_messages_total Number of messages published to the topic",
+            "# TYPE nsqd_topic_messages_total counter",
+            "nsqd_topic_messages_total{topic=\"t\"} 2",
+            "nsqd_topic_messages_total{topic=\"e#ephemeral\"} 0",
+            "nsqd_topic_message_bytes_total{topic=\"t\"} 4",
+            "nsqd_topic_paused{topic=\"e#ephemeral\"} 1",
+            "nsqd_topic_paused{topic=\"t\"} 0",
+            "# TYPE nsqd_channel_depth gauge",
+            "nsqd_channel_depth{topic=\"t\",channel=\"c\"} 2",
+            "# TYPE nsqd_channel_messages_total counter",
+            "nsqd_channel_messages_total{topic=\"t\",channel=\"c\"} 2",
+            "# TYPE nsqd_channel_requeued_total counter",
+            "nsqd_channel_requeued_total{topic=\"t\",channel=\"c\"} 0",
+        ] {
+            assert!(lines.contains(&expected), "{expected}\n{text}");
+        }
+        let pos = |line: &str| lines.iter().position(|l| *l == line).unwrap();
+        let help =
+            pos("# HELP nsqd_topic_depth Number of messages queued in the topic (memory and disk)");
+        assert_eq!(pos("# TYPE nsqd_topic_depth gauge"), help + 1);
+        assert!(pos("nsqd_topic_depth{topic=\"t\"} 0") > help + 1);
+
+        server.stop().await;
+    }
+
+    #[test]
+    fn label_escaping() {
+        assert_eq!(escape_label("t#ephemeral"), "t#ephemeral");
+        assert_eq!(escape_label(r#"a\b"c"#), r#"a\\b\"c"#);
+        assert_eq!(escape_label("a\nb"), r"a\nb");
+    }
+}
@@ -0,0 +1,272 @@
+use std::{io, sync::Arc, time::Duration};
+
+use tokio::{
+    select,
+    time::{interval_at, Instant},
+};
+use tracing::{debug, error, info};
+
+use super::{
+    lookup_peer::{self, IdentifyInfo, LookupPeer},
+    nsqd::{NotifyType, NSQD},
+};
+use crate::{common::Result, errors::ErrorCode, shutdown::Shutdown};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+impl NSQD {
+    // 连接所有的nsqlookupd，定时发送PING，topic/channel创建或删除时发送REGISTER/UNREGISTER
+    pub(super) async fn lookup_loop(self: Arc<Self>, mut shutdown: Shutdown) {
+        let mut notify_rx = self.take_notify_rx();
+        let hostname = hostname::get()
+            .map(|h| h.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let opts = self.get_opts();
+        let mut lookup_peers: Vec<LookupPeer> = Vec::new();
+        for addr in &opts.nsq_lookup_tcp_addrs {
+            if lookup_peers.iter().any(|p| p.to_string() == *addr) {
+                continue;
+            }
+            info!("LOOKUP({addr}): adding peer");
+            let mut lookup_peer = LookupPeer::new(addr, opts.max_body_size);
+            if let Err(e) = self.connect_lookup_peer(&mut lookup_peer, &hostname).await {
+                error!("LOOKUPD({lookup_peer}): failed to connect - {e}");
+            }
+            lookup_peers.push(lookup_peer);
+        }
+
+        let mut ticker = interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+        loop {
+            select! {
+                _ = ticker.tick() => {
+                    // 通过读取PING的响应发现已经断开的连接
+                    for lookup_peer in &mut lookup_peers {
+                        debug!("LOOKUPD({lookup_peer}): sending heartbeat");
+                        self.lookup_command(lookup_peer, &hostname, &lookup_peer::ping())
+                            .await;
+                    }
+                }
+                Some(v) = notify_rx.recv() => {
+                    let (branch, cmd) = match v {
+                        NotifyType::Channel(channel) => {
+                            let cmd = if channel.is_exiting() {
+                                lookup_peer::unregister(channel.topic_name(), channel.name())
+                            } else {
+                                lookup_peer::register(channel.topic_name(), channel.name())
+                            };
+                            ("channel", cmd)
+                        }
+                        NotifyType::Topic(topic) => {
+                            let cmd = if topic.is_exiting() {
+                                lookup_peer::unregister(topic.name(), "")
+                            } else {
+                                lookup_peer::register(topic.name(), "")
+                            };
+                            ("topic", cmd)
+                        }
+                    };
+
+                    for lookup_peer in &mut lookup_peers {
+                        info!(
+                            "LOOKUPD({lookup_peer}): {branch} {}",
+                            String::from_utf8_lossy(&cmd).trim()
+                        );
+                        self.lookup_command(lookup_peer, &hostname, &cmd).await;
+                    }
+                }
+                _ = shutdown.recv() => break,
+            }
+        }
+
+        info!("LOOKUP: closing");
+    }
+
+    // 连接断开时先重连，再发送命令
+    async fn lookup_command(&self, lookup_peer: &mut LookupPeer, hostname: &str, cmd: &[u8]) {
+        let res = match self.connect_lookup_peer(lookup_peer, hostname).await {
+            Ok(()) => lookup_peer.command(cmd).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            error!(
+                "LOOKUPD({lookup_peer}): {} - {e}",
+                String::from_utf8_lossy(cmd).trim()
+            );
+        }
+    }
+
+    // 新建立的连接需要先IDENTIFY，再注册当前所有的topic和channel
+    async fn connect_lookup_peer(
+        &self,
+        lookup_peer: &mut LookupPeer,
+        hostname: &str,
+    ) -> Result<()> {
+        if !lookup_peer.connect().await? {
+            return Ok(());
+        }
+
+        let opts = self.get_opts();
+        let cmd = lookup_peer::identify(&IdentifyInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            tcp_port: opts.broadcast_tcp_port,
+            http_port: opts.broadcast_http_port,
+            hostname,
+            broadcast_address: &opts.broadcast_addr,
+        })
+        .map_err(io::Error::other)?;
+
+        let resp = lookup_peer.command(&cmd).await?;
+        if resp == ErrorCode::Invalid.as_str().as_bytes() {
+            lookup_peer.close();
+            return Err(io::Error::other("lookupd returned E_INVALID").into());
+        }
+
+        match serde_json::from_slice(&resp) {
+            Ok(info) => lookup_peer.info = info,
+            Err(e) => {
+                lookup_peer.close();
+                return Err(io::Error::other(format!("error parsing response - {e}")).into());
+            }
+        }
+        info!("LOOKUPD({lookup_peer}): peer info {:?}", lookup_peer.info);
+        if lookup_peer.info.broadcast_address.is_empty() {
+            error!("LOOKUPD({lookup_peer}): no broadcast address");
+        }
+
+        // 先生成所有的命令，避免发送时持有锁
+        let mut commands = Vec::new();
+        for topic in self.topics() {
+            let channels = topic.channels();
+            if channels.is_empty() {
+                commands.push(lookup_peer::register(topic.name(), ""));
+            }
+            for channel in channels {
+                commands.push(lookup_peer::register(topic.name(), channel.name()));
+            }
+        }
+
+        for cmd in commands {
+            info!(
+                "LOOKUPD({lookup_peer}): {}",
+                String::from_utf8_lossy(&cmd).trim()
+            );
+            lookup_peer.command(&cmd).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+        sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+        time::timeout,
+    };
+
+    use super::*;
+    use crate::nsqd::test_util::{test_options, TestNsqd};
+
+    // 记录收到的命令的nsqlookupd，IDENTIFY返回peer info，其他命令都返回OK。
+    // 每个连接处理max_commands个命令之后断开
+    async fn start_lookupd(max_commands: usize) -> (String, UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((conn, _)) = listener.accept().await {
+                tokio::spawn(handle_lookupd_conn(conn, tx.clone(), max_commands));
+            }
+        });
+        (addr, rx)
+    }
+
+    async fn handle_lookupd_conn(conn: TcpStream, tx: UnboundedSender<String>, max: usize) {
+        let mut conn = BufReader::new(conn);
+        let mut magic = [0; 4];
+        conn.read_exact(&mut magic).await.unwrap();
+        assert_eq!(&magic, b"  V1");
+
+        for _ in 0..max {
+            let mut line = String::new();
+            if conn.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim().to_owned();
+            let resp = if line == "IDENTIFY" {
+                let len = conn.read_u32().await.unwrap();
+                let mut body = vec![0; len as usize];
+                conn.read_exact(&mut body).await.unwrap();
+                br#"{"tcp_port":4160,"http_port":4161,"version":"1.0.0","broadcast_address":"127.0.0.1"}"#.to_vec()
+            } else {
+                b"OK".to_vec()
+            };
+            let _ = tx.send(line);
+            conn.write_u32(resp.len() as u32).await.unwrap();
+            conn.write_all(&resp).await.unwrap();
+        }
+    }
+
+    async fn next_command(rx: &mut UnboundedReceiver<String>) -> String {
+        timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("timed out waiting for lookupd command")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn register_and_unregister() {
+        let (lookupd, mut rx) = start_lookupd(usize::MAX).await;
+        let dir = TempDir::new().unwrap();
+        let mut opts = test_options(dir.path());
+        opts.nsq_lookup_tcp_addrs = vec![lookupd];
+        let server = TestNsqd::start_with(opts, |nsqd| {
+            nsqd.get_topic("existing");
+        })
+        .await;
+        let nsqd = &server.nsqd;
+
+        // 连接之后注册已经存在的topic，之后处理创建topic时的通知
+        assert_eq!(next_command(&mut rx).await, "IDENTIFY");
+        assert_eq!(next_command(&mut rx).await, "REGISTER existing");
+        assert_eq!(next_command(&mut rx).await, "REGISTER existing");
+
+        let topic = nsqd.get_topic("topic");
+        assert_eq!(next_command(&mut rx).await, "REGISTER topic");
+        topic.get_channel("channel");
+        assert_eq!(next_command(&mut rx).await, "REGISTER topic channel");
+
+        topic.delete_existing_channel("channel").await.unwrap();
+        assert_eq!(next_command(&mut rx).await, "UNREGISTER topic channel");
+        nsqd.delete_existing_topic("topic").await.unwrap();
+        assert_eq!(next_command(&mut rx).await, "UNREGISTER topic");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn reregister_after_reconnect() {
+        // 每个连接在IDENTIFY和一次REGISTER之后断开
+        let (lookupd, mut rx) = start_lookupd(2).await;
+        let dir = TempDir::new().unwrap();
+        let mut opts = test_options(dir.path());
+        opts.nsq_lookup_tcp_addrs = vec![lookupd];
+        let server = TestNsqd::start_with(opts, |nsqd| {
+            nsqd.get_topic("topic").get_channel("channel");
+        })
+        .await;
+
+        // 连接断开之后REGISTER topic发送失败，下一个命令发送之前重连，
+        // 重新IDENTIFY并注册所有的channel
+        for _ in 0..2 {
+            assert_eq!(next_command(&mut rx).await, "IDENTIFY");
+            assert_eq!(next_command(&mut rx).await, "REGISTER topic channel");
+        }
+
+        server.stop().await;
+    }
+}
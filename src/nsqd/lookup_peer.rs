@@ -0,0 +1,232 @@
+use std::{
+    fmt::{self, Display},
+    io,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+    time::timeout,
+};
+use tracing::{error, info};
+
+use crate::common::Result;
+
+const MAGIC_V1: &[u8] = b"  V1";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+// 连接失败之后的重连间隔，每次失败翻倍
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// IDENTIFY时nsqd上报给nsqlookupd的信息
+#[derive(Serialize)]
+pub(super) struct IdentifyInfo<'a> {
+    pub version: &'static str,
+    pub tcp_port: u16,
+    pub http_port: u16,
+    pub hostname: &'a str,
+    pub broadcast_address: &'a str,
+}
+
+// nsqlookupd在IDENTIFY的响应中返回的信息
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(super) struct PeerInfo {
+    pub tcp_port: u16,
+    pub http_port: u16,
+    pub version: String,
+    pub broadcast_address: String,
+}
+
+// nsqd到一个nsqlookupd的连接，断开之后在下一次发送命令时重连
+pub(super) struct LookupPeer {
+    addr: String,
+    conn: Option<BufStream<TcpStream>>,
+    max_body_size: u32,
+    pub info: PeerInfo,
+
+    backoff: Duration,
+    next_connect: Instant,
+}
+
+impl LookupPeer {
+    pub fn new(addr: &str, max_body_size: u32) -> Self {
+        Self {
+            addr: addr.to_owned(),
+            conn: None,
+            max_body_size,
+            info: PeerInfo::default(),
+            backoff: MIN_BACKOFF,
+            next_connect: Instant::now(),
+        }
+    }
+
+    // 建立连接并发送magic，返回true表示这是一个新的连接，需要重新IDENTIFY和REGISTER
+    pub async fn connect(&mut self) -> Result<bool> {
+        if self.conn.is_some() {
+            return Ok(false);
+        }
+
+        if Instant::now() < self.next_connect {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "waiting to reconnect").into());
+        }
+
+        info!("LOOKUP connecting to {}", self.addr);
+        let res = async {
+            let conn = timeout(CONNECT_TIMEOUT, TcpStream::connect(&self.addr))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??;
+            let mut conn = BufStream::new(conn);
+            conn.write_all(MAGIC_V1).await?;
+            conn.flush().await?;
+            io::Result::Ok(conn)
+        }
+        .await;
+
+        match res {
+            Ok(conn) => {
+                self.conn = Some(conn);
+                self.backoff = MIN_BACKOFF;
+                Ok(true)
+            }
+            Err(e) => {
+                self.next_connect = Instant::now() + self.backoff;
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                Err(e.into())
+            }
+        }
+    }
+
+    // 发送命令并读取响应，出错时关闭连接
+    pub async fn command(&mut self, cmd: &[u8]) -> Result<Vec<u8>> {
+        let Some(conn) = &mut self.conn else {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "not connected").into());
+        };
+
+        let res = async {
+            conn.write_all(cmd).await?;
+            conn.flush().await?;
+            read_response_bounded(conn, self.max_body_size).await
+        }
+        .await;
+
+        if res.is_err() {
+            self.close();
+        }
+        Ok(res?)
+    }
+
+    pub fn close(&mut self) {
+        if self.conn.take().is_some() {
+            error!("LOOKUPD({self}): connection closed");
+        }
+    }
+}
+
+impl Display for LookupPeer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.addr)
+    }
+}
+
+// V1协议的响应格式：[4字节长度][数据]
+async fn read_response_bounded(
+    conn: &mut BufStream<TcpStream>,
+    max_body_size: u32,
+) -> io::Result<Vec<u8>> {
+    let len = conn.read_u32().await?;
+    if len > max_body_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("response body size ({len}) is greater than limit ({max_body_size})"),
+        ));
+    }
+
+    let mut buf = vec![0; len as usize];
+    conn.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+pub(super) fn ping() -> Vec<u8> {
+    b"PING\n".to_vec()
+}
+
+pub(super) fn register(topic: &str, channel: &str) -> Vec<u8> {
+    command("REGISTER", topic, channel)
+}
+
+pub(super) fn unregister(topic: &str, channel: &str) -> Vec<u8> {
+    command("UNREGISTER", topic, channel)
+}
+
+fn command(name: &str, topic: &str, channel: &str) -> Vec<u8> {
+    if channel.is_empty() {
+        format!("{name} {topic}\n").into_bytes()
+    } else {
+        format!("{name} {topic} {channel}\n").into_bytes()
+    }
+}
+
+pub(super) fn identify(info: &IdentifyInfo) -> serde_json::Result<Vec<u8>> {
+    let body = serde_json::to_vec(info)?;
+    let mut cmd = b"IDENTIFY\n".to_vec();
+    cmd.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    cmd.extend_from_slice(&body);
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::errors::NsqError;
+
+    #[tokio::test]
+    async fn reconnect_backoff() {
+        // 拿到一个没有监听的端口
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut peer = LookupPeer::new(&addr.to_string(), 1024);
+        assert!(peer.connect().await.is_err());
+        assert_eq!(peer.backoff, MIN_BACKOFF * 2);
+
+        // 等待期间不会重连
+        match peer.connect().await {
+            Err(NsqError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::NotConnected),
+            res => panic!("unexpected result {res:?}"),
+        }
+        assert!(peer.next_connect > Instant::now());
+        assert_eq!(peer.backoff, MIN_BACKOFF * 2);
+
+        // 连续失败时重连间隔翻倍，但不超过MAX_BACKOFF
+        for _ in 0..10 {
+            peer.next_connect = Instant::now();
+            assert!(peer.connect().await.is_err());
+        }
+        assert_eq!(peer.backoff, MAX_BACKOFF);
+
+        // 连接成功之后重置重连间隔
+        let listener = TcpListener::bind(addr).await.unwrap();
+        peer.next_connect = Instant::now();
+        assert!(peer.connect().await.unwrap());
+        assert_eq!(peer.backoff, MIN_BACKOFF);
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut magic = [0; 4];
+        conn.read_exact(&mut magic).await.unwrap();
+        assert_eq!(&magic, MAGIC_V1);
+
+        // 已经连接时不需要重新IDENTIFY
+        assert!(!peer.connect().await.unwrap());
+
+        // 响应超过max_body_size时断开连接
+        conn.write_u32(4096).await.unwrap();
+        assert!(peer.command(&ping()).await.is_err());
+        assert!(peer.conn.is_none());
+    }
+}
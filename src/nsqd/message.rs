@@ -1,49 +1,42 @@
 use core::time;
 use std::time::Instant;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
 
-use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
 use tokio::io::BufWriter;
 
-use crate::common::Result;
+use crate::common::{unix_nanos, Result};
 use crate::errors::NsqError;
 
 use super::backend_queue::BackEndQueue;
 
 // use tokio::time::Instant;
 
-const MSG_ID_LENGTH: usize = 16;
-const MIN_VALID_MSG_LEN: usize = MSG_ID_LENGTH + 8 + 2; // Timestamp + Attempts
+pub(super) const MSG_ID_LENGTH: usize = 16;
+pub(super) const MIN_VALID_MSG_LEN: usize = MSG_ID_LENGTH + 8 + 2; // Timestamp + Attempts
 
 pub(super) type MessageID = [u8; MSG_ID_LENGTH];
 
+#[derive(Clone)]
 pub(super) struct Message {
     pub id: MessageID,
     pub body: Vec<u8>,
 
-    timestamp: i64,
-    attempts: u16,
+    pub timestamp: i64,
+    pub attempts: u16,
 
-    delivery_ts: Option<Instant>,
-    client_id: Option<i64>,
-    pri: i64,
-    index: isize,
-    deferred: Option<time::Duration>,
+    pub delivery_ts: Option<Instant>,
+    pub client_id: Option<i64>,
+    pub pri: i64,
+    pub index: isize,
+    pub deferred: Option<time::Duration>,
 }
 
 impl Message {
     pub fn new(id: MessageID, body: Vec<u8>) -> Self {
-        // 这里不存在panic的情况；
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap() // 这里不可能panic
-            .as_nanos() as i64;
         Self {
             id,
             body,
-            timestamp,
+            timestamp: unix_nanos(),
             attempts: 0,
             delivery_ts: None,
             client_id: None,
@@ -89,7 +82,7 @@ impl Message {
         let timestamp = u64::from_be_bytes(b[..8].try_into().unwrap()) as i64;
         let attempts = u16::from_be_bytes(b[8..10].try_into().unwrap());
         let id = b[10..10 + MSG_ID_LENGTH].try_into().unwrap();
-        let body = b[10 + MSG_ID_LENGTH..].try_into().unwrap();
+        let body = b[10 + MSG_ID_LENGTH..].into();
         Ok(Message {
             id,
             body,
@@ -104,17 +97,15 @@ impl Message {
     }
 
     // 将消息写入到后端队列，缓解内存压力
-    pub async fn write_to_backend<Q>(&mut self, bq: &mut Q) -> Result<()>
+    pub async fn write_to_backend<Q>(&self, bq: &Q) -> Result<()>
     where
-        Q: BackEndQueue,
+        Q: BackEndQueue + ?Sized,
     {
         // 这里要不要用buf pool优化一下？
         let mut buf = Vec::with_capacity(MIN_VALID_MSG_LEN);
         // 这里写入一次到buf，然后再写入一次到bq，能不能优化？
         self.write_to(&mut buf).await?;
 
-        bq.put(buf.as_slice()).await?;
-
-        Ok(())
+        bq.put(buf).await
     }
 }
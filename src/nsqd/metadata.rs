@@ -0,0 +1,206 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::nsqd::NSQD;
+use crate::common::{is_ephemeral, is_valid_channel_name, is_valid_topic_name, Result};
+
+// 元数据文件的格式和golang版本的nsqd.dat保持一致
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    topics: Vec<TopicMetadata>,
+    version: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TopicMetadata {
+    name: String,
+    paused: bool,
+    channels: Vec<ChannelMetadata>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelMetadata {
+    name: String,
+    paused: bool,
+}
+
+impl NSQD {
+    fn metadata_file_name(&self) -> PathBuf {
+        self.get_opts().data_path.join("nsqd.dat")
+    }
+
+    // 根据元数据文件重新创建topic和channel，恢复暂停状态
+    pub(super) fn load_metadata(self: &Arc<Self>) -> Result<()> {
+        let file_name = self.metadata_file_name();
+        let data = match fs::read(&file_name) {
+            Ok(data) => data,
+            // 第一次启动时没有元数据文件
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let meta: Metadata = serde_json::from_slice(&data).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("failed to parse metadata in {} - {e}", file_name.display()),
+            )
+        })?;
+
+        for t in meta.topics {
+            if !is_valid_topic_name(&t.name) {
+                warn!("skipping creation of invalid topic {}", t.name);
+                continue;
+            }
+            let topic = self.get_topic(&t.name);
+            if t.paused {
+                topic.pause();
+            }
+
+            for c in t.channels {
+                if !is_valid_channel_name(&c.name) {
+                    warn!("skipping creation of invalid channel {}", c.name);
+                    continue;
+                }
+                let channel = topic.get_channel(&c.name);
+                if c.paused {
+                    channel.pause();
+                }
+            }
+
+            // 所有channel恢复之后才开始投递消息
+            topic.start();
+        }
+
+        Ok(())
+    }
+
+    // 先写入临时文件再重命名，保证元数据文件的原子性。临时topic和channel不需要持久化
+    pub(super) fn persist_metadata(&self) -> io::Result<()> {
+        let file_name = self.metadata_file_name();
+        info!(
+            "NSQ: persisting topic/channel metadata to {}",
+            file_name.display()
+        );
+
+        let mut topics = self.topics();
+        topics.retain(|t| !is_ephemeral(t.name()));
+        topics.sort_by(|a, b| a.name().cmp(b.name()));
+
+        let meta = Metadata {
+            topics: topics
+                .iter()
+                .map(|t| {
+                    let mut channels = t.channels();
+                    channels.retain(|c| !is_ephemeral(c.name()));
+                    channels.sort_by(|a, b| a.name().cmp(b.name()));
+
+                    TopicMetadata {
+                        name: t.name().to_owned(),
+                        paused: t.is_paused(),
+                        channels: channels
+                            .iter()
+                            .map(|c| ChannelMetadata {
+                                name: c.name().to_owned(),
+                                paused: c.is_paused(),
+                            })
+                            .collect(),
+                    }
+                })
+                .collect(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+        };
+        let data = serde_json::to_vec(&meta)?;
+
+        let mut tmp_file_name = file_name.clone().into_os_string();
+        tmp_file_name.push(format!(".{}.tmp", rand::random::<u32>()));
+
+        let mut f = File::create(&tmp_file_name)?;
+        f.write_all(&data)?;
+        f.sync_all()?;
+        drop(f);
+
+        fs::rename(&tmp_file_name, &file_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use tempfile::TempDir;
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::nsqd::{
+        protocol_v2::FrameType,
+        test_util::{test_options, TestNsqd},
+    };
+
+    const MSG_COUNT: i64 = 100;
+
+    // 重启时topic中有积压的消息，每个channel都要收到全部消息
+    #[tokio::test(flavor = "multi_thread")]
+    async fn restart_with_backlog() {
+        let dir = TempDir::new().unwrap();
+        let opts = || {
+            let mut opts = test_options(dir.path());
+            opts.mem_queue_size = 0;
+            opts
+        };
+
+        // 暂停topic，消息积压在topic的磁盘队列中
+        let server = TestNsqd::start_with(opts(), |nsqd| {
+            let topic = nsqd.get_topic("backlog");
+            topic.get_channel("ch1");
+            topic.get_channel("ch2");
+            topic.pause();
+        })
+        .await;
+        let mut client = server.connect().await;
+        for i in 0..MSG_COUNT {
+            client
+                .command_with_body("PUB backlog", format!("msg{i}").as_bytes())
+                .await;
+            assert_eq!(client.read_frame().await.unwrap().1, b"OK");
+        }
+        server.stop().await;
+
+        // 重启之前取消暂停
+        let file_name = dir.path().join("nsqd.dat");
+        let mut meta: Metadata = serde_json::from_slice(&fs::read(&file_name).unwrap()).unwrap();
+        assert_eq!(meta.topics.len(), 1);
+        assert_eq!(meta.topics[0].channels.len(), 2);
+        meta.topics[0].paused = false;
+        fs::write(&file_name, serde_json::to_vec(&meta).unwrap()).unwrap();
+
+        let server = TestNsqd::start(opts()).await;
+        for name in ["ch1", "ch2"] {
+            let mut client = server.connect().await;
+            client.command(&format!("SUB backlog {name}")).await;
+            assert_eq!(client.read_frame().await.unwrap().1, b"OK");
+            client.command(&format!("RDY {MSG_COUNT}")).await;
+
+            // 消息格式: 8字节时间戳 + 2字节attempts + 16字节ID + body
+            let mut bodies = HashSet::new();
+            for _ in 0..MSG_COUNT {
+                let (ft, data) = timeout(Duration::from_secs(5), client.read_frame())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(ft, FrameType::Message as u32);
+                bodies.insert(String::from_utf8(data[26..].to_vec()).unwrap());
+            }
+            let expected: HashSet<_> = (0..MSG_COUNT).map(|i| format!("msg{i}")).collect();
+            assert_eq!(bodies, expected, "channel {name}");
+        }
+
+        server.stop().await;
+    }
+}
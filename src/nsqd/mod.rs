@@ -1,10 +1,28 @@
+mod auth;
 mod backend_queue;
 mod channel;
 mod client_v2;
+mod compress;
+mod config;
+mod disk_queue;
+mod guid;
+mod http;
+mod lookup;
+mod lookup_peer;
 mod message;
+mod metadata;
+#[allow(clippy::module_inception)]
 mod nsqd;
 mod options;
+mod pqueue;
 mod protocol_v2;
-mod shutdown;
+mod stats;
+mod statsd;
 mod tcp_server;
+#[cfg(test)]
+pub(crate) mod test_util;
 mod topic;
+
+pub use config::Config;
+pub use nsqd::NSQD;
+pub use options::{Options, TLS_NOT_REQUIRED, TLS_REQUIRED, TLS_REQUIRED_EXCEPT_HTTP};
@@ -1,26 +1,61 @@
 use std::{
     collections::HashMap,
-    sync::atomic::{AtomicBool, AtomicI64},
-    time::{self, Duration, Instant},
+    fs::{self, File},
+    future::pending,
+    io::{self, BufReader},
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{self, Instant},
+};
+
+use async_channel::{Receiver as AsyncReceiver, Sender as AsyncSender};
+use rustls::{
+    client::danger::HandshakeSignatureValid,
+    crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, UnixTime},
+    server::{
+        danger::{ClientCertVerified, ClientCertVerifier},
+        WebPkiClientVerifier,
+    },
+    DigitallySignedStruct, DistinguishedName, ProtocolVersion, RootCertStore, ServerConfig,
+    SignatureScheme,
 };
 
 use tokio::{
-    io::BufReader,
     net::{TcpListener, TcpStream},
     select,
     sync::{
         broadcast,
-        mpsc::{self, Receiver, Sender},
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Notify,
     },
-    time::sleep,
+    time::{interval_at, Instant as TokioInstant},
 };
+use tokio_rustls::TlsAcceptor;
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
-use tracing::{debug, info, warn};
+use tracing::{error, info, warn};
 
-use crate::{common::Result, nsqd::shutdown::Shutdown};
+use crate::{
+    common::{unix_nanos, Result},
+    errors::NsqError,
+    shutdown::Shutdown,
+};
 
-use super::{channel::Channel, options::Options, topic::Topic};
+use super::{
+    channel::Channel,
+    client_v2::ClientV2,
+    http,
+    options::{Options, TLS_NOT_REQUIRED, TLS_REQUIRED},
+    stats::{sort_channels, sort_topics, ChannelStats, ClientStats, TopicStats},
+    tcp_server,
+    topic::Topic,
+};
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct NSQD {
     client_id_seq: AtomicI64,
 
@@ -29,21 +64,30 @@ pub struct NSQD {
 
     start_time: Instant,
 
-    topic_map: HashMap<String, Topic>,
+    topic_map: RwLock<HashMap<String, Arc<Topic>>>,
+
+    clients: RwLock<HashMap<i64, Arc<ClientV2>>>,
 
     // tcp_server:
     tcp_listener: TcpListener,
     http_listener: TcpListener,
-    https_listener: TcpListener,
+    // 只有配置了TLS证书时才监听https_addr
+    https_listener: Option<TcpListener>,
 
     exit_token: CancellationToken,
 
-    // tls_config:,
+    tls_config: Option<Arc<ServerConfig>>,
     // client_tls_config:,
-    pool_size: usize,
-
-    notify_tx: Sender<NotifyType>,
-    notify_rx: Receiver<NotifyType>,
+    // 访问auth server等外部HTTP服务
+    http_client: reqwest::Client,
+    // queue scan worker的数量
+    pool_size: AtomicUsize,
+
+    // topic/channel创建或删除时通知lookup loop
+    notify_tx: UnboundedSender<NotifyType>,
+    notify_rx: Mutex<Option<UnboundedReceiver<NotifyType>>>,
+    // topic/channel发生变化时通知metadata loop持久化元数据
+    persist_notify: Notify,
     // 集群信息
     // ci,
 
@@ -52,110 +96,851 @@ pub struct NSQD {
 }
 
 impl NSQD {
-    pub async fn new(opts: Options) -> (Self, CancellationToken) {
+    pub async fn new(mut opts: Options) -> Result<(Self, CancellationToken)> {
         let token = CancellationToken::new();
-        let (notify_tx, notify_rx) = mpsc::channel(1);
+        let (notify_tx, notify_rx) = mpsc::unbounded_channel();
+
+        opts.validate()?;
+        fs::create_dir_all(&opts.data_path)?;
+
+        let http_client = reqwest::Client::builder()
+            .connect_timeout(opts.http_client_connect_timeout)
+            .timeout(opts.http_client_request_timeout)
+            .build()
+            .map_err(|e| NsqError::InvalidOptions(format!("failed to build http client - {e}")))?;
+
+        // 配置了客户端证书校验策略时默认要求使用TLS
+        if !opts.tls_client_auth_policy.is_empty() && opts.tls_required == TLS_NOT_REQUIRED {
+            opts.tls_required = TLS_REQUIRED;
+        }
+
+        let tls_config = build_tls_config(&opts)?;
+        if tls_config.is_none() && opts.tls_required != TLS_NOT_REQUIRED {
+            return Err(NsqError::InvalidOptions(
+                "cannot require TLS client connections without TLS key and cert".to_owned(),
+            ));
+        }
 
-        let tcp_listener = TcpListener::bind(opts.tcp_addr.clone()).await.unwrap();
+        let tcp_listener = TcpListener::bind(&opts.tcp_addr).await?;
+        let http_listener = TcpListener::bind(&opts.http_addr).await?;
+        let https_listener = match &tls_config {
+            Some(_) if !opts.https_addr.is_empty() => {
+                Some(TcpListener::bind(&opts.https_addr).await?)
+            }
+            _ => None,
+        };
 
-        let http_listener = TcpListener::bind(opts.http_addr).await.unwrap();
-        let https_listener = TcpListener::bind(opts.https_addr).await.unwrap();
+        // 没有指定时使用实际监听的端口
+        if opts.broadcast_tcp_port == 0 {
+            opts.broadcast_tcp_port = tcp_listener.local_addr()?.port();
+        }
+        if opts.broadcast_http_port == 0 {
+            opts.broadcast_http_port = http_listener.local_addr()?.port();
+        }
 
         let nsqd = NSQD {
-            client_id_seq: todo!(),
+            client_id_seq: AtomicI64::new(0),
             is_loading: false.into(),
             is_exiting: false.into(),
             start_time: time::Instant::now(),
-            topic_map: HashMap::new(),
+            topic_map: RwLock::new(HashMap::new()),
+            clients: RwLock::new(HashMap::new()),
             tcp_listener,
             http_listener,
             https_listener,
             exit_token: token.clone(),
-            pool_size: todo!(),
+            tls_config,
+            http_client,
+            pool_size: AtomicUsize::new(0),
             notify_tx,
-            notify_rx,
+            notify_rx: Mutex::new(Some(notify_rx)),
+            persist_notify: Notify::new(),
             opts,
         };
 
-        (nsqd, token)
+        Ok((nsqd, token))
     }
 
-    pub async fn start(&self) -> Result<()> {
+    pub async fn start(self: Arc<Self>) -> Result<()> {
+        // 在接受连接之前恢复topic和channel
+        self.is_loading.store(true, Ordering::SeqCst);
+        let res = self.load_metadata();
+        self.is_loading.store(false, Ordering::SeqCst);
+        res?;
+        self.persist_metadata()?;
+
         let tracker = TaskTracker::new();
-        let (tx, rx) = broadcast::channel(1);
+        let (tx, _) = broadcast::channel(1);
+
+        tracker.spawn(tcp_server::serve(self.clone(), (&tx).into()));
+        tracker.spawn(http::serve(self.clone(), None, (&tx).into()));
+        if let Some(acceptor) = self
+            .tls_acceptor()
+            .filter(|_| self.https_listener.is_some())
+        {
+            tracker.spawn(http::serve(self.clone(), Some(acceptor), (&tx).into()));
+        }
+        tracker.spawn(self.clone().queue_scan_loop((&tx).into()));
+        tracker.spawn(self.clone().lookup_loop((&tx).into()));
+        tracker.spawn(self.clone().metadata_loop((&tx).into()));
+        if !self.opts.statsd_address.is_empty() {
+            tracker.spawn(self.clone().statsd_loop((&tx).into()));
+        }
+
+        // 等待退出信号
+        select! {
+            _ = self.exit_token.cancelled() => {
+                warn!("NSQD exiting");
+            }
+        }
+        self.is_exiting.store(true, Ordering::SeqCst);
+
+        // 通知所有组件退出
+        let _ = tx.send(());
+        tracker.close();
+
+        // 等待所有组件退出
+        tracker.wait().await;
+
+        if let Err(e) = self.persist_metadata() {
+            error!("failed to persist metadata - {e}");
+        }
+        self.close_topics().await;
+        Ok(())
+    }
+
+    pub fn exit(&self) {
+        self.exit_token.cancel();
+    }
+
+    pub fn get_opts(&self) -> &Options {
+        &self.opts
+    }
+
+    pub fn tcp_addr(&self) -> SocketAddr {
+        self.tcp_listener.local_addr().unwrap()
+    }
+
+    pub(super) async fn tcp_accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.tcp_listener.accept().await
+    }
+
+    pub fn http_addr(&self) -> SocketAddr {
+        self.http_listener.local_addr().unwrap()
+    }
+
+    pub(super) async fn http_accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.http_listener.accept().await
+    }
+
+    pub fn https_addr(&self) -> Option<SocketAddr> {
+        self.https_listener
+            .as_ref()
+            .map(|listener| listener.local_addr().unwrap())
+    }
+
+    pub(super) async fn https_accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        match &self.https_listener {
+            Some(listener) => listener.accept().await,
+            None => pending().await,
+        }
+    }
+
+    pub(super) fn tls_acceptor(&self) -> Option<TlsAcceptor> {
+        self.tls_config.clone().map(TlsAcceptor::from)
+    }
+
+    pub(super) fn http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
+
+    // 配置了auth server时客户端必须先AUTH才能PUB/SUB
+    pub fn is_auth_enabled(&self) -> bool {
+        !self.opts.auth_http_addrs.is_empty()
+    }
 
-        let mut shutdown: Shutdown = (&tx).into();
-        let mut shutdown2: Shutdown = (&tx).into();
+    pub(super) fn notify(&self, v: NotifyType) {
+        let _ = self.notify_tx.send(v);
+        self.notify_persist();
+    }
+
+    // 加载元数据时创建topic/channel不需要再次持久化
+    pub(super) fn notify_persist(&self) {
+        if !self.is_loading.load(Ordering::SeqCst) {
+            self.persist_notify.notify_one();
+        }
+    }
+
+    // 只能被lookup loop获取一次
+    pub(super) fn take_notify_rx(&self) -> UnboundedReceiver<NotifyType> {
+        self.notify_rx.lock().unwrap().take().unwrap()
+    }
+
+    pub fn uptime(&self) -> time::Duration {
+        self.start_time.elapsed()
+    }
+
+    pub(super) fn next_client_id(&self) -> i64 {
+        self.client_id_seq.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub(super) fn add_client(&self, client: Arc<ClientV2>) {
+        self.clients.write().unwrap().insert(client.id, client);
+    }
+
+    pub(super) fn remove_client(&self, client_id: i64) {
+        self.clients.write().unwrap().remove(&client_id);
+    }
+
+    pub(super) fn client_count(&self) -> usize {
+        self.clients.read().unwrap().len()
+    }
+
+    // 获取topic，如果不存在则创建
+    pub(super) fn get_topic(self: &Arc<Self>, name: &str) -> Arc<Topic> {
+        if let Some(topic) = self.topic_map.read().unwrap().get(name) {
+            return topic.clone();
+        }
+
+        let topic = self
+            .topic_map
+            .write()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_insert_with(|| Topic::new(name, self.clone()))
+            .clone();
+
+        // 加载元数据时由load_metadata在恢复channel之后启动
+        if !self.is_loading.load(Ordering::SeqCst) {
+            topic.start();
+        }
+        topic
+    }
+
+    pub(super) fn get_existing_topic(&self, name: &str) -> Option<Arc<Topic>> {
+        self.topic_map.read().unwrap().get(name).cloned()
+    }
+
+    // 先删除topic中所有的channel和消息，再从topic列表中移除
+    pub(super) async fn delete_existing_topic(&self, name: &str) -> Result<()> {
+        let topic = self
+            .get_existing_topic(name)
+            .ok_or(NsqError::TopicNotFound)?;
+
+        topic.delete().await?;
+
+        self.topic_map.write().unwrap().remove(name);
+        self.notify(NotifyType::Topic(topic));
+        Ok(())
+    }
+
+    pub(super) fn topics(&self) -> Vec<Arc<Topic>> {
+        self.topic_map.read().unwrap().values().cloned().collect()
+    }
+}
+
+impl NSQD {
+    // topic不为空时只统计这个topic，channel不为空时只统计这个channel
+    pub(super) fn get_stats(
+        &self,
+        topic: Option<&str>,
+        channel: Option<&str>,
+        include_clients: bool,
+    ) -> Vec<TopicStats> {
+        let mut topics: Vec<_> = match topic {
+            Some(name) => self.get_existing_topic(name).into_iter().collect(),
+            None => self.topic_map.read().unwrap().values().cloned().collect(),
+        };
+        sort_topics(&mut topics);
+
+        topics
+            .iter()
+            .map(|t| {
+                let mut channels = match channel {
+                    Some(name) => t.get_existing_channel(name).into_iter().collect(),
+                    None => t.channels(),
+                };
+                sort_channels(&mut channels);
+
+                let channel_stats = channels
+                    .iter()
+                    .map(|c| {
+                        let clients = c.clients();
+                        let client_stats = if include_clients {
+                            clients.iter().map(|client| client.stats()).collect()
+                        } else {
+                            Vec::new()
+                        };
+                        ChannelStats::new(c, client_stats, clients.len())
+                    })
+                    .collect();
+
+                TopicStats::new(t, channel_stats)
+            })
+            .collect()
+    }
 
-        // TODO: 启动tcp server
-        tracker.spawn(async move {
-            let tcp_listener = TcpListener::bind("127.0.0.1:6999").await.unwrap();
-            let tracker = TaskTracker::new();
+    // 发布过消息的客户端
+    pub(super) fn get_producer_stats(&self) -> Vec<ClientStats> {
+        let mut producers: Vec<_> = self
+            .clients
+            .read()
+            .unwrap()
+            .values()
+            .filter(|c| c.is_producer())
+            .cloned()
+            .collect();
+        producers.sort_by_key(|c| c.id);
+
+        producers.iter().map(|c| c.stats()).collect()
+    }
+
+    fn channels(&self) -> Vec<Arc<Channel>> {
+        self.topic_map
+            .read()
+            .unwrap()
+            .values()
+            .flat_map(|topic| topic.channels())
+            .collect()
+    }
+
+    // 每隔queue_scan_interval随机选取一部分channel，处理超时的in-flight消息和到期的deferred消息。
+    // 如果被选中的channel中有超过queue_scan_dirty_percent的比例存在需要处理的消息，立即再执行一轮
+    async fn queue_scan_loop(self: Arc<Self>, mut shutdown: Shutdown) {
+        let opts = self.get_opts();
+        let pool = QueueScanPool::new(opts.queue_scan_selection_count);
+
+        let start = TokioInstant::now();
+        let mut work_ticker =
+            interval_at(start + opts.queue_scan_interval, opts.queue_scan_interval);
+        let mut refresh_ticker = interval_at(
+            start + opts.queue_scan_refresh_interval,
+            opts.queue_scan_refresh_interval,
+        );
+
+        let mut channels = self.channels();
+        self.resize_pool(channels.len(), &pool).await;
+
+        loop {
+            select! {
+                _ = work_ticker.tick() => {
+                    if channels.is_empty() {
+                        continue;
+                    }
+                }
+                _ = refresh_ticker.tick() => {
+                    channels = self.channels();
+                    self.resize_pool(channels.len(), &pool).await;
+                    continue;
+                }
+                _ = shutdown.recv() => break,
+            }
+
+            let num = opts.queue_scan_selection_count.min(channels.len());
+            if num == 0 {
+                continue;
+            }
             loop {
-                select! {
-                    Ok((mut conn, addr)) = tcp_listener.accept() => {
-
-                        let (mut reader, mut writer) = conn.split();
-
-                        tracker.spawn(async move {
-                            // 实际上这里是不断 read和write
-                            // 当收到退出信号，应该先关闭read
-                            debug!("Connection accept: {addr}");
-                            // buf
-                            // loop {
-                                // _ = conn.
-                            // }
-                            sleep(Duration::from_secs(15)).await;
-                            debug!("Connection close: {addr}");
-                        });
-                    },
-                    _ = shutdown.recv() => {
-                        info!("TCP Server shutting down");
-                        break;
+                let selected = rand::seq::index::sample(&mut rand::rng(), channels.len(), num);
+                for i in selected {
+                    let _ = pool.work_tx.send(channels[i].clone()).await;
+                }
+
+                let mut num_dirty = 0;
+                for _ in 0..num {
+                    if let Ok(true) = pool.response_rx.recv().await {
+                        num_dirty += 1;
                     }
+                }
 
+                if num_dirty as f64 / num as f64 <= opts.queue_scan_dirty_percent {
+                    break;
                 }
+            }
+        }
 
-                sleep(Duration::from_secs(5)).await;
+        info!("QUEUESCAN: closing");
+        pool.close().await;
+    }
+
+    // worker数量为channel数量的1/4，最少1个，最多queue_scan_worker_pool_max个
+    async fn resize_pool(&self, num: usize, pool: &QueueScanPool) {
+        let ideal_pool_size = ((num as f64 * 0.25) as usize)
+            .max(1)
+            .min(self.get_opts().queue_scan_worker_pool_max);
+
+        loop {
+            let pool_size = self.pool_size.load(Ordering::SeqCst);
+            if ideal_pool_size == pool_size {
+                break;
+            } else if ideal_pool_size < pool_size {
+                // 让一个worker退出
+                let _ = pool.close_tx.send(()).await;
+                self.pool_size.fetch_sub(1, Ordering::SeqCst);
+            } else {
+                pool.spawn_worker();
+                self.pool_size.fetch_add(1, Ordering::SeqCst);
             }
+        }
+    }
 
-            // 不再接受新的连接
-            drop(tcp_listener);
+    // topic/channel发生变化时持久化元数据，短时间内的多次变化只会写一次
+    async fn metadata_loop(self: Arc<Self>, mut shutdown: Shutdown) {
+        loop {
+            select! {
+                _ = self.persist_notify.notified() => {
+                    if let Err(e) = self.persist_metadata() {
+                        error!("failed to persist metadata - {e}");
+                    }
+                }
+                _ = shutdown.recv() => break,
+            }
+        }
 
-            // 等待所有task完成
-            tracker.wait().await;
-        });
+        info!("METADATA: closing");
+    }
 
-        // TODO: 启动http server(if have)
-        // TODO: 启动https server(if have)
-        // TODO: 启动queue scan loop
-        // TODO: 启动lookup loop
-        // TODO: 启动statsd loop
-        // TODO: 等待退出信号
+    async fn close_topics(&self) {
+        let topics: Vec<_> = self.topic_map.write().unwrap().drain().collect();
+        for (name, topic) in topics {
+            if let Err(e) = topic.close().await {
+                warn!("failed to close topic({name}) - {e}");
+            }
+        }
+    }
+}
 
-        // self.exit_token.await;
+// 没有配置证书和私钥时不启用TLS
+fn build_tls_config(opts: &Options) -> Result<Option<Arc<ServerConfig>>> {
+    if opts.tls_cert.as_os_str().is_empty() && opts.tls_key.as_os_str().is_empty() {
+        return Ok(None);
+    }
 
-        select! {
-            _ = self.exit_token.cancelled() => {
-                warn!("NSQD existing");
+    let certs = load_certs(&opts.tls_cert)?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&opts.tls_key)?))?
+        .ok_or_else(|| {
+            NsqError::InvalidOptions(format!(
+                "no private key found in {}",
+                opts.tls_key.display()
+            ))
+        })?;
+
+    // rustls只支持TLS1.2和TLS1.3
+    let versions: &[_] = if opts.tls_min_version == ProtocolVersion::TLSv1_3 {
+        &[&rustls::version::TLS13]
+    } else {
+        rustls::ALL_VERSIONS
+    };
+    let builder = ServerConfig::builder_with_protocol_versions(versions);
+
+    let builder = match opts.tls_client_auth_policy.as_str() {
+        // 和golang的tls.RequireAnyClientCert一致，要求客户端提供证书但不校验
+        "require" => {
+            let verifier = AcceptAnyClientCert(builder.crypto_provider().clone());
+            builder.with_client_cert_verifier(Arc::new(verifier))
+        }
+        "require-verify" => {
+            if opts.tls_root_ca_file.as_os_str().is_empty() {
+                return Err(NsqError::InvalidOptions(format!(
+                    "tls_root_ca_file is required by tls_client_auth_policy {}",
+                    opts.tls_client_auth_policy
+                )));
+            }
+
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(&opts.tls_root_ca_file)? {
+                roots.add(cert)?;
             }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|e| NsqError::InvalidOptions(e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
         }
+        _ => builder.with_no_client_auth(),
+    };
 
-        tracker.close();
+    let config = builder.with_single_cert(certs, key)?;
+    Ok(Some(Arc::new(config)))
+}
 
-        // TODO:等待所有组件退出
-        let _ = tracker.wait().await;
-        Ok(())
+// 接受任意客户端证书，只校验握手签名
+#[derive(Debug)]
+struct AcceptAnyClientCert(Arc<CryptoProvider>);
+
+impl ClientCertVerifier for AcceptAnyClientCert {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
     }
 
-    pub fn stop(&mut self) {}
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> std::result::Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
 
-    pub fn get_opts(&self) -> &Options {
-        &self.opts
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
     }
 }
 
-pub enum NotifyType {
-    Channel(Channel),
-    Topic(Topic),
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))
+        .collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(NsqError::InvalidOptions(format!(
+            "no certificate found in {}",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+struct QueueScanPool {
+    work_tx: AsyncSender<Arc<Channel>>,
+    work_rx: AsyncReceiver<Arc<Channel>>,
+    response_tx: AsyncSender<bool>,
+    response_rx: AsyncReceiver<bool>,
+    close_tx: AsyncSender<()>,
+    close_rx: AsyncReceiver<()>,
+    workers: TaskTracker,
+}
+
+impl QueueScanPool {
+    fn new(selection_count: usize) -> Self {
+        let (work_tx, work_rx) = async_channel::bounded(selection_count);
+        let (response_tx, response_rx) = async_channel::bounded(selection_count);
+        let (close_tx, close_rx) = async_channel::bounded(1);
+        Self {
+            work_tx,
+            work_rx,
+            response_tx,
+            response_rx,
+            close_tx,
+            close_rx,
+            workers: TaskTracker::new(),
+        }
+    }
+
+    fn spawn_worker(&self) {
+        self.workers.spawn(queue_scan_worker(
+            self.work_rx.clone(),
+            self.response_tx.clone(),
+            self.close_rx.clone(),
+        ));
+    }
+
+    // 通知所有worker退出，并等待正在处理的channel完成
+    async fn close(self) {
+        self.close_tx.close();
+        self.workers.close();
+        self.workers.wait().await;
+    }
+}
+
+async fn queue_scan_worker(
+    work_rx: AsyncReceiver<Arc<Channel>>,
+    response_tx: AsyncSender<bool>,
+    close_rx: AsyncReceiver<()>,
+) {
+    loop {
+        select! {
+            Ok(channel) = work_rx.recv() => {
+                let now = unix_nanos();
+                let mut dirty = false;
+                if channel.process_in_flight_queue(now).await {
+                    dirty = true;
+                }
+                if channel.process_deferred_queue(now).await {
+                    dirty = true;
+                }
+                if response_tx.send(dirty).await.is_err() {
+                    break;
+                }
+            }
+            _ = close_rx.recv() => break,
+        }
+    }
+}
+
+pub(super) enum NotifyType {
+    Channel(Arc<Channel>),
+    Topic(Arc<Topic>),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use rustls::{pki_types::ServerName, ClientConfig};
+    use tempfile::TempDir;
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+        time::timeout,
+    };
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+    use crate::nsqd::{
+        protocol_v2::FrameType,
+        test_util::{test_options, TestClient, TestNsqd},
+    };
+
+    fn scan_options(dir: &Path) -> Options {
+        let mut opts = test_options(dir);
+        opts.msg_timeout = Duration::from_millis(100);
+        // 消息需要在超时之前flush给客户端
+        opts.output_buffer_timeout = Duration::from_millis(10);
+        opts.queue_scan_interval = Duration::from_millis(10);
+        opts.queue_scan_refresh_interval = Duration::from_millis(10);
+        opts
+    }
+
+    async fn subscribe(server: &TestNsqd) -> TestClient {
+        let mut client = server.connect().await;
+        client.command("SUB scan ch").await;
+        assert_eq!(client.read_frame().await.unwrap().1, b"OK");
+        client.command("RDY 1").await;
+        client
+    }
+
+    #[tokio::test]
+    async fn in_flight_timeout_requeue() {
+        let dir = TempDir::new().unwrap();
+        let server = TestNsqd::start(scan_options(dir.path())).await;
+        let mut consumer = subscribe(&server).await;
+
+        let mut producer = server.connect().await;
+        producer.command_with_body("PUB scan", b"hello").await;
+        assert_eq!(producer.read_frame().await.unwrap().1, b"OK");
+
+        let msg = consumer.read_message().await;
+        assert_eq!(msg.attempts, 1);
+
+        // 不发送FIN，超时之后重新投递
+        let start = Instant::now();
+        let again = timeout(Duration::from_secs(5), consumer.read_message())
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(again.id, msg.id);
+        assert_eq!(again.body, b"hello");
+        assert_eq!(again.attempts, 2);
+
+        let channel = server
+            .nsqd
+            .get_existing_topic("scan")
+            .unwrap()
+            .get_existing_channel("ch")
+            .unwrap();
+        assert_eq!(channel.timeout_count(), 1);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn deferred_promotion() {
+        let dir = TempDir::new().unwrap();
+        let server = TestNsqd::start(scan_options(dir.path())).await;
+        let mut consumer = subscribe(&server).await;
+
+        let mut producer = server.connect().await;
+        let start = Instant::now();
+        producer.command_with_body("DPUB scan 200", b"later").await;
+        assert_eq!(producer.read_frame().await.unwrap().1, b"OK");
+
+        // 到期之前不会投递
+        assert!(timeout(Duration::from_millis(100), consumer.read_frame())
+            .await
+            .is_err());
+
+        let msg = timeout(Duration::from_secs(5), consumer.read_message())
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(msg.body, b"later");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn reject_zero_selection_count() {
+        let dir = TempDir::new().unwrap();
+        let mut opts = test_options(dir.path());
+        opts.queue_scan_selection_count = 0;
+        assert!(matches!(
+            NSQD::new(opts).await,
+            Err(NsqError::InvalidOptions(_))
+        ));
+    }
+
+    // 生成自签名证书，返回证书和私钥的PEM文件路径
+    fn self_signed_cert(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert_file = dir.join(format!("{name}.crt"));
+        let key_file = dir.join(format!("{name}.key"));
+        fs::write(&cert_file, cert.cert.pem()).unwrap();
+        fs::write(&key_file, cert.key_pair.serialize_pem()).unwrap();
+        (cert_file, key_file)
+    }
+
+    // 信任服务端的自签名证书，client_cert不为空时提供客户端证书
+    fn tls_connector(server_cert: &Path, client_cert: Option<(PathBuf, PathBuf)>) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(server_cert).unwrap() {
+            roots.add(cert).unwrap();
+        }
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let config = match client_cert {
+            Some((cert, key)) => {
+                let key =
+                    rustls_pemfile::private_key(&mut BufReader::new(File::open(key).unwrap()))
+                        .unwrap()
+                        .unwrap();
+                builder
+                    .with_client_auth_cert(load_certs(&cert).unwrap(), key)
+                    .unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        };
+        TlsConnector::from(Arc::new(config))
+    }
+
+    async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> (u32, Vec<u8>) {
+        let size = r.read_u32().await.unwrap();
+        let frame_type = r.read_u32().await.unwrap();
+        let mut data = vec![0; size as usize - 4];
+        r.read_exact(&mut data).await.unwrap();
+        (frame_type, data)
+    }
+
+    async fn https_get(
+        connector: &TlsConnector,
+        addr: SocketAddr,
+        path: &str,
+    ) -> io::Result<String> {
+        let conn = TcpStream::connect(addr).await?;
+        let domain = ServerName::try_from("localhost").unwrap();
+        let mut conn = connector.connect(domain, conn).await?;
+        conn.write_all(
+            format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .as_bytes(),
+        )
+        .await?;
+        let mut resp = String::new();
+        conn.read_to_string(&mut resp).await?;
+        Ok(resp)
+    }
+
+    #[tokio::test]
+    async fn tls_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let (cert, key) = self_signed_cert(dir.path(), "server");
+        let mut opts = test_options(dir.path());
+        opts.https_addr = "127.0.0.1:0".to_owned();
+        opts.tls_cert = cert.clone();
+        opts.tls_key = key;
+        opts.tls_required = TLS_REQUIRED;
+        let server = TestNsqd::start(opts).await;
+        let connector = tls_connector(&cert, None);
+
+        // 升级为TLS之前只能IDENTIFY
+        let mut client = server.connect().await;
+        client.command_with_body("PUB tls", b"msg").await;
+        let (ft, body) = client.read_frame().await.unwrap();
+        assert_eq!(ft, FrameType::Error as u32);
+        assert!(body.starts_with(b"E_INVALID"));
+
+        let mut conn = TcpStream::connect(server.nsqd.tcp_addr()).await.unwrap();
+        let identify = br#"{"feature_negotiation":true,"tls_v1":true}"#;
+        let mut buf = b"  V2IDENTIFY\n".to_vec();
+        buf.extend_from_slice(&(identify.len() as u32).to_be_bytes());
+        buf.extend_from_slice(identify);
+        conn.write_all(&buf).await.unwrap();
+        let (ft, body) = read_frame(&mut conn).await;
+        assert_eq!(ft, FrameType::Response as u32);
+        let resp: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(resp["tls_v1"], true);
+
+        // 协商之后升级为TLS连接，升级成功返回OK
+        let domain = ServerName::try_from("localhost").unwrap();
+        let mut conn = connector.connect(domain, conn).await.unwrap();
+        assert_eq!(read_frame(&mut conn).await.1, b"OK");
+        conn.write_all(b"PUB tls\n\x00\x00\x00\x03msg")
+            .await
+            .unwrap();
+        assert_eq!(read_frame(&mut conn).await.1, b"OK");
+        assert_eq!(server.nsqd.get_existing_topic("tls").unwrap().depth(), 1);
+
+        // HTTPS正常提供服务
+        let https_addr = server.nsqd.https_addr().unwrap();
+        let resp = https_get(&connector, https_addr, "/ping").await.unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200"), "{resp}");
+        assert!(resp.ends_with("OK"), "{resp}");
+
+        // 明文HTTP返回403
+        let resp = reqwest::get(format!("http://{}/ping", server.nsqd.http_addr()))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["message"], "TLS_REQUIRED");
+        assert_eq!(body["https_port"], https_addr.port());
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn tls_require_client_cert() {
+        let dir = TempDir::new().unwrap();
+        let (cert, key) = self_signed_cert(dir.path(), "server");
+        let mut opts = test_options(dir.path());
+        opts.https_addr = "127.0.0.1:0".to_owned();
+        opts.tls_cert = cert.clone();
+        opts.tls_key = key;
+        // require不需要root CA，也不校验客户端证书
+        opts.tls_client_auth_policy = "require".to_owned();
+        let server = TestNsqd::start(opts).await;
+        let https_addr = server.nsqd.https_addr().unwrap();
+
+        let connector = tls_connector(&cert, None);
+        assert!(https_get(&connector, https_addr, "/ping").await.is_err());
+
+        let client_cert = self_signed_cert(dir.path(), "client");
+        let connector = tls_connector(&cert, Some(client_cert));
+        let resp = https_get(&connector, https_addr, "/ping").await.unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200"), "{resp}");
+
+        server.stop().await;
+    }
 }
@@ -1,84 +1,111 @@
 use core::time;
 use std::{
-    os,
+    hash::{DefaultHasher, Hash, Hasher},
     path::{Path, PathBuf},
     time::Duration,
 };
 
 use rustls::ProtocolVersion;
+use tracing::Level;
+
+use crate::{common::Result, errors::NsqError};
+
+// tls_required的取值
+pub const TLS_NOT_REQUIRED: u32 = 0;
+// 只要求TCP连接使用TLS，HTTP接口仍然可以使用明文访问
+pub const TLS_REQUIRED_EXCEPT_HTTP: u32 = 1;
+pub const TLS_REQUIRED: u32 = 2;
 
 pub struct Options {
-    id: u16,
+    pub log_level: Level,
+
+    // 节点ID，用于生成消息ID，取值范围[0,1024)
+    pub id: u16,
 
     pub tcp_addr: String,
     pub http_addr: String,
     pub https_addr: String,
-    broadcast_addr: String,
-    broadcast_tcp_port: u16,
-    broadcast_http_port: u16,
-    nsq_lookup_tcp_addrs: Vec<String>,
-    auth_http_addrs: Vec<String>,
-    auth_http_request_method: String,
-    http_client_connect_timeout: Duration,
-    http_client_request_timeout: Duration,
+    pub broadcast_addr: String,
+    pub broadcast_tcp_port: u16,
+    pub broadcast_http_port: u16,
+    pub nsq_lookup_tcp_addrs: Vec<String>,
+    pub auth_http_addrs: Vec<String>,
+    pub auth_http_request_method: String,
+    pub http_client_connect_timeout: Duration,
+    pub http_client_request_timeout: Duration,
 
     // diskqueue options
-    data_path: PathBuf,
-    mem_queue_size: u32,
-    max_bytes_per_file: u32,
-    sync_every: u32,
-    sync_timeout: Duration,
-
-    queue_scan_interval: Duration,
-    queue_scan_refresh_interval: Duration,
-    queue_scan_selection_count: usize,
-    queue_scan_worker_pool_max: usize,
-    queue_scan_dirty_percent: f64,
+    pub data_path: PathBuf,
+    pub mem_queue_size: u32,
+    pub max_bytes_per_file: u32,
+    pub sync_every: u32,
+    pub sync_timeout: Duration,
+
+    pub queue_scan_interval: Duration,
+    pub queue_scan_refresh_interval: Duration,
+    pub queue_scan_selection_count: usize,
+    pub queue_scan_worker_pool_max: usize,
+    pub queue_scan_dirty_percent: f64,
 
     // msg and command options
     pub msg_timeout: Duration,
-    max_msg_timeout: Duration,
-    max_msg_size: u32,
-    max_body_size: u32,
-    max_req_timeout: Duration,
+    pub max_msg_timeout: Duration,
+    pub max_msg_size: u32,
+    pub max_body_size: u32,
+    pub max_req_timeout: Duration,
+    // 默认的心跳间隔为client_timeout的一半
     pub client_timeout: Duration,
+    // 连续这么多个心跳间隔内没有收到客户端的任何命令时断开连接
+    pub max_missed_heartbeats: u32,
 
     // 客户端可以更改的配置选项
-    max_heartbeat_interval: Duration,
-    max_rdy_count: i64,
-    max_output_buffer_size: i64,
-    max_output_buffer_timeout: Duration,
-    min_output_buffer_timeout: Duration,
+    pub max_heartbeat_interval: Duration,
+    pub max_rdy_count: i64,
+    pub max_output_buffer_size: i64,
+    pub max_output_buffer_timeout: Duration,
+    pub min_output_buffer_timeout: Duration,
     pub output_buffer_timeout: Duration,
-    max_channel_consumers: isize,
+    pub max_channel_consumers: isize,
 
     // TLS config
-    tls_cert: PathBuf,
-    tls_key: PathBuf,
-    tls_client_auth_policy: String,
-    tls_root_ca_file: PathBuf,
-    tls_required: u32,
-    tls_min_version: ProtocolVersion,
+    pub tls_cert: PathBuf,
+    pub tls_key: PathBuf,
+    pub tls_client_auth_policy: String,
+    pub tls_root_ca_file: PathBuf,
+    pub tls_required: u32,
+    pub tls_min_version: ProtocolVersion,
 
     // compression
-    deflate_enabled: bool,
-    max_deflate_level: u32,
-    snappy_enabled: bool,
+    pub deflate_enabled: bool,
+    pub max_deflate_level: u32,
+    pub snappy_enabled: bool,
+
+    // statsd，statsd_address为空时不推送。statsd_prefix中的%s会被替换成当前节点的地址
+    pub statsd_address: String,
+    pub statsd_prefix: String,
+    pub statsd_interval: Duration,
+    pub statsd_mem_stats: bool,
+    pub statsd_udp_packet_size: usize,
+    pub statsd_exclude_ephemeral: bool,
 }
 
 impl Options {
     pub fn new() -> Self {
         Self {
-            id: 1, // :TODO
+            log_level: Level::INFO,
+
+            id: default_node_id(),
             tcp_addr: "0.0.0.0:4150".to_owned(),
             http_addr: "0.0.0.0:4151".to_owned(),
             https_addr: "0.0.0.0:4152".to_owned(),
-            broadcast_addr: "待定".to_owned(),
+            broadcast_addr: hostname::get()
+                .map(|h| h.to_string_lossy().into_owned())
+                .unwrap_or_default(),
             broadcast_tcp_port: 0,
             broadcast_http_port: 0,
             nsq_lookup_tcp_addrs: Vec::new(),
             auth_http_addrs: Vec::new(),
-            auth_http_request_method: "GET".to_owned(),
+            auth_http_request_method: "get".to_owned(),
             http_client_connect_timeout: time::Duration::from_secs(2),
             http_client_request_timeout: time::Duration::from_secs(5),
 
@@ -100,25 +127,120 @@ impl Options {
             max_body_size: 5 * 1024 * 1024,
             max_req_timeout: time::Duration::from_secs(60 * 60),
             client_timeout: time::Duration::from_secs(60),
+            max_missed_heartbeats: 2,
 
-            tls_cert: "/path/to/do".into(),
-            tls_key: "/path/to/do".into(),
-            tls_client_auth_policy: "todo".to_owned(),
-            tls_root_ca_file: "/path/to/do".into(),
-            tls_required: 1,
+            tls_cert: PathBuf::new(),
+            tls_key: PathBuf::new(),
+            tls_client_auth_policy: String::new(),
+            tls_root_ca_file: PathBuf::new(),
+            tls_required: TLS_NOT_REQUIRED,
             tls_min_version: rustls::ProtocolVersion::TLSv1_0,
 
             deflate_enabled: true,
             max_deflate_level: 6,
             snappy_enabled: true,
 
-            max_heartbeat_interval: todo!(),
-            max_rdy_count: todo!(),
-            max_output_buffer_size: todo!(),
-            max_output_buffer_timeout: todo!(),
-            min_output_buffer_timeout: todo!(),
-            output_buffer_timeout: todo!(),
-            max_channel_consumers: todo!(),
+            statsd_address: String::new(),
+            statsd_prefix: "nsq.%s".to_owned(),
+            statsd_interval: time::Duration::from_secs(60),
+            statsd_mem_stats: true,
+            statsd_udp_packet_size: 508,
+            statsd_exclude_ephemeral: false,
+
+            max_heartbeat_interval: time::Duration::from_secs(60),
+            max_rdy_count: 2500,
+            max_output_buffer_size: 64 * 1024,
+            max_output_buffer_timeout: time::Duration::from_secs(30),
+            min_output_buffer_timeout: time::Duration::from_millis(25),
+            output_buffer_timeout: time::Duration::from_millis(250),
+            max_channel_consumers: 0,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Options {
+    pub fn validate(&self) -> Result<()> {
+        if self.id >= 1024 {
+            return invalid(format!("--node-id must be [0,1024), got {}", self.id));
+        }
+
+        if self.max_msg_timeout < self.msg_timeout {
+            return invalid(format!(
+                "--max-msg-timeout ({:?}) must be >= --msg-timeout ({:?})",
+                self.max_msg_timeout, self.msg_timeout
+            ));
+        }
+
+        if self.queue_scan_selection_count == 0 {
+            return invalid("--queue-scan-selection-count must be > 0".to_owned());
+        }
+
+        if self.max_missed_heartbeats == 0 {
+            return invalid("--max-missed-heartbeats must be > 0".to_owned());
+        }
+
+        if !(1..=9).contains(&self.max_deflate_level) {
+            return invalid("--max-deflate-level must be [1,9]".to_owned());
+        }
+
+        if self.auth_http_request_method != "get" && self.auth_http_request_method != "post" {
+            return invalid("--auth-http-request-method must be post or get".to_owned());
+        }
+
+        if !matches!(
+            self.tls_client_auth_policy.as_str(),
+            "" | "require" | "require-verify"
+        ) {
+            return invalid(format!(
+                "--tls-client-auth-policy must be require or require-verify, got {}",
+                self.tls_client_auth_policy
+            ));
         }
+
+        if self.tls_required > TLS_REQUIRED {
+            return invalid(format!("invalid --tls-required {}", self.tls_required));
+        }
+
+        // 证书和私钥必须同时配置
+        let has_cert = !self.tls_cert.as_os_str().is_empty();
+        let has_key = !self.tls_key.as_os_str().is_empty();
+        if has_cert != has_key {
+            return invalid("--tls-cert and --tls-key must be specified together".to_owned());
+        }
+        check_file("--tls-cert", &self.tls_cert)?;
+        check_file("--tls-key", &self.tls_key)?;
+        check_file("--tls-root-ca-file", &self.tls_root_ca_file)?;
+
+        if !self.statsd_address.is_empty() && self.statsd_interval.is_zero() {
+            return invalid("--statsd-interval must be > 0".to_owned());
+        }
+
+        Ok(())
     }
 }
+
+fn invalid(msg: String) -> Result<()> {
+    Err(NsqError::InvalidOptions(msg))
+}
+
+// 路径为空表示没有配置
+fn check_file(flag: &str, path: &Path) -> Result<()> {
+    if path.as_os_str().is_empty() || path.is_file() {
+        return Ok(());
+    }
+    invalid(format!("{flag} {} does not exist", path.display()))
+}
+
+// 没有指定node id时根据hostname生成，同一台机器上每次启动都相同
+fn default_node_id() -> u16 {
+    let hostname = hostname::get().unwrap_or_default();
+    let mut hasher = DefaultHasher::new();
+    hostname.hash(&mut hasher);
+    (hasher.finish() % 1024) as u16
+}
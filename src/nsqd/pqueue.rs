@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use super::message::{Message, MessageID};
+use crate::{common::Result, errors::NsqError};
+
+// 以Message::pri为优先级的小顶堆，in-flight队列和deferred队列共用
+//
+// 消息本身保存在map中，堆里只保存消息ID，Message::index记录消息在堆中的位置，
+// 这样可以根据消息ID在O(log n)内删除任意消息
+pub(super) struct PriorityQueue {
+    heap: Vec<MessageID>,
+    messages: HashMap<MessageID, Message>,
+}
+
+impl PriorityQueue {
+    pub fn new() -> Self {
+        Self {
+            heap: Vec::new(),
+            messages: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn get(&self, id: &MessageID) -> Option<&Message> {
+        self.messages.get(id)
+    }
+
+    pub fn push(&mut self, mut msg: Message) -> Result<()> {
+        if self.messages.contains_key(&msg.id) {
+            return Err(NsqError::MsgAlreadyInFlight);
+        }
+
+        let i = self.heap.len();
+        msg.index = i as isize;
+        self.heap.push(msg.id);
+        self.messages.insert(msg.id, msg);
+        self.up(i);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &MessageID) -> Option<Message> {
+        let i = self.messages.get(id)?.index as usize;
+        let n = self.heap.len() - 1;
+        if i != n {
+            self.swap(i, n);
+            self.heap.pop();
+            if !self.down(i) {
+                self.up(i);
+            }
+        } else {
+            self.heap.pop();
+        }
+
+        let mut msg = self.messages.remove(id)?;
+        msg.index = -1;
+        Some(msg)
+    }
+
+    // 如果堆顶消息的优先级不大于max，将其弹出
+    pub fn peek_and_shift(&mut self, max: i64) -> Option<Message> {
+        let id = *self.heap.first()?;
+        if self.messages[&id].pri > max {
+            return None;
+        }
+        self.remove(&id)
+    }
+
+    pub fn clear(&mut self) {
+        self.heap.clear();
+        self.messages.clear();
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = Message> + '_ {
+        self.heap.clear();
+        self.messages.drain().map(|(_, msg)| msg)
+    }
+
+    fn pri(&self, i: usize) -> i64 {
+        self.messages[&self.heap[i]].pri
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.heap.swap(i, j);
+        for k in [i, j] {
+            let id = self.heap[k];
+            self.messages.get_mut(&id).unwrap().index = k as isize;
+        }
+    }
+
+    fn up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if self.pri(parent) <= self.pri(i) {
+                break;
+            }
+            self.swap(parent, i);
+            i = parent;
+        }
+    }
+
+    fn down(&mut self, i0: usize) -> bool {
+        let n = self.heap.len();
+        let mut i = i0;
+        loop {
+            let left = 2 * i + 1;
+            if left >= n {
+                break;
+            }
+            let mut child = left;
+            let right = left + 1;
+            if right < n && self.pri(right) < self.pri(left) {
+                child = right;
+            }
+            if self.pri(i) <= self.pri(child) {
+                break;
+            }
+            self.swap(i, child);
+            i = child;
+        }
+        i > i0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(n: u8, pri: i64) -> Message {
+        let mut msg = Message::new([n; 16], vec![n]);
+        msg.pri = pri;
+        msg
+    }
+
+    // 每条消息记录的index都要和它在堆中的位置一致
+    fn check_index(pq: &PriorityQueue) {
+        for (i, id) in pq.heap.iter().enumerate() {
+            assert_eq!(pq.messages[id].index, i as isize);
+        }
+        assert_eq!(pq.heap.len(), pq.messages.len());
+    }
+
+    fn drain_sorted(pq: &mut PriorityQueue) -> Vec<i64> {
+        let mut pris = Vec::new();
+        while let Some(msg) = pq.peek_and_shift(i64::MAX) {
+            check_index(pq);
+            pris.push(msg.pri);
+        }
+        pris
+    }
+
+    #[test]
+    fn ordering() {
+        let mut pq = PriorityQueue::new();
+        let pris = [50, 10, 90, 30, 70, 20, 80, 60, 40, 0];
+        for (n, &pri) in pris.iter().enumerate() {
+            pq.push(msg(n as u8, pri)).unwrap();
+            check_index(&pq);
+        }
+        assert_eq!(pq.len(), pris.len());
+
+        // 相同ID的消息不能重复放入
+        assert!(matches!(
+            pq.push(msg(0, 1)),
+            Err(NsqError::MsgAlreadyInFlight)
+        ));
+
+        // 堆顶优先级大于max时不弹出
+        assert!(pq.peek_and_shift(-1).is_none());
+        assert_eq!(pq.peek_and_shift(0).unwrap().pri, 0);
+        assert!(pq.peek_and_shift(5).is_none());
+        assert_eq!(pq.peek_and_shift(10).unwrap().pri, 10);
+
+        assert_eq!(drain_sorted(&mut pq), [20, 30, 40, 50, 60, 70, 80, 90]);
+        assert_eq!(pq.len(), 0);
+    }
+
+    #[test]
+    fn remove_by_id() {
+        let mut pq = PriorityQueue::new();
+        for n in 0..20u8 {
+            pq.push(msg(n, (n as i64 * 7) % 20)).unwrap();
+        }
+
+        // 删除堆顶、堆尾和中间的消息
+        let top = pq.heap[0];
+        let last = *pq.heap.last().unwrap();
+        for id in [top, last, [5; 16], [13; 16]] {
+            let removed = pq.remove(&id).unwrap();
+            assert_eq!(removed.id, id);
+            assert_eq!(removed.index, -1);
+            assert!(pq.get(&id).is_none());
+            check_index(&pq);
+        }
+        assert!(pq.remove(&[5; 16]).is_none());
+        assert!(pq.remove(&[100; 16]).is_none());
+        assert_eq!(pq.len(), 16);
+
+        let pris = drain_sorted(&mut pq);
+        assert_eq!(pris.len(), 16);
+        assert!(pris.windows(2).all(|w| w[0] <= w[1]), "{pris:?}");
+    }
+}
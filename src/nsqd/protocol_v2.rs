@@ -1,25 +1,72 @@
-use std::sync::Arc;
+use std::{
+    future::{pending, Future},
+    io,
+    sync::Arc,
+    time::Duration,
+};
+
+use async_channel::{Receiver, RecvError};
+use serde::Serialize;
 
-use tokio::sync::oneshot;
-use tracing::debug;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    select,
+    sync::oneshot,
+    time::{interval_at, timeout, Instant, Interval, MissedTickBehavior},
+};
+use tracing::{debug, error, info, warn};
 
 use super::{
-    client_v2::{Client, ClientV2},
-    message::Message,
+    channel::Channel,
+    client_v2::{Client, ClientV2, IdentifyData, State, DEFAULT_BUF_SIZE},
+    message::{Message, MessageID, MSG_ID_LENGTH},
     nsqd::NSQD,
+    options::TLS_NOT_REQUIRED,
+    topic::Topic,
+};
+use crate::{
+    common::{is_valid_channel_name, is_valid_topic_name, Result},
+    errors::{ErrorCode, NsqError},
+    shutdown::Shutdown,
 };
-use crate::common::Result;
 
-const separator_bytes: &str = " ";
-const heartbeat_bytes: &str = "_heartbeat_";
-const ok_bytes: &str = "OK";
+const SEPARATOR_BYTES: &[u8] = b" ";
+const HEARTBEAT_BYTES: &[u8] = b"_heartbeat_";
+const OK_BYTES: &[u8] = b"OK";
+const MAX_LINE_LENGTH: usize = DEFAULT_BUF_SIZE;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(super) enum FrameType {
-    Response,
-    Error,
-    Message,
+    Response = 0,
+    Error = 1,
+    Message = 2,
+}
+
+#[derive(Serialize)]
+struct IdentifyResponse {
+    max_rdy_count: i64,
+    version: &'static str,
+    max_msg_timeout: u64,
+    msg_timeout: u64,
+    tls_v1: bool,
+    deflate: bool,
+    deflate_level: u32,
+    max_deflate_level: u32,
+    snappy: bool,
+    sample_rate: i32,
+    auth_required: bool,
+    output_buffer_size: usize,
+    output_buffer_timeout: u64,
+}
+
+#[derive(Serialize)]
+struct AuthResponse<'a> {
+    identity: &'a str,
+    identity_url: &'a str,
+    permission_count: usize,
 }
 
+#[derive(Clone)]
 pub(super) struct ProtocolV2 {
     nsqd: Arc<NSQD>,
 }
@@ -29,17 +76,835 @@ impl ProtocolV2 {
         Self { nsqd }
     }
 
-    pub fn io_loop(&mut self, c: ClientV2) -> Result<()> {
-        // TODO: 等待pump
+    pub async fn io_loop<R>(
+        &self,
+        mut reader: BufReader<R>,
+        c: Arc<ClientV2>,
+        mut shutdown: Shutdown,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+    {
+        // 等待message pump启动之后再开始处理命令
+        let (started_tx, started_rx) = oneshot::channel();
+        let pump = tokio::spawn(self.clone().message_pump(c.clone(), started_tx));
+        let _ = started_rx.await;
+
+        self.nsqd.add_client(c.clone());
+
+        let mut line = Vec::new();
+        let result = loop {
+            line.clear();
+            // 超时说明客户端已经连续多次没有响应心跳，可能是半开连接
+            let read_timeout = c.read_timeout();
+            let n = select! {
+                res = read_line(&mut reader, &mut line, read_timeout) => res,
+                _ = c.exited() => break Ok(()),
+                _ = shutdown.recv() => break Ok(()),
+            };
+            let n = match n {
+                Ok(n) => n,
+                Err(e) => {
+                    if e.code().is_some() {
+                        let _ = self.send(&c, FrameType::Error, &e.frame_body()).await;
+                    }
+                    break Err(e);
+                }
+            };
+            // 读到EOF，客户端已经关闭连接
+            if n == 0 || line.last() != Some(&b'\n') {
+                break Ok(());
+            }
+
+            // 去掉结尾的\n和可能存在的\r
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+
+            let params: Vec<&[u8]> = line.split(|b| SEPARATOR_BYTES.contains(b)).collect();
+            debug!(
+                "PROTOCOL(V2): [{}] {:?}",
+                c.addr(),
+                String::from_utf8_lossy(&line)
+            );
+
+            match self.exec(&c, &mut reader, &params).await {
+                Ok(Some(resp)) => {
+                    if let Err(e) = self.send(&c, FrameType::Response, &resp).await {
+                        break Err(e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!("[{}] - {e}", c.addr());
+                    if let Err(e) = self.send(&c, FrameType::Error, &e.frame_body()).await {
+                        break Err(e);
+                    }
+                    if e.is_fatal() {
+                        break Err(e);
+                    }
+                }
+            }
+        };
+
+        info!("PROTOCOL(V2): [{}] exiting ioloop", c.addr());
+        c.set_state(State::Disconnected);
+        c.exit();
+        let _ = pump.await;
+        if let Some(channel) = c.channel() {
+            channel.remove_client(c.id);
+        }
+        self.nsqd.remove_client(c.id);
+
+        result
+    }
+
+    async fn exec<R>(
+        &self,
+        c: &Arc<ClientV2>,
+        reader: &mut BufReader<R>,
+        params: &[&[u8]],
+    ) -> Result<Option<Vec<u8>>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if params[0] == b"IDENTIFY" {
+            return self.identify(c, reader, params).await;
+        }
+
+        // 要求使用TLS时，升级为TLS连接之前只能执行IDENTIFY
+        if self.nsqd.get_opts().tls_required != TLS_NOT_REQUIRED && !c.is_tls() {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                format!(
+                    "cannot {} in current state (client not yet upgraded to TLS)",
+                    String::from_utf8_lossy(params[0])
+                ),
+            ));
+        }
+
+        match params[0] {
+            b"FIN" => self.fin(c, params),
+            b"RDY" => self.rdy(c, params),
+            b"REQ" => self.req(c, params).await,
+            b"PUB" => self.publish(c, reader, params).await,
+            b"MPUB" => self.mpub(c, reader, params).await,
+            b"DPUB" => self.dpub(c, reader, params).await,
+            b"NOP" => Ok(None),
+            b"TOUCH" => self.touch(c, params),
+            b"SUB" => self.sub(c, params).await,
+            b"CLS" => self.cls(c),
+            b"AUTH" => self.auth(c, reader, params).await,
+            _ => Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                format!("invalid command {}", String::from_utf8_lossy(params[0])),
+            )),
+        }
+    }
+
+    async fn identify<R>(
+        &self,
+        c: &ClientV2,
+        reader: &mut BufReader<R>,
+        _params: &[&[u8]],
+    ) -> Result<Option<Vec<u8>>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if c.state() != State::Init {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "cannot IDENTIFY in current state".to_owned(),
+            ));
+        }
+
+        let opts = self.nsqd.get_opts();
+        let body = read_body(reader, opts.max_body_size, "IDENTIFY", c.read_timeout()).await?;
+
+        let identify_data: IdentifyData = serde_json::from_slice(&body).map_err(|_| {
+            NsqError::FatalClientErr(
+                ErrorCode::BadBody,
+                "IDENTIFY failed to decode JSON body".to_owned(),
+            )
+        })?;
+        debug!("PROTOCOL(V2): [{}] {identify_data:?}", c.addr());
+
+        c.identify(&identify_data)
+            .await
+            .map_err(|e| NsqError::FatalClientErr(ErrorCode::BadBody, format!("IDENTIFY {e}")))?;
+
+        c.set_state(State::Connected);
+
+        // 客户端不支持特性协商时直接返回OK
+        if !identify_data.feature_negotiation {
+            return Ok(Some(OK_BYTES.to_vec()));
+        }
+
+        let tls_acceptor = self.nsqd.tls_acceptor().filter(|_| identify_data.tls_v1);
+
+        let deflate = opts.deflate_enabled && identify_data.deflate;
+        let mut deflate_level = 6;
+        if deflate && identify_data.deflate_level > 0 {
+            deflate_level = identify_data.deflate_level;
+        }
+        deflate_level = deflate_level.min(opts.max_deflate_level);
+
+        let snappy = opts.snappy_enabled && identify_data.snappy;
+        if deflate && snappy {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::IdentifyFailed,
+                "cannot enable both deflate and snappy compression".to_owned(),
+            ));
+        }
+
+        let resp = serde_json::to_vec(&IdentifyResponse {
+            max_rdy_count: opts.max_rdy_count,
+            version: env!("CARGO_PKG_VERSION"),
+            max_msg_timeout: opts.max_msg_timeout.as_millis() as u64,
+            msg_timeout: c.msg_timeout().as_millis() as u64,
+            tls_v1: tls_acceptor.is_some(),
+            deflate,
+            deflate_level,
+            max_deflate_level: opts.max_deflate_level,
+            snappy,
+            sample_rate: c.sample_rate(),
+            auth_required: self.nsqd.is_auth_enabled(),
+            output_buffer_size: c.output_buffer_size(),
+            output_buffer_timeout: c.output_buffer_timeout().as_millis() as u64,
+        })
+        .map_err(|_| {
+            NsqError::FatalClientErr(
+                ErrorCode::IdentifyFailed,
+                "IDENTIFY failed to marshal JSON response".to_owned(),
+            )
+        })?;
+        self.send(c, FrameType::Response, &resp).await?;
+
+        // 协商结果发送之后依次升级连接，每次升级成功后都返回一次OK
+        if let Some(acceptor) = tls_acceptor {
+            info!("PROTOCOL(V2): [{}] upgrading connection to TLS", c.addr());
+            c.upgrade_tls(&acceptor).await?;
+            self.send(c, FrameType::Response, OK_BYTES).await?;
+        }
+
+        if snappy {
+            info!(
+                "PROTOCOL(V2): [{}] upgrading connection to snappy",
+                c.addr()
+            );
+            c.upgrade_snappy().await?;
+            self.send(c, FrameType::Response, OK_BYTES).await?;
+        }
+
+        if deflate {
+            info!(
+                "PROTOCOL(V2): [{}] upgrading connection to deflate (level {deflate_level})",
+                c.addr()
+            );
+            c.upgrade_deflate(deflate_level).await?;
+            self.send(c, FrameType::Response, OK_BYTES).await?;
+        }
+
+        Ok(None)
+    }
+
+    async fn auth<R>(
+        &self,
+        c: &ClientV2,
+        reader: &mut BufReader<R>,
+        params: &[&[u8]],
+    ) -> Result<Option<Vec<u8>>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if !matches!(c.state(), State::Init | State::Connected) {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "cannot AUTH in current state".to_owned(),
+            ));
+        }
+
+        if params.len() != 1 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "AUTH invalid number of parameters".to_owned(),
+            ));
+        }
+
+        let max_body_size = self.nsqd.get_opts().max_body_size;
+        let secret = read_body(reader, max_body_size, "AUTH", c.read_timeout()).await?;
+
+        if c.has_authorizations() {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "AUTH already set".to_owned(),
+            ));
+        }
+
+        if !self.nsqd.is_auth_enabled() {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::AuthDisabled,
+                "AUTH disabled".to_owned(),
+            ));
+        }
+
+        // 不把auth server的错误信息暴露给客户端
+        if let Err(e) = c.auth(String::from_utf8_lossy(&secret).into_owned()).await {
+            warn!("PROTOCOL(V2): [{}] AUTH failed {e}", c.addr());
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::AuthFailed,
+                "AUTH failed".to_owned(),
+            ));
+        }
+
+        if !c.has_authorizations() {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Unauthorized,
+                "AUTH no authorizations found".to_owned(),
+            ));
+        }
+
+        let state = c.auth_state().unwrap();
+        let resp = serde_json::to_vec(&AuthResponse {
+            identity: &state.identity,
+            identity_url: &state.identity_url,
+            permission_count: state.authorizations.len(),
+        })
+        .map_err(|e| NsqError::FatalClientErr(ErrorCode::AuthError, format!("AUTH error {e}")))?;
+
+        Ok(Some(resp))
+    }
+
+    // 开启了auth之后，客户端必须先AUTH，并且授权信息中包含对应的topic/channel
+    async fn check_auth(
+        &self,
+        c: &ClientV2,
+        cmd: &str,
+        topic_name: &str,
+        channel_name: &str,
+    ) -> Result<()> {
+        if !self.nsqd.is_auth_enabled() {
+            return Ok(());
+        }
+
+        if !c.has_authorizations() {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::AuthFirst,
+                format!("AUTH required before {cmd}"),
+            ));
+        }
+
+        let ok = c
+            .is_authorized(topic_name, channel_name)
+            .await
+            .map_err(|e| {
+                warn!("PROTOCOL(V2): [{}] AUTH failed {e}", c.addr());
+                NsqError::FatalClientErr(ErrorCode::AuthFailed, "AUTH failed".to_owned())
+            })?;
+        if !ok {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Unauthorized,
+                format!("AUTH failed for {cmd} on {topic_name:?} {channel_name:?}"),
+            ));
+        }
 
         Ok(())
     }
 
-    fn message_pump(&self, c: ClientV2, started_chan: oneshot::Sender<()>) {
-        //
+    async fn sub(&self, c: &Arc<ClientV2>, params: &[&[u8]]) -> Result<Option<Vec<u8>>> {
+        if !matches!(c.state(), State::Init | State::Connected) {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "cannot SUB in current state".to_owned(),
+            ));
+        }
+
+        if c.heartbeat_interval().is_zero() {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "cannot SUB with heartbeats disabled".to_owned(),
+            ));
+        }
+
+        if params.len() < 3 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "SUB insufficient number of parameters".to_owned(),
+            ));
+        }
+
+        let topic_name = String::from_utf8_lossy(params[1]);
+        if !is_valid_topic_name(&topic_name) {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadTopic,
+                format!("SUB topic name {topic_name:?} is not valid"),
+            ));
+        }
+
+        let channel_name = String::from_utf8_lossy(params[2]);
+        if !is_valid_channel_name(&channel_name) {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadChannel,
+                format!("SUB channel name {channel_name:?} is not valid"),
+            ));
+        }
+
+        self.check_auth(c, "SUB", &topic_name, &channel_name)
+            .await?;
+
+        let topic = self.nsqd.get_topic(&topic_name);
+        let channel = topic.get_channel(&channel_name);
+        channel.add_client(c.id, c.clone()).map_err(|e| match e {
+            NsqError::TooManyConsumers(..) => {
+                NsqError::FatalClientErr(ErrorCode::TooManyChannelConsumers, e.to_string())
+            }
+            e => e,
+        })?;
+
+        c.set_state(State::Subscribed);
+        c.set_channel(channel.clone());
+        c.sub_event(channel);
+
+        Ok(Some(OK_BYTES.to_vec()))
+    }
+
+    fn rdy(&self, c: &ClientV2, params: &[&[u8]]) -> Result<Option<Vec<u8>>> {
+        let state = c.state();
+
+        if state == State::Closing {
+            // 客户端已经发送了CLS，忽略后续的RDY
+            info!(
+                "PROTOCOL(V2): [{}] ignoring RDY after CLS in state ClientStateV2Closing",
+                c.addr()
+            );
+            return Ok(None);
+        }
+
+        if state != State::Subscribed {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "cannot RDY in current state".to_owned(),
+            ));
+        }
+
+        let count = match params.get(1) {
+            Some(p) => parse_int(p).ok_or_else(|| {
+                NsqError::FatalClientErr(
+                    ErrorCode::Invalid,
+                    format!("RDY could not parse count {}", String::from_utf8_lossy(p)),
+                )
+            })?,
+            None => 1,
+        };
+
+        // 这里必须是致命错误，否则客户端和服务端记录的RDY会不一致
+        let max_rdy_count = self.nsqd.get_opts().max_rdy_count;
+        if !(0..=max_rdy_count).contains(&count) {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                format!("RDY count {count} out of range 0-{max_rdy_count}"),
+            ));
+        }
+
+        c.set_ready_count(count);
+
+        Ok(None)
+    }
+
+    fn fin(&self, c: &ClientV2, params: &[&[u8]]) -> Result<Option<Vec<u8>>> {
+        let channel = subscribed_channel(c, "FIN")?;
+
+        if params.len() < 2 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "FIN insufficient number of params".to_owned(),
+            ));
+        }
+
+        let id = get_message_id(params[1])?;
+        channel.finish_message(c.id, &id).map_err(|e| {
+            NsqError::ClientErr(
+                ErrorCode::FinFailed,
+                format!("FIN {} failed {e}", String::from_utf8_lossy(&id)),
+            )
+        })?;
+
+        c.finished_msg();
+
+        Ok(None)
+    }
+
+    async fn req(&self, c: &ClientV2, params: &[&[u8]]) -> Result<Option<Vec<u8>>> {
+        let channel = subscribed_channel(c, "REQ")?;
+
+        if params.len() < 3 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "REQ insufficient number of params".to_owned(),
+            ));
+        }
+
+        let id = get_message_id(params[1])?;
+
+        let timeout_ms = parse_int(params[2]).ok_or_else(|| {
+            NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                format!(
+                    "REQ could not parse timeout {}",
+                    String::from_utf8_lossy(params[2])
+                ),
+            )
+        })?;
+
+        // 超时时间限制在 [0, max_req_timeout]
+        let max_req_timeout = self.nsqd.get_opts().max_req_timeout;
+        let timeout = Duration::from_millis(timeout_ms.max(0) as u64).min(max_req_timeout);
+
+        channel
+            .requeue_message(c.id, &id, timeout)
+            .await
+            .map_err(|e| {
+                NsqError::ClientErr(
+                    ErrorCode::ReqFailed,
+                    format!("REQ {} failed {e}", String::from_utf8_lossy(&id)),
+                )
+            })?;
+
+        c.requeue_msg();
+
+        Ok(None)
+    }
+
+    fn touch(&self, c: &ClientV2, params: &[&[u8]]) -> Result<Option<Vec<u8>>> {
+        let channel = subscribed_channel(c, "TOUCH")?;
+
+        if params.len() < 2 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "TOUCH insufficient number of params".to_owned(),
+            ));
+        }
+
+        let id = get_message_id(params[1])?;
+        channel
+            .touch_message(c.id, &id, c.msg_timeout())
+            .map_err(|e| {
+                NsqError::ClientErr(
+                    ErrorCode::TouchFailed,
+                    format!("TOUCH {} failed {e}", String::from_utf8_lossy(&id)),
+                )
+            })?;
+
+        Ok(None)
+    }
+
+    fn cls(&self, c: &ClientV2) -> Result<Option<Vec<u8>>> {
+        if c.state() != State::Subscribed {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "cannot CLS in current state".to_owned(),
+            ));
+        }
+
+        c.start_close();
+
+        Ok(Some(b"CLOSE_WAIT".to_vec()))
+    }
+
+    async fn publish<R>(
+        &self,
+        c: &ClientV2,
+        reader: &mut BufReader<R>,
+        params: &[&[u8]],
+    ) -> Result<Option<Vec<u8>>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if params.len() < 2 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "PUB insufficient number of parameters".to_owned(),
+            ));
+        }
+
+        let topic_name = get_topic_name(params[1], "PUB")?;
+
+        let body_len = read_len(reader, "PUB", c.read_timeout()).await?;
+        let max_msg_size = self.nsqd.get_opts().max_msg_size;
+        if body_len == 0 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadMessage,
+                format!("PUB invalid message body size {body_len}"),
+            ));
+        }
+        if body_len > max_msg_size {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadMessage,
+                format!("PUB message too big {body_len} > {max_msg_size}"),
+            ));
+        }
+
+        let mut body = vec![0; body_len as usize];
+        timed_read(c.read_timeout(), reader.read_exact(&mut body))
+            .await
+            .map_err(|_| {
+                NsqError::FatalClientErr(
+                    ErrorCode::BadMessage,
+                    "PUB failed to read message body".to_owned(),
+                )
+            })?;
+
+        self.check_auth(c, "PUB", &topic_name, "").await?;
+
+        let topic = self.nsqd.get_topic(&topic_name);
+        let msg = Message::new(topic.generate_id().await, body);
+        topic.put_message(msg).await.map_err(|e| {
+            NsqError::FatalClientErr(ErrorCode::PubFailed, format!("PUB failed {e}"))
+        })?;
+
+        c.published_msg(&topic_name, 1);
+
+        Ok(Some(OK_BYTES.to_vec()))
+    }
+
+    async fn mpub<R>(
+        &self,
+        c: &ClientV2,
+        reader: &mut BufReader<R>,
+        params: &[&[u8]],
+    ) -> Result<Option<Vec<u8>>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if params.len() < 2 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "MPUB insufficient number of parameters".to_owned(),
+            ));
+        }
+
+        let topic_name = get_topic_name(params[1], "MPUB")?;
+
+        self.check_auth(c, "MPUB", &topic_name, "").await?;
+
+        let opts = self.nsqd.get_opts();
+        let body_len = read_len(reader, "MPUB", c.read_timeout()).await?;
+        if body_len == 0 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadBody,
+                format!("MPUB invalid body size {body_len}"),
+            ));
+        }
+        if body_len > opts.max_body_size {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadBody,
+                format!("MPUB body too big {body_len} > {}", opts.max_body_size),
+            ));
+        }
+
+        let topic = self.nsqd.get_topic(&topic_name);
+        let msgs = timed_read(
+            c.read_timeout(),
+            read_mpub(reader, opts.max_msg_size, body_len, &topic),
+        )
+        .await?;
+        let count = msgs.len() as u64;
+
+        topic.put_messages(msgs).await.map_err(|e| {
+            NsqError::FatalClientErr(ErrorCode::MpubFailed, format!("MPUB failed {e}"))
+        })?;
+
+        c.published_msg(&topic_name, count);
+
+        Ok(Some(OK_BYTES.to_vec()))
+    }
+
+    async fn dpub<R>(
+        &self,
+        c: &ClientV2,
+        reader: &mut BufReader<R>,
+        params: &[&[u8]],
+    ) -> Result<Option<Vec<u8>>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if params.len() < 3 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "DPUB insufficient number of parameters".to_owned(),
+            ));
+        }
+
+        let topic_name = get_topic_name(params[1], "DPUB")?;
+
+        let opts = self.nsqd.get_opts();
+        let timeout_ms = parse_int(params[2]).ok_or_else(|| {
+            NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                format!(
+                    "DPUB could not parse timeout {}",
+                    String::from_utf8_lossy(params[2])
+                ),
+            )
+        })?;
+        let max_req_timeout_ms = opts.max_req_timeout.as_millis() as i64;
+        if timeout_ms < 0 || timeout_ms > max_req_timeout_ms {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                format!("DPUB timeout {timeout_ms} out of range 0-{max_req_timeout_ms}"),
+            ));
+        }
+
+        let body_len = read_len(reader, "DPUB", c.read_timeout()).await?;
+        if body_len == 0 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadMessage,
+                format!("DPUB invalid message body size {body_len}"),
+            ));
+        }
+        if body_len > opts.max_msg_size {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadMessage,
+                format!("DPUB message too big {body_len} > {}", opts.max_msg_size),
+            ));
+        }
+
+        let mut body = vec![0; body_len as usize];
+        timed_read(c.read_timeout(), reader.read_exact(&mut body))
+            .await
+            .map_err(|_| {
+                NsqError::FatalClientErr(
+                    ErrorCode::BadMessage,
+                    "DPUB failed to read message body".to_owned(),
+                )
+            })?;
+
+        self.check_auth(c, "DPUB", &topic_name, "").await?;
+
+        let topic = self.nsqd.get_topic(&topic_name);
+        let mut msg = Message::new(topic.generate_id().await, body);
+        msg.deferred = Some(Duration::from_millis(timeout_ms as u64));
+        topic.put_message(msg).await.map_err(|e| {
+            NsqError::FatalClientErr(ErrorCode::PubFailed, format!("DPUB failed {e}"))
+        })?;
+
+        c.published_msg(&topic_name, 1);
+
+        Ok(Some(OK_BYTES.to_vec()))
+    }
+
+    async fn message_pump(self, c: Arc<ClientV2>, started_chan: oneshot::Sender<()>) {
+        let mut sub_event_rx = c.take_sub_event_rx();
+        let mut identify_event_rx = c.take_identify_event_rx();
+        let mut identified = false;
+        let mut sub_channel: Option<Arc<Channel>> = None;
+        let mut memory_msg_chan = None;
+        let mut backend_msg_chan = None;
+
+        let mut sample_rate = c.sample_rate();
+        let mut msg_timeout = c.msg_timeout();
+
+        // 响应类的帧会立即flush，消息帧则先写入缓冲区，由这里定时flush。
+        // output_buffer_timeout为0时每条消息都立即flush
+        let mut output_buffer_ticker = new_ticker(c.output_buffer_timeout());
+        let mut heartbeat_ticker = new_ticker(c.heartbeat_interval());
+
+        let _ = started_chan.send(());
+
+        let result = loop {
+            // 订阅之后才开始投递消息，CLS或者channel暂停之后不再投递，
+            // in-flight消息达到RDY之后等待ready state变化
+            let ready = sub_channel.as_ref().is_some_and(|ch| !ch.is_paused())
+                && c.state() == State::Subscribed
+                && c.is_ready_for_messages();
+
+            let msg = select! {
+                _ = tick(&mut output_buffer_ticker) => {
+                    if let Err(e) = c.flush().await {
+                        break Err(e.into());
+                    }
+                    continue;
+                }
+                _ = tick(&mut heartbeat_ticker) => {
+                    if let Err(e) = self.send(&c, FrameType::Response, HEARTBEAT_BYTES).await {
+                        break Err(e);
+                    }
+                    continue;
+                }
+                _ = c.ready_state_changed() => continue,
+                Ok(ev) = &mut identify_event_rx, if !identified => {
+                    identified = true;
+                    output_buffer_ticker = new_ticker(ev.output_buffer_timeout);
+                    heartbeat_ticker = new_ticker(ev.heartbeat_interval);
+                    sample_rate = ev.sample_rate;
+                    msg_timeout = ev.msg_timeout;
+                    continue;
+                }
+                Ok(channel) = &mut sub_event_rx, if sub_channel.is_none() => {
+                    memory_msg_chan = Some(channel.memory_msg_chan());
+                    backend_msg_chan = Some(channel.backend_msg_chan());
+                    sub_channel = Some(channel);
+                    continue;
+                }
+                Ok(msg) = recv(&memory_msg_chan), if ready => msg,
+                Ok(buf) = recv(&backend_msg_chan), if ready => {
+                    match Message::decode(&buf) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            error!("failed to decode message - {e}");
+                            continue;
+                        }
+                    }
+                }
+                _ = c.exited() => break Ok(()),
+            };
+
+            // 按照采样率丢弃一部分消息
+            if sample_rate > 0 && rand::random_range(0..100) > sample_rate {
+                continue;
+            }
+
+            let channel = sub_channel.as_ref().unwrap();
+            let mut msg = msg;
+            msg.attempts += 1;
+
+            if let Err(e) = channel.start_in_flight_timeout(msg.clone(), c.id, msg_timeout) {
+                error!(
+                    "PROTOCOL(V2): [{}] failed to start in-flight timeout - {e}",
+                    c.addr()
+                );
+                // 消息已经从队列中取出，放回channel避免丢失。延迟msg_timeout之后再投递，
+                // 此时in-flight中相同ID的消息已经处理完或者超时
+                msg.attempts -= 1;
+                if let Err(e) = channel.start_deferred_timeout(msg, msg_timeout) {
+                    error!(
+                        "PROTOCOL(V2): [{}] failed to put back message - {e}",
+                        c.addr()
+                    );
+                }
+                continue;
+            }
+            c.sending_msg();
+            if let Err(e) = self.send_msg(&c, &msg).await {
+                break Err(e);
+            }
+            if output_buffer_ticker.is_none() {
+                if let Err(e) = c.flush().await {
+                    break Err(e.into());
+                }
+            }
+        };
+
+        if let Err(e) = result {
+            error!("PROTOCOL(V2): [{}] messagePump error - {e}", c.addr());
+            c.close();
+        }
+
+        info!("PROTOCOL(V2): [{}] exiting messagePump", c.addr());
     }
 
-    pub async fn send_msg(&self, c: &ClientV2, msg: Message) -> Result<()> {
+    pub async fn send_msg(&self, c: &ClientV2, msg: &Message) -> Result<()> {
         debug!(
             "PROTOCOL(V2): writing msg({:#?}) to client({:#?}) - {:#?}",
             msg.id,
@@ -52,10 +917,756 @@ impl ProtocolV2 {
         let mut buf = Vec::new();
         msg.write_to(&mut buf).await?;
 
-        self.send(c, FrameType::Message, &buf);
+        self.send(c, FrameType::Message, &buf).await
+    }
+
+    async fn send(&self, c: &ClientV2, ft: FrameType, data: &[u8]) -> Result<()> {
+        let mut writer = c.writer.lock().await;
+        send_framed_response(&mut *writer, ft, data).await?;
+
+        // 消息帧依赖output buffer批量发送，其他帧需要立即发送给客户端
+        if ft != FrameType::Message {
+            writer.flush().await?;
+        }
 
         Ok(())
     }
+}
+
+// 帧格式：
+//
+//	[x][x][x][x][x][x][x][x][x][x][x][x]...
+//	|  (int32) ||  (int32) || (binary)
+//	|  4-byte  ||  4-byte  || N-byte
+//	------------------------------------...
+//	    size     frame type     data
+//
+// size包含了frame type的4个字节
+pub(super) async fn send_framed_response<W>(w: &mut W, ft: FrameType, data: &[u8]) -> Result<usize>
+where
+    W: AsyncWrite + Unpin,
+{
+    w.write_u32(data.len() as u32 + 4).await?;
+    w.write_u32(ft as u32).await?;
+    w.write_all(data).await?;
+    Ok(data.len() + 8)
+}
+
+// period为0时不启用。第一次触发在一个period之后，避免在IDENTIFY之前发送心跳
+fn new_ticker(period: Duration) -> Option<Interval> {
+    if period.is_zero() {
+        return None;
+    }
+    let mut ticker = interval_at(Instant::now() + period, period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    Some(ticker)
+}
+
+async fn tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => pending().await,
+    }
+}
+
+// 和golang版本一样，一行命令不能超过读缓冲区的大小，避免客户端一直不发送\n导致内存无限增长
+async fn read_line<R>(
+    reader: &mut BufReader<R>,
+    line: &mut Vec<u8>,
+    read_timeout: Option<Duration>,
+) -> Result<usize>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut limited = reader.take(MAX_LINE_LENGTH as u64);
+    let n = match read_timeout {
+        Some(read_timeout) => timeout(read_timeout, limited.read_until(b'\n', line))
+            .await
+            .unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("no command received in {read_timeout:?}"),
+                ))
+            })?,
+        None => limited.read_until(b'\n', line).await?,
+    };
+
+    if n == MAX_LINE_LENGTH && line.last() != Some(&b'\n') {
+        return Err(NsqError::FatalClientErr(
+            ErrorCode::Invalid,
+            format!("command too long, exceeds {MAX_LINE_LENGTH} bytes"),
+        ));
+    }
+    Ok(n)
+}
+
+// 订阅之前没有可读取的消息队列
+async fn recv<T>(rx: &Option<Receiver<T>>) -> std::result::Result<T, RecvError> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => pending().await,
+    }
+}
+
+// FIN/REQ/TOUCH只能在订阅之后执行
+fn subscribed_channel(c: &ClientV2, cmd: &str) -> Result<Arc<Channel>> {
+    match (c.state(), c.channel()) {
+        (State::Subscribed | State::Closing, Some(channel)) => Ok(channel),
+        _ => Err(NsqError::FatalClientErr(
+            ErrorCode::Invalid,
+            format!("cannot {cmd} in current state"),
+        )),
+    }
+}
+
+fn get_topic_name(p: &[u8], cmd: &str) -> Result<String> {
+    let topic_name = String::from_utf8_lossy(p).into_owned();
+    if !is_valid_topic_name(&topic_name) {
+        return Err(NsqError::FatalClientErr(
+            ErrorCode::BadTopic,
+            format!("{cmd} topic name {topic_name:?} is not valid"),
+        ));
+    }
+    Ok(topic_name)
+}
+
+fn get_message_id(p: &[u8]) -> Result<MessageID> {
+    p.try_into().map_err(|_| {
+        NsqError::FatalClientErr(
+            ErrorCode::Invalid,
+            format!("Invalid Message ID, length {} != {MSG_ID_LENGTH}", p.len()),
+        )
+    })
+}
+
+fn parse_int(p: &[u8]) -> Option<i64> {
+    std::str::from_utf8(p).ok()?.parse().ok()
+}
+
+// 读取body同样受心跳超时的限制，避免客户端发送命令之后不再发送数据而一直占用连接
+async fn timed_read<T, E>(
+    read_timeout: Option<Duration>,
+    fut: impl Future<Output = std::result::Result<T, E>>,
+) -> std::result::Result<T, E>
+where
+    E: From<io::Error>,
+{
+    match read_timeout {
+        Some(read_timeout) => timeout(read_timeout, fut).await.unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no body received in {read_timeout:?}"),
+            )
+            .into())
+        }),
+        None => fut.await,
+    }
+}
+
+async fn read_len<R>(
+    reader: &mut BufReader<R>,
+    cmd: &str,
+    read_timeout: Option<Duration>,
+) -> Result<u32>
+where
+    R: AsyncRead + Unpin,
+{
+    timed_read(read_timeout, reader.read_u32())
+        .await
+        .map_err(|_| {
+            NsqError::FatalClientErr(
+                ErrorCode::BadBody,
+                format!("{cmd} failed to read body size"),
+            )
+        })
+}
+
+async fn read_body<R>(
+    reader: &mut BufReader<R>,
+    max_body_size: u32,
+    cmd: &str,
+    read_timeout: Option<Duration>,
+) -> Result<Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+{
+    let body_len = read_len(reader, cmd, read_timeout).await?;
+    if body_len == 0 {
+        return Err(NsqError::FatalClientErr(
+            ErrorCode::BadBody,
+            format!("{cmd} invalid body size {body_len}"),
+        ));
+    }
+    if body_len > max_body_size {
+        return Err(NsqError::FatalClientErr(
+            ErrorCode::BadBody,
+            format!("{cmd} body too big {body_len} > {max_body_size}"),
+        ));
+    }
+
+    let mut body = vec![0; body_len as usize];
+    timed_read(read_timeout, reader.read_exact(&mut body))
+        .await
+        .map_err(|_| {
+            NsqError::FatalClientErr(ErrorCode::BadBody, format!("{cmd} failed to read body"))
+        })?;
+    Ok(body)
+}
+
+// MPUB的body格式：
+//
+//	[ 4-byte num messages ]
+//	[ 4-byte message #1 size ][ N-byte binary data ]
+//	    ... (repeated <num_messages> times)
+pub(super) async fn read_mpub<R>(
+    reader: &mut BufReader<R>,
+    max_msg_size: u32,
+    body_len: u32,
+    topic: &Topic,
+) -> Result<Vec<Message>>
+where
+    R: AsyncRead + Unpin,
+{
+    let num_messages = reader.read_u32().await.map_err(|_| {
+        NsqError::FatalClientErr(
+            ErrorCode::BadBody,
+            "MPUB failed to read message count".to_owned(),
+        )
+    })?;
+
+    // 每条消息至少有4字节的长度
+    let max_messages = body_len.saturating_sub(4) / 4;
+    if num_messages == 0 || num_messages > max_messages {
+        return Err(NsqError::FatalClientErr(
+            ErrorCode::BadBody,
+            format!("MPUB invalid message count {num_messages}"),
+        ));
+    }
+
+    let mut msgs = Vec::with_capacity(num_messages as usize);
+    for _ in 0..num_messages {
+        let msg_size = reader.read_u32().await.map_err(|_| {
+            NsqError::FatalClientErr(
+                ErrorCode::BadMessage,
+                "MPUB failed to read message body size".to_owned(),
+            )
+        })?;
+        if msg_size == 0 {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadMessage,
+                format!("MPUB invalid message body size {msg_size}"),
+            ));
+        }
+        if msg_size > max_msg_size {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadMessage,
+                format!("MPUB message too big {msg_size} > {max_msg_size}"),
+            ));
+        }
+
+        let mut body = vec![0; msg_size as usize];
+        reader.read_exact(&mut body).await.map_err(|_| {
+            NsqError::FatalClientErr(
+                ErrorCode::BadMessage,
+                "MPUB failed to read message body".to_owned(),
+            )
+        })?;
+
+        msgs.push(Message::new(topic.generate_id().await, body));
+    }
+
+    Ok(msgs)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::nsqd::{
+        compress::{Decoder, Encoder},
+        test_util::{test_options, TestClient, TestNsqd},
+    };
+
+    async fn identify(client: &mut TestClient, data: Value) -> Vec<u8> {
+        client
+            .command_with_body("IDENTIFY", data.to_string().as_bytes())
+            .await;
+        let (ft, body) = client.read_frame().await.unwrap();
+        assert_eq!(ft, FrameType::Response as u32);
+        body
+    }
+
+    #[tokio::test]
+    async fn command_too_long() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let mut client = server.connect().await;
+
+        // 一直不发送\n
+        client.send(&vec![b'A'; MAX_LINE_LENGTH + 1]).await;
+        let (ft, body) = client.read_frame().await.unwrap();
+        assert_eq!(ft, FrameType::Error as u32);
+        assert!(body.starts_with(b"E_INVALID"));
+        assert!(client.read_frame().await.is_none());
+
+        // 不超过限制的命令仍然可以正常处理
+        let mut client = server.connect().await;
+        client.command("NOP").await;
+        client.command("PUB").await;
+        let (ft, body) = client.read_frame().await.unwrap();
+        assert_eq!(ft, FrameType::Error as u32);
+        assert!(body.starts_with(b"E_INVALID"));
 
-    fn send(&self, c: &ClientV2, ft: FrameType, data: &[u8]) {}
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn identify_clamps_to_limits() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let opts = server.nsqd.get_opts();
+
+        // 超出服务端限制的值调整到限制范围内
+        let mut client = server.connect().await;
+        let body = identify(
+            &mut client,
+            json!({
+                "client_id": "test",
+                "feature_negotiation": true,
+                "heartbeat_interval": 1,
+                "output_buffer_size": 10,
+                "output_buffer_timeout": 3_600_000,
+                "sample_rate": 200,
+                "msg_timeout": 3_600_000,
+            }),
+        )
+        .await;
+        let resp: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(resp["output_buffer_size"], 64);
+        assert_eq!(
+            resp["output_buffer_timeout"],
+            opts.max_output_buffer_timeout.as_millis() as u64
+        );
+        assert_eq!(resp["sample_rate"], 99);
+        assert_eq!(resp["msg_timeout"], opts.max_msg_timeout.as_millis() as u64);
+        assert_eq!(
+            resp["max_msg_timeout"],
+            opts.max_msg_timeout.as_millis() as u64
+        );
+        assert_eq!(resp["max_rdy_count"], opts.max_rdy_count);
+        assert_eq!(resp["tls_v1"], false);
+        assert_eq!(resp["auth_required"], false);
+
+        // 心跳间隔调整为1秒
+        let (ft, body) = tokio::time::timeout(Duration::from_secs(3), client.read_frame())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ft, FrameType::Response as u32);
+        assert_eq!(body, HEARTBEAT_BYTES);
+
+        // 小于下限的值调整到下限
+        let mut client = server.connect().await;
+        let body = identify(
+            &mut client,
+            json!({
+                "feature_negotiation": true,
+                "output_buffer_size": 1_000_000,
+                "output_buffer_timeout": 1,
+                "sample_rate": -5,
+                "msg_timeout": 1,
+            }),
+        )
+        .await;
+        let resp: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(resp["output_buffer_size"], opts.max_output_buffer_size);
+        assert_eq!(
+            resp["output_buffer_timeout"],
+            opts.min_output_buffer_timeout.as_millis() as u64
+        );
+        assert_eq!(resp["sample_rate"], 0);
+        assert_eq!(resp["msg_timeout"], 1000);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn identify_negotiation() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let opts = server.nsqd.get_opts();
+
+        // 不支持特性协商的客户端只返回OK
+        let mut client = server.connect().await;
+        let body = identify(&mut client, json!({"client_id": "test"})).await;
+        assert_eq!(body, OK_BYTES);
+
+        // 0表示使用默认值
+        let mut client = server.connect().await;
+        let body = identify(&mut client, json!({"feature_negotiation": true})).await;
+        let resp: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(resp["output_buffer_size"], DEFAULT_BUF_SIZE);
+        assert_eq!(
+            resp["output_buffer_timeout"],
+            opts.output_buffer_timeout.as_millis() as u64
+        );
+        assert_eq!(resp["msg_timeout"], opts.msg_timeout.as_millis() as u64);
+        assert_eq!(resp["sample_rate"], 0);
+
+        // -1表示关闭输出缓冲
+        let mut client = server.connect().await;
+        let body = identify(
+            &mut client,
+            json!({
+                "feature_negotiation": true,
+                "heartbeat_interval": -1,
+                "output_buffer_size": -1,
+            }),
+        )
+        .await;
+        let resp: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(resp["output_buffer_size"], 1);
+        assert_eq!(resp["output_buffer_timeout"], 0);
+
+        // 不能重复IDENTIFY
+        client.command_with_body("IDENTIFY", b"{}").await;
+        let (ft, body) = client.read_frame().await.unwrap();
+        assert_eq!(ft, FrameType::Error as u32);
+        assert!(body.starts_with(b"E_INVALID"));
+
+        server.stop().await;
+    }
+
+    // 开启压缩之后，发送的命令和收到的帧都要经过编解码
+    async fn send_compressed(client: &mut TestClient, encoder: &mut Encoder, data: &[u8]) {
+        let mut out = Vec::new();
+        encoder.write(data).unwrap();
+        encoder.flush().unwrap();
+        encoder.take_output(&mut out);
+        client.send(&out).await;
+    }
+
+    async fn read_compressed_frame(
+        client: &mut TestClient,
+        decoder: &mut Decoder,
+    ) -> (u32, Vec<u8>) {
+        loop {
+            let out = decoder.output();
+            if out.len() >= 4 {
+                let size = u32::from_be_bytes(out[..4].try_into().unwrap()) as usize;
+                if out.len() >= 4 + size {
+                    let frame: Vec<u8> = out.drain(..4 + size).collect();
+                    let frame_type = u32::from_be_bytes(frame[4..8].try_into().unwrap());
+                    return (frame_type, frame[8..].to_vec());
+                }
+            }
+            let mut buf = [0; 4096];
+            let n = client.read(&mut buf).await;
+            assert!(n > 0, "connection closed");
+            decoder.decode(&buf[..n]).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn identify_compression() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let mut producer = server.connect().await;
+
+        let codecs = [
+            ("snappy", Encoder::snappy(), Decoder::snappy()),
+            ("deflate", Encoder::deflate(3), Decoder::deflate()),
+        ];
+        for (i, (feature, mut encoder, mut decoder)) in codecs.into_iter().enumerate() {
+            let mut client = server.connect().await;
+            let body = identify(
+                &mut client,
+                json!({"feature_negotiation": true, feature: true, "deflate_level": 3}),
+            )
+            .await;
+            let resp: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(resp[feature], true);
+
+            // 升级之后的OK已经是压缩过的
+            let (ft, body) = read_compressed_frame(&mut client, &mut decoder).await;
+            assert_eq!((ft, &body[..]), (FrameType::Response as u32, OK_BYTES));
+
+            let channel = format!("c{i}");
+            send_compressed(
+                &mut client,
+                &mut encoder,
+                format!("SUB t {channel}\nRDY 1\n").as_bytes(),
+            )
+            .await;
+            let (_, body) = read_compressed_frame(&mut client, &mut decoder).await;
+            assert_eq!(body, OK_BYTES);
+
+            // 消息超过64KB，snappy编码时会拆成多个chunk
+            let payload = feature.repeat(20000);
+            producer
+                .command_with_body("PUB t", payload.as_bytes())
+                .await;
+            assert_eq!(producer.read_frame().await.unwrap().1, OK_BYTES);
+            let (ft, body) = read_compressed_frame(&mut client, &mut decoder).await;
+            assert_eq!(ft, FrameType::Message as u32);
+            let msg = Message::decode(&body).unwrap();
+            assert_eq!(msg.body, payload.as_bytes());
+        }
+
+        // 不能同时开启两种压缩
+        let mut client = server.connect().await;
+        client
+            .command_with_body(
+                "IDENTIFY",
+                json!({"feature_negotiation": true, "snappy": true, "deflate": true})
+                    .to_string()
+                    .as_bytes(),
+            )
+            .await;
+        let (ft, body) = client.read_frame().await.unwrap();
+        assert_eq!(ft, FrameType::Error as u32);
+        assert!(body.starts_with(b"E_IDENTIFY_FAILED"), "{body:?}");
+
+        server.stop().await;
+    }
+
+    // 读取到连接关闭为止，返回期间收到的心跳数量
+    async fn heartbeats_until_closed(client: &mut TestClient) -> usize {
+        let mut heartbeats = 0;
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), client.read_frame())
+                .await
+                .unwrap()
+            {
+                Some((ft, body)) if ft == FrameType::Response as u32 => {
+                    assert_eq!(body, HEARTBEAT_BYTES);
+                    heartbeats += 1;
+                }
+                Some((ft, body)) => {
+                    // 读取body超时返回的错误
+                    assert_eq!(ft, FrameType::Error as u32);
+                    assert!(body.starts_with(b"E_BAD_MESSAGE"), "{body:?}");
+                }
+                None => return heartbeats,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn heartbeats() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let heartbeat = json!({"feature_negotiation": true, "heartbeat_interval": 1000});
+
+        // 响应心跳的客户端一直保持连接
+        let mut alive = server.connect().await;
+        identify(&mut alive, heartbeat.clone()).await;
+        // 从不响应心跳的客户端在max_missed_heartbeats个间隔之后被断开
+        let mut silent = server.connect().await;
+        identify(&mut silent, heartbeat.clone()).await;
+        // 发送命令之后不再发送body的客户端同样会被断开
+        let mut stalled = server.connect().await;
+        identify(&mut stalled, heartbeat).await;
+        stalled.command("PUB t").await;
+        stalled.send(&[0, 0, 0, 10, b'a']).await;
+
+        let start = Instant::now();
+        let alive = async {
+            for _ in 0..4 {
+                let (ft, body) = alive.read_frame().await.unwrap();
+                assert_eq!(ft, FrameType::Response as u32);
+                assert_eq!(body, HEARTBEAT_BYTES);
+                alive.command("NOP").await;
+            }
+        };
+        let (_, silent, stalled) = tokio::join!(
+            alive,
+            heartbeats_until_closed(&mut silent),
+            heartbeats_until_closed(&mut stalled),
+        );
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert!(silent >= 1, "{silent}");
+        assert!(stalled >= 1, "{stalled}");
+        assert_eq!(server.nsqd.get_topic("t").depth(), 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn put_back_when_already_in_flight() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let channel = server.nsqd.get_topic("t").get_channel("c");
+
+        // 相同ID的消息已经在in-flight队列中，投递失败时不能丢失
+        let msg = Message::new([1; 16], b"dup".to_vec());
+        channel
+            .start_in_flight_timeout(msg.clone(), -1, Duration::from_secs(60))
+            .unwrap();
+        channel.put_message(msg).await.unwrap();
+
+        let mut client = server.connect().await;
+        identify(
+            &mut client,
+            json!({"feature_negotiation": true, "msg_timeout": 1000}),
+        )
+        .await;
+        client.command("SUB t c").await;
+        assert_eq!(client.read_frame().await.unwrap().1, OK_BYTES);
+        client.command("RDY 1").await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(channel.deferred_count(), 1);
+        channel.finish_message(-1, &[1; 16]).unwrap();
+
+        let msg = tokio::time::timeout(Duration::from_secs(3), client.read_message())
+            .await
+            .unwrap();
+        assert_eq!(msg.id, [1; 16]);
+        assert_eq!(msg.attempts, 1);
+        assert_eq!(msg.body, b"dup");
+
+        server.stop().await;
+    }
+
+    async fn fin(client: &mut TestClient, id: &MessageID) {
+        let mut line = b"FIN ".to_vec();
+        line.extend_from_slice(id);
+        line.push(b'\n');
+        client.send(&line).await;
+    }
+
+    // 在给定时间内没有收到任何帧
+    async fn assert_no_frame(client: &mut TestClient) {
+        let res = tokio::time::timeout(Duration::from_millis(200), client.read_frame()).await;
+        assert!(res.is_err(), "unexpected frame {res:?}");
+    }
+
+    #[tokio::test]
+    async fn rdy_flow_control() {
+        let dir = TempDir::new().unwrap();
+        let mut opts = test_options(dir.path());
+        opts.output_buffer_timeout = Duration::from_millis(10);
+        let server = TestNsqd::start(opts).await;
+        let max_rdy_count = server.nsqd.get_opts().max_rdy_count;
+
+        let mut client = server.connect().await;
+        client.command("SUB t c").await;
+        assert_eq!(client.read_frame().await.unwrap().1, OK_BYTES);
+        let topic = server.nsqd.get_topic("t");
+        for body in [b"1", b"2", b"3"] {
+            let msg = Message::new(topic.generate_id().await, body.to_vec());
+            topic.put_message(msg).await.unwrap();
+        }
+
+        // 订阅之后RDY为0，不会投递消息
+        assert_no_frame(&mut client).await;
+        client.command("RDY 1").await;
+        let first = client.read_message().await;
+        // RDY 0之后停止投递
+        client.command("RDY 0").await;
+        fin(&mut client, &first.id).await;
+        assert_no_frame(&mut client).await;
+
+        // in-flight的消息数量达到RDY之后暂停投递，直到FIN
+        client.command("RDY 2").await;
+        let second = client.read_message().await;
+        let third = client.read_message().await;
+        assert_eq!([second.body, third.body], [b"2", b"3"]);
+        let msg = Message::new(topic.generate_id().await, b"4".to_vec());
+        topic.put_message(msg).await.unwrap();
+        assert_no_frame(&mut client).await;
+        fin(&mut client, &second.id).await;
+        assert_eq!(client.read_message().await.body, b"4");
+
+        // 超过max_rdy_count是致命错误
+        client.command(&format!("RDY {}", max_rdy_count + 1)).await;
+        let (ft, body) = client.read_frame().await.unwrap();
+        assert_eq!(ft, FrameType::Error as u32);
+        assert!(body.starts_with(b"E_INVALID"), "{body:?}");
+        assert!(client.read_frame().await.is_none());
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn frame_layout() {
+        let mut buf = Vec::new();
+        let n = send_framed_response(&mut buf, FrameType::Response, OK_BYTES)
+            .await
+            .unwrap();
+        assert_eq!(n, 10);
+        assert_eq!(buf, b"\x00\x00\x00\x06\x00\x00\x00\x00OK");
+
+        let mut buf = Vec::new();
+        send_framed_response(&mut buf, FrameType::Error, b"E_INVALID bad")
+            .await
+            .unwrap();
+        assert_eq!(&buf[..8], [0, 0, 0, 17, 0, 0, 0, 1]);
+        assert_eq!(&buf[8..], b"E_INVALID bad");
+
+        // 消息帧的内容是[8字节时间戳][2字节尝试次数][16字节ID][body]
+        let mut msg = Message::new(*b"0123456789abcdef", b"body".to_vec());
+        msg.attempts = 3;
+        let mut data = Vec::new();
+        msg.write_to(&mut data).await.unwrap();
+        let mut buf = Vec::new();
+        send_framed_response(&mut buf, FrameType::Message, &data)
+            .await
+            .unwrap();
+        assert_eq!(u32::from_be_bytes(buf[..4].try_into().unwrap()), 4 + 26 + 4);
+        assert_eq!(u32::from_be_bytes(buf[4..8].try_into().unwrap()), 2);
+        assert_eq!(&buf[8..16], msg.timestamp.to_be_bytes());
+        assert_eq!(&buf[16..18], [0, 3]);
+        assert_eq!(&buf[18..34], b"0123456789abcdef");
+        assert_eq!(&buf[34..], b"body");
+
+        let decoded = Message::decode(&buf[8..]).unwrap();
+        assert_eq!(decoded.timestamp, msg.timestamp);
+        assert_eq!(decoded.attempts, 3);
+        assert_eq!(decoded.id, msg.id);
+        assert_eq!(decoded.body, b"body");
+    }
+
+    #[tokio::test]
+    async fn output_buffer_flush() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let topic = server.nsqd.get_topic("t");
+
+        // 消息帧在output_buffer_timeout之后才发送
+        let mut buffered = server.connect().await;
+        identify(
+            &mut buffered,
+            json!({"feature_negotiation": true, "output_buffer_timeout": 1000}),
+        )
+        .await;
+        // 关闭输出缓冲时立即发送
+        let mut unbuffered = server.connect().await;
+        identify(
+            &mut unbuffered,
+            json!({"feature_negotiation": true, "output_buffer_size": -1}),
+        )
+        .await;
+        for (client, channel) in [(&mut buffered, "a"), (&mut unbuffered, "b")] {
+            client.command(&format!("SUB t {channel}")).await;
+            assert_eq!(client.read_frame().await.unwrap().1, OK_BYTES);
+            client.command("RDY 1").await;
+        }
+        // 等待RDY生效
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = Instant::now();
+        let msg = Message::new(topic.generate_id().await, b"m".to_vec());
+        topic.put_message(msg).await.unwrap();
+
+        let msg = unbuffered.read_message().await;
+        assert_eq!(msg.body, b"m");
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        assert_no_frame(&mut buffered).await;
+        let msg = tokio::time::timeout(Duration::from_secs(2), buffered.read_message())
+            .await
+            .unwrap();
+        assert_eq!(msg.body, b"m");
+
+        server.stop().await;
+    }
 }
@@ -0,0 +1,156 @@
+use std::{
+    fmt::{self, Display},
+    sync::Arc,
+};
+
+use serde::Serialize;
+
+use super::{channel::Channel, topic::Topic};
+
+#[derive(Serialize)]
+pub(super) struct TopicStats {
+    pub topic_name: String,
+    pub channels: Vec<ChannelStats>,
+    pub depth: i64,
+    pub backend_depth: i64,
+    pub message_count: u64,
+    pub message_bytes: u64,
+    pub paused: bool,
+}
+
+impl TopicStats {
+    pub fn new(t: &Topic, channels: Vec<ChannelStats>) -> Self {
+        Self {
+            topic_name: t.name().to_owned(),
+            channels,
+            depth: t.depth(),
+            backend_depth: t.backend_depth(),
+            message_count: t.message_count(),
+            message_bytes: t.message_bytes(),
+            paused: t.is_paused(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(super) struct ChannelStats {
+    pub channel_name: String,
+    pub depth: i64,
+    pub backend_depth: i64,
+    pub in_flight_count: usize,
+    pub deferred_count: usize,
+    pub message_count: u64,
+    pub requeue_count: u64,
+    pub timeout_count: u64,
+    pub client_count: usize,
+    pub clients: Vec<ClientStats>,
+    pub paused: bool,
+}
+
+impl ChannelStats {
+    pub fn new(c: &Channel, clients: Vec<ClientStats>, client_count: usize) -> Self {
+        Self {
+            channel_name: c.name().to_owned(),
+            depth: c.depth(),
+            backend_depth: c.backend_depth(),
+            in_flight_count: c.in_flight_count(),
+            deferred_count: c.deferred_count(),
+            message_count: c.message_count(),
+            requeue_count: c.requeue_count(),
+            timeout_count: c.timeout_count(),
+            client_count,
+            clients,
+            paused: c.is_paused(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(super) struct PubCount {
+    pub topic: String,
+    pub count: u64,
+}
+
+#[derive(Serialize)]
+pub(super) struct ClientStats {
+    pub client_id: String,
+    pub hostname: String,
+    pub version: &'static str,
+    pub remote_address: String,
+    pub state: i32,
+    pub ready_count: i64,
+    pub in_flight_count: i64,
+    pub message_count: u64,
+    pub finish_count: u64,
+    pub requeue_count: u64,
+    pub connect_ts: i64,
+    pub sample_rate: i32,
+    pub deflate: bool,
+    pub snappy: bool,
+    pub user_agent: String,
+    pub tls: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub authed: bool,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub auth_identity: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub auth_identity_url: String,
+    pub pub_counts: Vec<PubCount>,
+    // 连接时长，只用于文本格式的输出
+    #[serde(skip)]
+    pub connected_secs: u64,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+// 文本格式的/stats中每个客户端占一行，生产者和消费者展示的内容不同
+impl Display for ClientStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let port = self.remote_address.rsplit(':').next().unwrap_or_default();
+        let id = format!("{}:{} {}", self.hostname, port, self.user_agent);
+        let connected = format!("{}s", self.connected_secs);
+
+        if !self.pub_counts.is_empty() {
+            let total: u64 = self.pub_counts.iter().map(|p| p.count).sum();
+            let topics: Vec<_> = self
+                .pub_counts
+                .iter()
+                .map(|p| format!("{}={}", p.topic, p.count))
+                .collect();
+            return write!(
+                f,
+                "[{} {:<21}] msgs: {:<8} topics: {} connected: {}",
+                self.version,
+                id,
+                total,
+                topics.join(","),
+                connected
+            );
+        }
+
+        write!(
+            f,
+            "[{} {:<21}] state: {} inflt: {:<4} rdy: {:<4} fin: {:<8} re-q: {:<8} msgs: {:<8} connected: {}",
+            self.version,
+            id,
+            self.state,
+            self.in_flight_count,
+            self.ready_count,
+            self.finish_count,
+            self.requeue_count,
+            self.message_count,
+            connected
+        )
+    }
+}
+
+// 按照名称排序，保证每次输出的顺序一致
+pub(super) fn sort_topics(topics: &mut [Arc<Topic>]) {
+    topics.sort_by(|a, b| a.name().cmp(b.name()));
+}
+
+pub(super) fn sort_channels(channels: &mut [Arc<Channel>]) {
+    channels.sort_by(|a, b| a.name().cmp(b.name()));
+}
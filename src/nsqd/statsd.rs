@@ -0,0 +1,359 @@
+use std::{fs, io, mem, sync::Arc};
+
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    select,
+    time::{interval_at, Instant},
+};
+use tracing::{error, info};
+
+use super::{nsqd::NSQD, stats::TopicStats};
+use crate::{common::is_ephemeral, shutdown::Shutdown};
+
+impl NSQD {
+    // 每隔statsd_interval把topic和channel的统计数据推送到statsd，计数类的指标只推送和上一次的差值
+    pub(super) async fn statsd_loop(self: Arc<Self>, mut shutdown: Shutdown) {
+        let opts = self.get_opts();
+        let mut last_stats: Vec<TopicStats> = Vec::new();
+
+        let mut ticker = interval_at(Instant::now() + opts.statsd_interval, opts.statsd_interval);
+        loop {
+            select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.recv() => break,
+            }
+
+            let mut client = StatsdClient::new(self.statsd_prefix(), opts.statsd_udp_packet_size);
+
+            let stats = self.get_stats(None, None, true);
+            for topic in &stats {
+                if opts.statsd_exclude_ephemeral && is_ephemeral(&topic.topic_name) {
+                    continue;
+                }
+                let last_topic = last_stats.iter().find(|t| t.topic_name == topic.topic_name);
+                let prefix = format!("topic.{}", topic.topic_name);
+
+                let last = last_topic.map(|t| t.message_count).unwrap_or_default();
+                client.incr(
+                    &format!("{prefix}.message_count"),
+                    diff(topic.message_count, last),
+                );
+                let last = last_topic.map(|t| t.message_bytes).unwrap_or_default();
+                client.incr(
+                    &format!("{prefix}.message_bytes"),
+                    diff(topic.message_bytes, last),
+                );
+                client.gauge(&format!("{prefix}.depth"), topic.depth);
+                client.gauge(&format!("{prefix}.backend_depth"), topic.backend_depth);
+
+                for channel in &topic.channels {
+                    if opts.statsd_exclude_ephemeral && is_ephemeral(&channel.channel_name) {
+                        continue;
+                    }
+                    let last_channel = last_topic.and_then(|t| {
+                        t.channels
+                            .iter()
+                            .find(|c| c.channel_name == channel.channel_name)
+                    });
+                    let prefix = format!("{prefix}.channel.{}", channel.channel_name);
+
+                    let last = last_channel.map(|c| c.message_count).unwrap_or_default();
+                    client.incr(
+                        &format!("{prefix}.message_count"),
+                        diff(channel.message_count, last),
+                    );
+                    client.gauge(&format!("{prefix}.depth"), channel.depth);
+                    client.gauge(&format!("{prefix}.backend_depth"), channel.backend_depth);
+                    client.gauge(
+                        &format!("{prefix}.in_flight_count"),
+                        channel.in_flight_count as i64,
+                    );
+                    client.gauge(
+                        &format!("{prefix}.deferred_count"),
+                        channel.deferred_count as i64,
+                    );
+                    let last = last_channel.map(|c| c.requeue_count).unwrap_or_default();
+                    client.incr(
+                        &format!("{prefix}.requeue_count"),
+                        diff(channel.requeue_count, last),
+                    );
+                    let last = last_channel.map(|c| c.timeout_count).unwrap_or_default();
+                    client.incr(
+                        &format!("{prefix}.timeout_count"),
+                        diff(channel.timeout_count, last),
+                    );
+                    client.gauge(&format!("{prefix}.clients"), channel.client_count as i64);
+
+                    // 客户端按远程地址区分，计数类的指标同样推送差值
+                    for c in &channel.clients {
+                        let last_client = last_channel.and_then(|ch| {
+                            ch.clients
+                                .iter()
+                                .find(|lc| lc.remote_address == c.remote_address)
+                        });
+                        let prefix = format!(
+                            "{prefix}.client.{}",
+                            c.remote_address.replace(['.', ':'], "_")
+                        );
+
+                        let last = last_client.map(|c| c.message_count).unwrap_or_default();
+                        client.incr(
+                            &format!("{prefix}.message_count"),
+                            diff(c.message_count, last),
+                        );
+                        let last = last_client.map(|c| c.finish_count).unwrap_or_default();
+                        client.incr(
+                            &format!("{prefix}.finish_count"),
+                            diff(c.finish_count, last),
+                        );
+                        let last = last_client.map(|c| c.requeue_count).unwrap_or_default();
+                        client.incr(
+                            &format!("{prefix}.requeue_count"),
+                            diff(c.requeue_count, last),
+                        );
+                        client.gauge(&format!("{prefix}.ready_count"), c.ready_count);
+                        client.gauge(&format!("{prefix}.in_flight_count"), c.in_flight_count);
+                    }
+                }
+            }
+
+            if opts.statsd_mem_stats {
+                mem_stats(&mut client);
+            }
+
+            info!("STATSD: pushing stats to {}", opts.statsd_address);
+            match client.flush(&opts.statsd_address).await {
+                Ok(_) => last_stats = stats,
+                // 推送失败时保留上一次的数据，下一次推送的差值包含这一次的
+                Err(e) => error!("failed to push stats to {} - {e}", opts.statsd_address),
+            }
+        }
+
+        info!("STATSD: closing");
+    }
+
+    // 和golang版本一样，用broadcast地址和http端口替换前缀中的%s，其中的.和:替换成_
+    fn statsd_prefix(&self) -> String {
+        let opts = self.get_opts();
+        let host_key = format!("{}:{}", opts.broadcast_addr, opts.broadcast_http_port)
+            .replace(['.', ':'], "_");
+        let mut prefix = opts.statsd_prefix.replace("%s", &host_key);
+        if !prefix.is_empty() && !prefix.ends_with('.') {
+            prefix.push('.');
+        }
+        prefix
+    }
+}
+
+fn diff(cur: u64, last: u64) -> i64 {
+    cur as i64 - last as i64
+}
+
+// 从/proc/self/status读取进程的内存占用，其他平台不推送
+fn mem_stats(client: &mut StatsdClient) {
+    let Ok(status) = fs::read_to_string("/proc/self/status") else {
+        return;
+    };
+    for line in status.lines() {
+        let stat = match line.split(':').next() {
+            Some("VmRSS") => "mem.resident_bytes",
+            Some("VmSize") => "mem.virtual_bytes",
+            Some("VmData") => "mem.data_bytes",
+            _ => continue,
+        };
+        let kb = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|v| v.parse::<i64>().ok());
+        if let Some(kb) = kb {
+            client.gauge(stat, kb * 1024);
+        }
+    }
+}
+
+// 把指标按行拼接成不超过packet_size的UDP包，单个指标超过packet_size时单独发送
+struct StatsdClient {
+    prefix: String,
+    packet_size: usize,
+    buf: String,
+    packets: Vec<String>,
+}
+
+impl StatsdClient {
+    fn new(prefix: String, packet_size: usize) -> Self {
+        Self {
+            prefix,
+            packet_size,
+            buf: String::new(),
+            packets: Vec::new(),
+        }
+    }
+
+    fn incr(&mut self, stat: &str, count: i64) {
+        self.send(stat, format_args!("{count}|c"));
+    }
+
+    fn gauge(&mut self, stat: &str, value: i64) {
+        self.send(stat, format_args!("{value}|g"));
+    }
+
+    fn send(&mut self, stat: &str, value: std::fmt::Arguments) {
+        let line = format!("{}{stat}:{value}\n", self.prefix);
+        if !self.buf.is_empty() && self.buf.len() + line.len() > self.packet_size {
+            self.packets.push(mem::take(&mut self.buf));
+        }
+        self.buf.push_str(&line);
+    }
+
+    async fn flush(mut self, addr: &str) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.packets.push(mem::take(&mut self.buf));
+        }
+
+        let target = lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address resolved"))?;
+        let local = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(target).await?;
+
+        for packet in &self.packets {
+            socket.send(packet.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::nsqd::test_util::{test_options, TestNsqd};
+
+    const PACKET_SIZE: usize = 200;
+
+    // 接收一次推送的所有UDP包，直到收到last这一行
+    async fn recv_push(statsd: &UdpSocket, last: &str) -> Vec<String> {
+        // 一次推送的指标超过packet_size，需要拆分成多个UDP包
+        let mut lines = Vec::new();
+        let mut packets = 0;
+        let mut buf = vec![0; 65536];
+        while !lines.iter().any(|l| l == last) {
+            let n = timeout(Duration::from_secs(5), statsd.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n <= PACKET_SIZE, "packet too big: {n}");
+            packets += 1;
+            let packet = std::str::from_utf8(&buf[..n]).unwrap();
+            lines.extend(packet.lines().map(str::to_owned));
+        }
+        assert!(packets > 1);
+        lines
+    }
+
+    #[tokio::test]
+    async fn push_stats() {
+        let statsd = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let dir = TempDir::new().unwrap();
+        let mut opts = test_options(dir.path());
+        opts.statsd_address = statsd.local_addr().unwrap().to_string();
+        opts.statsd_interval = Duration::from_millis(500);
+        opts.statsd_mem_stats = false;
+        opts.statsd_udp_packet_size = PACKET_SIZE;
+        let server = TestNsqd::start_with(opts, |nsqd| {
+            nsqd.get_topic("stats").get_channel("ch");
+        })
+        .await;
+
+        let mut client = server.connect().await;
+        for _ in 0..2 {
+            client.command_with_body("PUB stats", b"hello").await;
+            assert_eq!(client.read_frame().await.unwrap().1, b"OK");
+        }
+
+        // 消费者收到两条消息，FIN其中一条
+        let mut consumer = server.connect().await;
+        consumer.command("SUB stats ch").await;
+        assert_eq!(consumer.read_frame().await.unwrap().1, b"OK");
+        consumer.command("RDY 1").await;
+        let msg = consumer.read_message().await;
+        consumer
+            .command(&format!("FIN {}", String::from_utf8_lossy(&msg.id)))
+            .await;
+        consumer.read_message().await;
+
+        // 前缀中的%s替换成broadcast地址和http端口
+        let prefix = format!(
+            "nsq.127_0_0_1_{}.",
+            server.nsqd.get_opts().broadcast_http_port
+        );
+        let client_prefix = format!(
+            "{prefix}topic.stats.channel.ch.client.{}.",
+            consumer.local_addr().to_string().replace(['.', ':'], "_")
+        );
+        let last = format!("{client_prefix}in_flight_count:1|g");
+
+        let lines = recv_push(&statsd, &last).await;
+        for expected in [
+            "topic.stats.message_count:2|c",
+            "topic.stats.message_bytes:10|c",
+            "topic.stats.depth:0|g",
+            "topic.stats.channel.ch.message_count:2|c",
+            "topic.stats.channel.ch.depth:0|g",
+            "topic.stats.channel.ch.in_flight_count:1|g",
+            "topic.stats.channel.ch.requeue_count:0|c",
+            "topic.stats.channel.ch.clients:1|g",
+        ] {
+            let expected = format!("{prefix}{expected}");
+            assert!(lines.contains(&expected), "missing {expected} in {lines:?}");
+        }
+        for expected in [
+            "message_count:2|c",
+            "finish_count:1|c",
+            "requeue_count:0|c",
+            "ready_count:1|g",
+        ] {
+            let expected = format!("{client_prefix}{expected}");
+            assert!(lines.contains(&expected), "missing {expected} in {lines:?}");
+        }
+
+        // 下一次推送的计数是和上一次的差值
+        let lines = recv_push(&statsd, &last).await;
+        for expected in ["message_count:0|c", "finish_count:0|c"] {
+            let expected = format!("{client_prefix}{expected}");
+            assert!(lines.contains(&expected), "missing {expected} in {lines:?}");
+        }
+        let expected = format!("{prefix}topic.stats.message_count:0|c");
+        assert!(lines.contains(&expected), "missing {expected} in {lines:?}");
+
+        server.stop().await;
+    }
+
+    #[test]
+    fn split_packets() {
+        let mut client = StatsdClient::new("nsq.".to_owned(), 20);
+        client.gauge("a", 1);
+        client.gauge("b", 2);
+        client.incr("counter", 3);
+        client.gauge("a_very_long_stat_name", 4);
+        client.packets.push(mem::take(&mut client.buf));
+        assert_eq!(
+            client.packets,
+            [
+                "nsq.a:1|g\nnsq.b:2|g\n",
+                "nsq.counter:3|c\n",
+                "nsq.a_very_long_stat_name:4|g\n",
+            ]
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{
+    io::{AsyncReadExt, BufReader},
+    net::TcpStream,
+    select,
+};
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, warn};
+
+use super::{
+    client_v2::{ClientConn, ClientV2, DEFAULT_BUF_SIZE},
+    nsqd::NSQD,
+    protocol_v2::{send_framed_response, FrameType, ProtocolV2},
+};
+use crate::{errors::ErrorCode, shutdown::Shutdown};
+
+pub(super) async fn serve(nsqd: Arc<NSQD>, mut shutdown: Shutdown) {
+    info!("TCP: listening on {}", nsqd.tcp_addr());
+
+    let tracker = TaskTracker::new();
+    loop {
+        select! {
+            res = nsqd.tcp_accept() => {
+                let (conn, addr) = match res {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("TCP: accept error - {e}");
+                        continue;
+                    }
+                };
+                tracker.spawn(handle(nsqd.clone(), conn, addr, shutdown.clone()));
+            },
+            _ = shutdown.recv() => {
+                info!("TCP: closing {}", nsqd.tcp_addr());
+                break;
+            }
+        }
+    }
+
+    // 等待所有连接处理完
+    tracker.close();
+    tracker.wait().await;
+}
+
+async fn handle(nsqd: Arc<NSQD>, mut conn: TcpStream, addr: SocketAddr, shutdown: Shutdown) {
+    info!("TCP: new client({addr})");
+
+    // 客户端连接后需要先发送4字节的magic，用于确定协议版本
+    let mut magic = [0u8; 4];
+    if let Err(e) = conn.read_exact(&mut magic).await {
+        error!("failed to read protocol version - {e}");
+        return;
+    }
+
+    match &magic {
+        b"  V2" => {
+            let conn = ClientConn::new(conn);
+            let reader = BufReader::with_capacity(DEFAULT_BUF_SIZE, conn.clone());
+            let client = Arc::new(ClientV2::new(
+                nsqd.next_client_id(),
+                conn,
+                addr,
+                nsqd.clone(),
+            ));
+
+            let protocol = ProtocolV2::new(nsqd);
+            if let Err(e) = protocol.io_loop(reader, client, shutdown).await {
+                error!("client({addr}) - {e}");
+            }
+        }
+        _ => {
+            error!(
+                "client({addr}) bad protocol magic '{}'",
+                String::from_utf8_lossy(&magic)
+            );
+            let _ = send_framed_response(
+                &mut conn,
+                FrameType::Error,
+                ErrorCode::BadProtocol.as_str().as_bytes(),
+            )
+            .await;
+        }
+    }
+
+    info!("TCP: client({addr}) closed");
+}
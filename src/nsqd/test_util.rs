@@ -0,0 +1,113 @@
+use std::{net::SocketAddr, path::Path, sync::Arc};
+
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::JoinHandle,
+};
+
+use super::{message::Message, nsqd::NSQD, options::Options, protocol_v2::FrameType};
+use crate::common::Result;
+
+// 监听随机端口，数据目录放在临时目录中
+pub(crate) fn test_options(data_path: &Path) -> Options {
+    let mut opts = Options::new();
+    opts.tcp_addr = "127.0.0.1:0".to_owned();
+    opts.http_addr = "127.0.0.1:0".to_owned();
+    opts.https_addr = String::new();
+    opts.broadcast_addr = "127.0.0.1".to_owned();
+    opts.data_path = data_path.to_owned();
+    opts
+}
+
+pub(crate) struct TestNsqd {
+    pub nsqd: Arc<NSQD>,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl TestNsqd {
+    pub async fn start(opts: Options) -> Self {
+        Self::start_with(opts, |_| {}).await
+    }
+
+    // init在启动之前执行，比如预先创建topic和channel
+    pub async fn start_with(opts: Options, init: impl FnOnce(&Arc<NSQD>)) -> Self {
+        let (nsqd, _) = NSQD::new(opts).await.unwrap();
+        let nsqd = Arc::new(nsqd);
+        init(&nsqd);
+        let handle = tokio::spawn(nsqd.clone().start());
+        Self { nsqd, handle }
+    }
+
+    // 使用新的临时目录启动
+    pub async fn start_default() -> (Self, TempDir) {
+        let dir = TempDir::new().unwrap();
+        (Self::start(test_options(dir.path())).await, dir)
+    }
+
+    pub async fn stop(self) {
+        self.nsqd.exit();
+        self.handle.await.unwrap().unwrap();
+    }
+
+    pub(super) async fn connect(&self) -> TestClient {
+        TestClient::connect(&self.nsqd).await
+    }
+}
+
+// 简单的V2协议客户端
+pub(super) struct TestClient {
+    conn: BufReader<TcpStream>,
+}
+
+impl TestClient {
+    pub async fn connect(nsqd: &NSQD) -> Self {
+        let mut conn = TcpStream::connect(nsqd.tcp_addr()).await.unwrap();
+        conn.write_all(b"  V2").await.unwrap();
+        Self {
+            conn: BufReader::new(conn),
+        }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.conn.get_ref().local_addr().unwrap()
+    }
+
+    pub async fn send(&mut self, data: &[u8]) {
+        self.conn.get_mut().write_all(data).await.unwrap();
+    }
+
+    // 读取原始数据，用于开启压缩之后的连接
+    pub async fn read(&mut self, buf: &mut [u8]) -> usize {
+        self.conn.read(buf).await.unwrap()
+    }
+
+    pub async fn command(&mut self, line: &str) {
+        self.send(format!("{line}\n").as_bytes()).await;
+    }
+
+    // 命令之后紧跟4字节长度和body
+    pub async fn command_with_body(&mut self, line: &str, body: &[u8]) {
+        let mut buf = format!("{line}\n").into_bytes();
+        buf.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        buf.extend_from_slice(body);
+        self.send(&buf).await;
+    }
+
+    // 返回帧类型和内容，连接关闭时返回None
+    pub async fn read_frame(&mut self) -> Option<(u32, Vec<u8>)> {
+        let size = self.conn.read_u32().await.ok()?;
+        let frame_type = self.conn.read_u32().await.ok()?;
+        let mut data = vec![0; size as usize - 4];
+        self.conn.read_exact(&mut data).await.ok()?;
+        Some((frame_type, data))
+    }
+
+    // 读取一条消息，帧类型必须是Message
+    pub async fn read_message(&mut self) -> Message {
+        let (frame_type, data) = self.read_frame().await.unwrap();
+        assert_eq!(frame_type, FrameType::Message as u32, "{data:?}");
+        Message::decode(&data).unwrap()
+    }
+}
@@ -1 +1,512 @@
-pub struct Topic {}
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use async_channel::{Receiver, Sender, TrySendError};
+use tokio::{select, sync::Notify, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use super::{
+    backend_queue::{BackEndQueue, DummyBackendQueue},
+    channel::Channel,
+    disk_queue::DiskQueue,
+    guid::GuidFactory,
+    message::{Message, MessageID},
+    nsqd::{NotifyType, NSQD},
+};
+use crate::{
+    common::{is_ephemeral, Result},
+    errors::NsqError,
+};
+
+pub(super) struct Topic {
+    name: String,
+    channel_map: RwLock<HashMap<String, Arc<Channel>>>,
+
+    // mem_queue_size为0时不使用内存队列，所有消息直接写入backend
+    mem_msg_tx: Option<Sender<Message>>,
+    mem_msg_rx: Receiver<Message>,
+    backend: Box<dyn BackEndQueue>,
+
+    message_count: AtomicU64,
+    message_bytes: AtomicU64,
+
+    paused: AtomicBool,
+    // channel列表或暂停状态发生变化时通知message pump
+    update_notify: Notify,
+
+    exit_token: CancellationToken,
+    pump_handle: Mutex<Option<JoinHandle<()>>>,
+
+    id_factory: GuidFactory,
+
+    nsqd: Arc<NSQD>,
+}
+
+impl Topic {
+    pub fn new(name: &str, nsqd: Arc<NSQD>) -> Arc<Self> {
+        let mem_queue_size = nsqd.get_opts().mem_queue_size as usize;
+        let (mem_msg_tx, mem_msg_rx) = async_channel::bounded(mem_queue_size.max(1));
+
+        let backend: Box<dyn BackEndQueue> = if is_ephemeral(name) {
+            Box::new(DummyBackendQueue::new())
+        } else {
+            Box::new(DiskQueue::new(name, nsqd.get_opts()))
+        };
+
+        let topic = Arc::new(Self {
+            name: name.to_owned(),
+            channel_map: RwLock::new(HashMap::new()),
+            mem_msg_tx: (mem_queue_size > 0).then_some(mem_msg_tx),
+            mem_msg_rx,
+            backend,
+            message_count: AtomicU64::new(0),
+            message_bytes: AtomicU64::new(0),
+            paused: AtomicBool::new(false),
+            update_notify: Notify::new(),
+            exit_token: CancellationToken::new(),
+            pump_handle: Mutex::new(None),
+            id_factory: GuidFactory::new(nsqd.get_opts().id),
+            nsqd,
+        });
+
+        info!("TOPIC({name}): created");
+        topic.nsqd.notify(NotifyType::Topic(topic.clone()));
+        topic
+    }
+
+    // 启动message pump，重复调用时忽略。加载元数据时需要先恢复channel再启动，避免消息丢失
+    pub fn start(self: &Arc<Self>) {
+        let mut handle = self.pump_handle.lock().unwrap();
+        if handle.is_none() && !self.exit_token.is_cancelled() {
+            *handle = Some(tokio::spawn(self.clone().message_pump()));
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // 获取channel，如果不存在则创建
+    pub fn get_channel(&self, name: &str) -> Arc<Channel> {
+        if let Some(channel) = self.channel_map.read().unwrap().get(name) {
+            return channel.clone();
+        }
+
+        let (channel, created) = {
+            let mut channel_map = self.channel_map.write().unwrap();
+            match channel_map.get(name) {
+                Some(channel) => (channel.clone(), false),
+                None => {
+                    let channel = Arc::new(Channel::new(&self.name, name, self.nsqd.clone()));
+                    channel_map.insert(name.to_owned(), channel.clone());
+                    (channel, true)
+                }
+            }
+        };
+
+        // 通知message pump更新channel列表
+        self.update_notify.notify_one();
+
+        if created {
+            self.nsqd.notify(NotifyType::Channel(channel.clone()));
+        }
+        channel
+    }
+
+    pub fn get_existing_channel(&self, name: &str) -> Option<Arc<Channel>> {
+        self.channel_map.read().unwrap().get(name).cloned()
+    }
+
+    // 从channel列表中移除channel，并删除其中所有的消息
+    pub async fn delete_existing_channel(&self, name: &str) -> Result<()> {
+        let channel = self
+            .channel_map
+            .write()
+            .unwrap()
+            .remove(name)
+            .ok_or(NsqError::ChannelNotFound)?;
+
+        info!("TOPIC({}): deleting channel {}", self.name, channel.name());
+
+        // 删除失败时channel也已经不可用了，不影响从列表中移除
+        if let Err(e) = channel.delete().await {
+            error!(
+                "TOPIC({}): failed to delete channel({}) - {e}",
+                self.name, name
+            );
+        }
+
+        self.update_notify.notify_one();
+        self.nsqd.notify(NotifyType::Channel(channel));
+        Ok(())
+    }
+
+    // 生成失败时说明时钟回拨或者序列号用完，等待1毫秒后重试。不能阻塞tokio的worker线程
+    pub async fn generate_id(&self) -> MessageID {
+        let mut i = 0u64;
+        loop {
+            match self.id_factory.new_guid() {
+                Ok(guid) => return guid.hex(),
+                Err(e) => {
+                    if i % 10000 == 0 {
+                        error!("TOPIC({}): failed to create guid - {e}", self.name);
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            i += 1;
+        }
+    }
+
+    pub async fn put_message(&self, msg: Message) -> Result<()> {
+        if self.exit_token.is_cancelled() {
+            return Err(NsqError::Exiting);
+        }
+
+        let body_len = msg.body.len() as u64;
+        self.put(msg).await?;
+
+        self.message_count.fetch_add(1, Ordering::SeqCst);
+        self.message_bytes.fetch_add(body_len, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub async fn put_messages(&self, msgs: Vec<Message>) -> Result<()> {
+        if self.exit_token.is_cancelled() {
+            return Err(NsqError::Exiting);
+        }
+
+        let mut count = 0;
+        let mut bytes = 0;
+        let mut result = Ok(());
+        for msg in msgs {
+            let body_len = msg.body.len() as u64;
+            if let Err(e) = self.put(msg).await {
+                result = Err(e);
+                break;
+            }
+            count += 1;
+            bytes += body_len;
+        }
+
+        self.message_count.fetch_add(count, Ordering::SeqCst);
+        self.message_bytes.fetch_add(bytes, Ordering::SeqCst);
+        result
+    }
+
+    // 优先写入内存队列，内存队列满了之后写入backend
+    async fn put(&self, msg: Message) -> Result<()> {
+        let msg = match &self.mem_msg_tx {
+            Some(tx) => match tx.try_send(msg) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(msg) | TrySendError::Closed(msg)) => msg,
+            },
+            None => msg,
+        };
+
+        msg.write_to_backend(&*self.backend).await.map_err(|e| {
+            error!(
+                "TOPIC({}): failed to write message to backend - {e}",
+                self.name
+            );
+            e
+        })
+    }
+
+    pub fn depth(&self) -> i64 {
+        self.mem_msg_rx.len() as i64 + self.backend.depth()
+    }
+
+    pub fn backend_depth(&self) -> i64 {
+        self.backend.depth()
+    }
+
+    pub fn message_count(&self) -> u64 {
+        self.message_count.load(Ordering::SeqCst)
+    }
+
+    pub fn message_bytes(&self) -> u64 {
+        self.message_bytes.load(Ordering::SeqCst)
+    }
+
+    pub fn pause(&self) {
+        self.do_pause(true);
+    }
+
+    pub fn unpause(&self) {
+        self.do_pause(false);
+    }
+
+    fn do_pause(&self, pause: bool) {
+        self.paused.store(pause, Ordering::SeqCst);
+        self.update_notify.notify_one();
+        self.nsqd.notify_persist();
+    }
+
+    pub fn is_exiting(&self) -> bool {
+        self.exit_token.is_cancelled()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    // 丢弃topic中还没有复制给channel的消息
+    pub async fn empty(&self) -> Result<()> {
+        while self.mem_msg_rx.try_recv().is_ok() {}
+        self.backend.empty().await
+    }
+
+    // 停止message pump，将内存中的消息写入backend
+    pub async fn close(&self) -> Result<()> {
+        self.exit(false).await
+    }
+
+    // 停止message pump，删除所有channel和消息
+    pub async fn delete(&self) -> Result<()> {
+        self.exit(true).await
+    }
+
+    async fn exit(&self, deleted: bool) -> Result<()> {
+        if self.exit_token.is_cancelled() {
+            return Err(NsqError::Exiting);
+        }
+
+        if deleted {
+            info!("TOPIC({}): deleting", self.name);
+        } else {
+            info!("TOPIC({}): closing", self.name);
+        }
+        self.exit_token.cancel();
+
+        let handle = self.pump_handle.lock().unwrap().take();
+        if let Some(handle) = handle {
+            let _ = handle.await;
+        }
+
+        if deleted {
+            let channels: Vec<_> = self.channel_map.write().unwrap().drain().collect();
+            for (name, channel) in channels {
+                if let Err(e) = channel.delete().await {
+                    error!(
+                        "TOPIC({}): failed to delete channel({name}) - {e}",
+                        self.name
+                    );
+                }
+                self.nsqd.notify(NotifyType::Channel(channel));
+            }
+
+            self.empty().await?;
+            return self.backend.delete().await;
+        }
+
+        for channel in self.channels() {
+            if let Err(e) = channel.close().await {
+                error!(
+                    "TOPIC({}): failed to close channel({}) - {e}",
+                    self.name,
+                    channel.name()
+                );
+            }
+        }
+
+        self.flush().await;
+        self.backend.close().await
+    }
+
+    async fn flush(&self) {
+        while let Ok(msg) = self.mem_msg_rx.try_recv() {
+            if let Err(e) = msg.write_to_backend(&*self.backend).await {
+                error!(
+                    "TOPIC({}): failed to write message to backend - {e}",
+                    self.name
+                );
+            }
+        }
+    }
+
+    pub fn channels(&self) -> Vec<Arc<Channel>> {
+        self.channel_map.read().unwrap().values().cloned().collect()
+    }
+
+    // 从内存队列或backend中读取消息，复制给每个channel
+    async fn message_pump(self: Arc<Self>) {
+        let backend_chan = self.backend.read_chan();
+        let mut chans = self.channels();
+
+        loop {
+            // 没有channel或者暂停时，消息留在topic中
+            let active = !chans.is_empty() && !self.is_paused();
+
+            let msg = select! {
+                Ok(msg) = self.mem_msg_rx.recv(), if active => msg,
+                Ok(buf) = backend_chan.recv(), if active => {
+                    match Message::decode(&buf) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            error!("TOPIC({}): failed to decode message - {e}", self.name);
+                            continue;
+                        }
+                    }
+                }
+                _ = self.update_notify.notified() => {
+                    chans = self.channels();
+                    continue;
+                }
+                _ = self.exit_token.cancelled() => break,
+            };
+
+            // 第一个channel之后的channel都使用消息的副本
+            let last = chans.len() - 1;
+            let mut msg = Some(msg);
+            for (i, channel) in chans.iter().enumerate() {
+                let chan_msg = if i == last {
+                    msg.take().unwrap()
+                } else {
+                    msg.as_ref().unwrap().clone()
+                };
+
+                let result = match chan_msg.deferred {
+                    Some(deferred) => channel.put_message_deferred(chan_msg, deferred),
+                    None => channel.put_message(chan_msg).await,
+                };
+                if let Err(e) = result {
+                    error!(
+                        "TOPIC({}): failed to put msg to channel({}) - {e}",
+                        self.name,
+                        channel.name()
+                    );
+                }
+            }
+        }
+
+        info!("TOPIC({}): closing ... messagePump", self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::nsqd::test_util::{test_options, TestNsqd};
+
+    // 等待条件成立，message pump在后台异步复制消息
+    async fn wait_for(cond: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !cond() {
+            assert!(start.elapsed() < Duration::from_secs(3), "timed out");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn put(topic: &Topic, body: &[u8]) -> MessageID {
+        let id = topic.generate_id().await;
+        topic
+            .put_message(Message::new(id, body.to_vec()))
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn fan_out() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let topic = server.nsqd.get_topic("t");
+        let channels = [topic.get_channel("a"), topic.get_channel("b")];
+
+        let mut ids = Vec::new();
+        for body in [b"1", b"2", b"3"] {
+            ids.push(put(&topic, body).await);
+        }
+
+        // 每个channel都收到所有消息的副本
+        wait_for(|| channels.iter().all(|c| c.depth() == 3)).await;
+        assert_eq!(topic.depth(), 0);
+        assert_eq!(topic.message_count(), 3);
+        assert_eq!(topic.message_bytes(), 3);
+        for channel in &channels {
+            assert_eq!(channel.message_count(), 3);
+            for (id, body) in ids.iter().zip([b"1", b"2", b"3"]) {
+                let msg = channel.memory_msg_chan().recv().await.unwrap();
+                assert_eq!(&msg.id, id);
+                assert_eq!(msg.body, body);
+            }
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn backend_overflow() {
+        let dir = TempDir::new().unwrap();
+        let mut opts = test_options(dir.path());
+        opts.mem_queue_size = 2;
+        let server = TestNsqd::start(opts).await;
+        let topic = server.nsqd.get_topic("t");
+
+        // 没有channel时消息留在topic中，超过mem_queue_size的部分写入backend
+        for i in 0..5u8 {
+            put(&topic, &[i]).await;
+        }
+        assert_eq!(topic.depth(), 5);
+        assert_eq!(topic.backend_depth(), 3);
+
+        // 临时topic没有backend，超出的消息直接丢弃
+        let ephemeral = server.nsqd.get_topic("t#ephemeral");
+        for i in 0..5u8 {
+            put(&ephemeral, &[i]).await;
+        }
+        assert_eq!(ephemeral.depth(), 2);
+        assert_eq!(ephemeral.backend_depth(), 0);
+
+        // channel同样在内存队列满了之后写入backend
+        let channel = topic.get_channel("c");
+        wait_for(|| channel.depth() == 5).await;
+        assert_eq!(topic.depth(), 0);
+        assert_eq!(channel.backend_depth(), 3);
+
+        let mut bodies = Vec::new();
+        for _ in 0..2 {
+            bodies.push(channel.memory_msg_chan().recv().await.unwrap().body);
+        }
+        for _ in 0..3 {
+            let buf = channel.backend_msg_chan().recv().await.unwrap();
+            bodies.push(Message::decode(&buf).unwrap().body);
+        }
+        bodies.sort();
+        assert_eq!(bodies, [[0], [1], [2], [3], [4]]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn pause() {
+        let (server, _dir) = TestNsqd::start_default().await;
+        let topic = server.nsqd.get_topic("t");
+        let channel = topic.get_channel("c");
+
+        topic.pause();
+        assert!(topic.is_paused());
+        put(&topic, b"1").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(topic.depth(), 1);
+        assert_eq!(channel.depth(), 0);
+
+        topic.unpause();
+        assert!(!topic.is_paused());
+        wait_for(|| channel.depth() == 1).await;
+        assert_eq!(topic.depth(), 0);
+
+        server.stop().await;
+    }
+}
@@ -0,0 +1,22 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use super::registration_db::PeerInfo;
+
+// nsqd到nsqlookupd的连接，IDENTIFY之后才能注册topic/channel
+pub(super) struct ClientV1 {
+    addr: SocketAddr,
+    pub peer_info: Option<Arc<PeerInfo>>,
+}
+
+impl ClientV1 {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            peer_info: None,
+        }
+    }
+
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+}
@@ -0,0 +1,511 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::select;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+use super::{
+    nsqlookupd::NSQLookupd,
+    registration_db::{
+        filter_by_active, keys, sub_keys, PeerInfo, Registration, CATEGORY_CHANNEL,
+        CATEGORY_CLIENT, CATEGORY_TOPIC,
+    },
+};
+use crate::{
+    http_api::{
+        get_topic_channel_args, get_topic_name_from_query, serve_conn, HttpError, HttpResult,
+    },
+    shutdown::Shutdown,
+};
+
+pub(super) async fn serve(nsqlookupd: Arc<NSQLookupd>, mut shutdown: Shutdown) {
+    let addr = nsqlookupd.http_addr();
+    info!("HTTP: listening on {addr}");
+
+    let router = router(nsqlookupd.clone());
+    let tracker = TaskTracker::new();
+    loop {
+        let res = select! {
+            res = nsqlookupd.http_accept() => res,
+            _ = shutdown.recv() => {
+                info!("HTTP: closing {addr}");
+                break;
+            }
+        };
+        let (conn, _) = match res {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("HTTP: accept error - {e}");
+                continue;
+            }
+        };
+
+        tracker.spawn(serve_conn(conn, router.clone(), shutdown.clone()));
+    }
+
+    tracker.close();
+    tracker.wait().await;
+}
+
+fn router(nsqlookupd: Arc<NSQLookupd>) -> Router {
+    Router::new()
+        .route("/ping", get(ping))
+        .route("/info", get(info))
+        .route("/lookup", get(do_lookup))
+        .route("/topics", get(do_topics))
+        .route("/channels", get(do_channels))
+        .route("/nodes", get(do_nodes))
+        .route("/topic/create", post(do_create_topic))
+        .route("/topic/delete", post(do_delete_topic))
+        .route("/topic/tombstone", post(do_tombstone_topic_producer))
+        .route("/channel/create", post(do_create_channel))
+        .route("/channel/delete", post(do_delete_channel))
+        .fallback(|| async { HttpError::new(StatusCode::NOT_FOUND, "NOT_FOUND") })
+        .with_state(nsqlookupd)
+}
+
+async fn ping() -> &'static str {
+    "OK"
+}
+
+async fn info() -> Json<Value> {
+    Json(json!({ "version": env!("CARGO_PKG_VERSION") }))
+}
+
+// 查询类的接口只要求带上topic参数，不校验topic名称
+fn get_topic_arg(params: &HashMap<String, String>) -> HttpResult<&str> {
+    params
+        .get("topic")
+        .map(|t| t.as_str())
+        .ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "MISSING_ARG_TOPIC"))
+}
+
+async fn do_topics(State(nsqlookupd): State<Arc<NSQLookupd>>) -> Json<Value> {
+    let topics = keys(&nsqlookupd.db().find_registrations(CATEGORY_TOPIC, "*", ""));
+    Json(json!({ "topics": topics }))
+}
+
+async fn do_channels(
+    State(nsqlookupd): State<Arc<NSQLookupd>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<Json<Value>> {
+    let topic_name = get_topic_arg(&params)?;
+    let channels = sub_keys(
+        &nsqlookupd
+            .db()
+            .find_registrations(CATEGORY_CHANNEL, topic_name, "*"),
+    );
+    Ok(Json(json!({ "channels": channels })))
+}
+
+#[derive(Serialize)]
+struct LookupResponse {
+    channels: Vec<String>,
+    producers: Vec<Arc<PeerInfo>>,
+}
+
+async fn do_lookup(
+    State(nsqlookupd): State<Arc<NSQLookupd>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<Json<LookupResponse>> {
+    let topic_name = get_topic_arg(&params)?;
+    let db = nsqlookupd.db();
+
+    if db
+        .find_registrations(CATEGORY_TOPIC, topic_name, "")
+        .is_empty()
+    {
+        return Err(HttpError::new(StatusCode::NOT_FOUND, "TOPIC_NOT_FOUND"));
+    }
+
+    let opts = nsqlookupd.get_opts();
+    let channels = sub_keys(&db.find_registrations(CATEGORY_CHANNEL, topic_name, "*"));
+    let producers = filter_by_active(
+        db.find_producers(CATEGORY_TOPIC, topic_name, ""),
+        opts.inactive_producer_timeout,
+        opts.tombstone_lifetime,
+    );
+
+    Ok(Json(LookupResponse {
+        channels,
+        producers: producers.into_iter().map(|p| p.peer_info).collect(),
+    }))
+}
+
+async fn do_create_topic(
+    State(nsqlookupd): State<Arc<NSQLookupd>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let topic_name = get_topic_name_from_query(&params)?;
+
+    info!("DB: adding topic({topic_name})");
+    nsqlookupd
+        .db()
+        .add_registration(Registration::new(CATEGORY_TOPIC, topic_name, ""));
+    Ok(())
+}
+
+async fn do_delete_topic(
+    State(nsqlookupd): State<Arc<NSQLookupd>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let topic_name = get_topic_arg(&params)?;
+    let db = nsqlookupd.db();
+
+    for r in db.find_registrations(CATEGORY_CHANNEL, topic_name, "*") {
+        info!(
+            "DB: removing channel({}) from topic({topic_name})",
+            r.sub_key
+        );
+        db.remove_registration(&r);
+    }
+
+    for r in db.find_registrations(CATEGORY_TOPIC, topic_name, "") {
+        info!("DB: removing topic({topic_name})");
+        db.remove_registration(&r);
+    }
+
+    Ok(())
+}
+
+// node的格式为 broadcast_address:http_port
+async fn do_tombstone_topic_producer(
+    State(nsqlookupd): State<Arc<NSQLookupd>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let topic_name = get_topic_arg(&params)?;
+    let node = params
+        .get("node")
+        .ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "MISSING_ARG_NODE"))?;
+
+    info!("DB: setting tombstone for producer@{node} of topic({topic_name})");
+    let db = nsqlookupd.db();
+    let key = Registration::new(CATEGORY_TOPIC, topic_name, "");
+    for p in db.find_producers(CATEGORY_TOPIC, topic_name, "") {
+        let this_node = format!(
+            "{}:{}",
+            p.peer_info.broadcast_address, p.peer_info.http_port
+        );
+        if &this_node == node {
+            db.tombstone_producer(&key, &p.peer_info.id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn do_create_channel(
+    State(nsqlookupd): State<Arc<NSQLookupd>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let (topic_name, channel_name) = get_topic_channel_args(&params)?;
+    let db = nsqlookupd.db();
+
+    info!("DB: adding channel({channel_name}) in topic({topic_name})");
+    db.add_registration(Registration::new(
+        CATEGORY_CHANNEL,
+        topic_name,
+        channel_name,
+    ));
+
+    info!("DB: adding topic({topic_name})");
+    db.add_registration(Registration::new(CATEGORY_TOPIC, topic_name, ""));
+    Ok(())
+}
+
+async fn do_delete_channel(
+    State(nsqlookupd): State<Arc<NSQLookupd>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> HttpResult<()> {
+    let (topic_name, channel_name) = get_topic_channel_args(&params)?;
+    let db = nsqlookupd.db();
+
+    let registrations = db.find_registrations(CATEGORY_CHANNEL, topic_name, channel_name);
+    if registrations.is_empty() {
+        return Err(HttpError::new(StatusCode::NOT_FOUND, "CHANNEL_NOT_FOUND"));
+    }
+
+    info!("DB: removing channel({channel_name}) from topic({topic_name})");
+    for r in registrations {
+        db.remove_registration(&r);
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Node {
+    #[serde(flatten)]
+    peer_info: Arc<PeerInfo>,
+    // 和topics一一对应，表示这个节点上的topic是否被tombstone
+    tombstones: Vec<bool>,
+    topics: Vec<String>,
+}
+
+async fn do_nodes(State(nsqlookupd): State<Arc<NSQLookupd>>) -> Json<Value> {
+    let opts = nsqlookupd.get_opts();
+    let db = nsqlookupd.db();
+
+    // 不过滤被tombstone的节点
+    let producers = filter_by_active(
+        db.find_producers(CATEGORY_CLIENT, "", ""),
+        opts.inactive_producer_timeout,
+        Duration::ZERO,
+    );
+
+    let nodes: Vec<_> = producers
+        .into_iter()
+        .map(|p| {
+            let mut topics = keys(
+                &db.lookup_registrations(&p.peer_info.id)
+                    .into_iter()
+                    .filter(|r| r.is_match(CATEGORY_TOPIC, "*", ""))
+                    .collect::<Vec<_>>(),
+            );
+            topics.sort();
+
+            // 每个topic下找到这个节点对应的producer，获取tombstone状态
+            let tombstones = topics
+                .iter()
+                .map(|t| {
+                    db.find_producers(CATEGORY_TOPIC, t, "")
+                        .iter()
+                        .find(|tp| Arc::ptr_eq(&tp.peer_info, &p.peer_info))
+                        .is_some_and(|tp| tp.is_tombstoned(opts.tombstone_lifetime))
+                })
+                .collect();
+
+            Node {
+                peer_info: p.peer_info,
+                tombstones,
+                topics,
+            }
+        })
+        .collect();
+
+    Json(json!({ "producers": nodes }))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        task::JoinHandle,
+    };
+
+    use super::*;
+    use crate::{common::Result, nsqlookupd::options::Options};
+
+    struct TestLookupd {
+        nsqlookupd: Arc<NSQLookupd>,
+        handle: JoinHandle<Result<()>>,
+    }
+
+    impl TestLookupd {
+        async fn start(tombstone_lifetime: Duration) -> Self {
+            let mut opts = Options::new();
+            opts.tcp_addr = "127.0.0.1:0".to_owned();
+            opts.http_addr = "127.0.0.1:0".to_owned();
+            opts.tombstone_lifetime = tombstone_lifetime;
+            let (nsqlookupd, _) = NSQLookupd::new(opts).await.unwrap();
+            let nsqlookupd = Arc::new(nsqlookupd);
+            let handle = tokio::spawn(nsqlookupd.clone().start());
+            Self { nsqlookupd, handle }
+        }
+
+        async fn stop(self) {
+            self.nsqlookupd.exit();
+            self.handle.await.unwrap().unwrap();
+        }
+
+        async fn get(&self, path: &str) -> (StatusCode, Value) {
+            let url = format!("http://{}{path}", self.nsqlookupd.http_addr());
+            let resp = reqwest::get(url).await.unwrap();
+            (resp.status(), resp.json().await.unwrap())
+        }
+
+        async fn get_ok(&self, path: &str) -> Value {
+            let (status, body) = self.get(path).await;
+            assert_eq!(status, StatusCode::OK, "{path}: {body}");
+            body
+        }
+
+        async fn post(&self, path: &str) -> (StatusCode, String) {
+            let url = format!("http://{}{path}", self.nsqlookupd.http_addr());
+            let resp = reqwest::Client::new().post(url).send().await.unwrap();
+            (resp.status(), resp.text().await.unwrap())
+        }
+
+        // 模拟nsqd通过TCP注册，连接断开之后注册信息会被删除
+        async fn register(&self, http_port: u16, registrations: &[&str]) -> TcpStream {
+            let mut conn = TcpStream::connect(self.nsqlookupd.tcp_addr())
+                .await
+                .unwrap();
+            let body = json!({
+                "broadcast_address": "127.0.0.1",
+                "tcp_port": http_port - 1,
+                "http_port": http_port,
+                "version": "1.0.0",
+            })
+            .to_string();
+            let mut buf = b"  V1IDENTIFY\n".to_vec();
+            buf.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            buf.extend_from_slice(body.as_bytes());
+            for r in registrations {
+                buf.extend_from_slice(format!("REGISTER {r}\n").as_bytes());
+            }
+            conn.write_all(&buf).await.unwrap();
+            for _ in 0..registrations.len() + 1 {
+                let size = conn.read_u32().await.unwrap();
+                let mut data = vec![0; size as usize];
+                conn.read_exact(&mut data).await.unwrap();
+            }
+            conn
+        }
+    }
+
+    fn message(body: &str) -> String {
+        let v: Value = serde_json::from_str(body).unwrap();
+        v["message"].as_str().unwrap().to_owned()
+    }
+
+    fn http_ports(producers: &Value) -> Vec<u64> {
+        let mut ports: Vec<_> = producers
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["http_port"].as_u64().unwrap())
+            .collect();
+        ports.sort();
+        ports
+    }
+
+    #[tokio::test]
+    async fn lookup_and_registrations() {
+        let server = TestLookupd::start(Duration::from_secs(45)).await;
+
+        let (status, body) = server.get("/lookup").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["message"], "MISSING_ARG_TOPIC");
+        let (status, body) = server.get("/lookup?topic=t").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["message"], "TOPIC_NOT_FOUND");
+
+        // 通过HTTP创建的topic和channel没有producer
+        assert_eq!(server.post("/topic/create?topic=t").await.0, StatusCode::OK);
+        let (status, body) = server.post("/topic/create?topic=a!").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "INVALID_TOPIC");
+        let (status, _) = server.post("/channel/create?topic=t2&channel=c").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let lookup = server.get_ok("/lookup?topic=t").await;
+        assert_eq!(lookup, json!({"channels": [], "producers": []}));
+        let mut topics = server.get_ok("/topics").await;
+        topics["topics"]
+            .as_array_mut()
+            .unwrap()
+            .sort_by_key(|t| t.to_string());
+        assert_eq!(topics, json!({"topics": ["t", "t2"]}));
+        let channels = server.get_ok("/channels?topic=t2").await;
+        assert_eq!(channels, json!({"channels": ["c"]}));
+
+        let _conn = server.register(4151, &["t", "t c"]).await;
+        let lookup = server.get_ok("/lookup?topic=t").await;
+        assert_eq!(lookup["channels"], json!(["c"]));
+        let producer = &lookup["producers"][0];
+        assert_eq!(producer["broadcast_address"], "127.0.0.1");
+        assert_eq!(producer["tcp_port"], 4150);
+        assert_eq!(producer["http_port"], 4151);
+        assert_eq!(producer["version"], "1.0.0");
+
+        let nodes = server.get_ok("/nodes").await;
+        assert_eq!(http_ports(&nodes["producers"]), [4151]);
+        assert_eq!(nodes["producers"][0]["topics"], json!(["t"]));
+        assert_eq!(nodes["producers"][0]["tombstones"], json!([false]));
+
+        assert_eq!(
+            server.post("/channel/delete?topic=t2&channel=c").await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            server.get_ok("/channels?topic=t2").await,
+            json!({"channels": []})
+        );
+        let (status, body) = server.post("/channel/delete?topic=t2&channel=c").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(message(&body), "CHANNEL_NOT_FOUND");
+
+        // 删除topic时同时删除channel
+        assert_eq!(server.post("/topic/delete?topic=t").await.0, StatusCode::OK);
+        assert_eq!(server.get("/lookup?topic=t").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(
+            server.get_ok("/channels?topic=t").await,
+            json!({"channels": []})
+        );
+        assert_eq!(server.get_ok("/topics").await, json!({"topics": ["t2"]}));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn tombstone() {
+        let lifetime = Duration::from_millis(500);
+        let server = TestLookupd::start(lifetime).await;
+        let _conn1 = server.register(4151, &["t", "u"]).await;
+        let _conn2 = server.register(5151, &["t"]).await;
+        assert_eq!(
+            http_ports(&server.get_ok("/lookup?topic=t").await["producers"]),
+            [4151, 5151]
+        );
+
+        let (status, body) = server.post("/topic/tombstone?topic=t").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(message(&body), "MISSING_ARG_NODE");
+        let (status, _) = server
+            .post("/topic/tombstone?topic=t&node=127.0.0.1:4151")
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // 只影响这个topic，节点仍然出现在/nodes中
+        let lookup = server.get_ok("/lookup?topic=t").await;
+        assert_eq!(http_ports(&lookup["producers"]), [5151]);
+        let lookup = server.get_ok("/lookup?topic=u").await;
+        assert_eq!(http_ports(&lookup["producers"]), [4151]);
+
+        let tombstones = |nodes: &Value, port: u64| {
+            nodes["producers"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|p| p["http_port"] == port)
+                .map(|p| (p["topics"].clone(), p["tombstones"].clone()))
+                .unwrap()
+        };
+        let nodes = server.get_ok("/nodes").await;
+        assert_eq!(http_ports(&nodes["producers"]), [4151, 5151]);
+        assert_eq!(
+            tombstones(&nodes, 4151),
+            (json!(["t", "u"]), json!([true, false]))
+        );
+        assert_eq!(tombstones(&nodes, 5151), (json!(["t"]), json!([false])));
+
+        // tombstone_lifetime之后重新出现在/lookup中
+        tokio::time::sleep(lifetime).await;
+        let lookup = server.get_ok("/lookup?topic=t").await;
+        assert_eq!(http_ports(&lookup["producers"]), [4151, 5151]);
+        let nodes = server.get_ok("/nodes").await;
+        assert_eq!(tombstones(&nodes, 4151).1, json!([false, false]));
+
+        server.stop().await;
+    }
+}
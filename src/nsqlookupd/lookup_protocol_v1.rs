@@ -0,0 +1,460 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    select,
+};
+use tracing::{debug, error, info, warn};
+
+use super::{
+    client_v1::ClientV1,
+    nsqlookupd::NSQLookupd,
+    registration_db::{
+        PeerInfo, Producer, Registration, CATEGORY_CHANNEL, CATEGORY_CLIENT, CATEGORY_TOPIC,
+    },
+};
+use crate::{
+    common::{is_ephemeral, is_valid_channel_name, is_valid_topic_name, Result},
+    errors::{ErrorCode, NsqError},
+    shutdown::Shutdown,
+};
+
+const OK_BYTES: &[u8] = b"OK";
+// 一行命令和IDENTIFY的body都很小，限制长度避免恶意的客户端耗尽内存
+const MAX_LINE_LENGTH: usize = 4096;
+const MAX_BODY_SIZE: u32 = 64 * 1024;
+
+#[derive(Serialize)]
+struct IdentifyResponse<'a> {
+    tcp_port: u16,
+    http_port: u16,
+    version: &'static str,
+    broadcast_address: &'a str,
+    hostname: String,
+}
+
+pub(super) struct LookupProtocolV1 {
+    nsqlookupd: Arc<NSQLookupd>,
+}
+
+impl LookupProtocolV1 {
+    pub fn new(nsqlookupd: Arc<NSQLookupd>) -> Self {
+        Self { nsqlookupd }
+    }
+
+    pub async fn io_loop<R, W>(
+        &self,
+        mut reader: BufReader<R>,
+        mut writer: W,
+        addr: SocketAddr,
+        mut shutdown: Shutdown,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut client = ClientV1::new(addr);
+
+        let mut line = String::new();
+        let result = loop {
+            line.clear();
+            let mut limited = (&mut reader).take(MAX_LINE_LENGTH as u64);
+            let n = select! {
+                res = limited.read_line(&mut line) => res,
+                _ = shutdown.recv() => break Ok(()),
+            };
+            match n {
+                Ok(0) => break Ok(()),
+                Ok(n) if n == MAX_LINE_LENGTH && !line.ends_with('\n') => {
+                    let e = NsqError::FatalClientErr(
+                        ErrorCode::Invalid,
+                        format!("command too long, exceeds {MAX_LINE_LENGTH} bytes"),
+                    );
+                    let _ = send_response(&mut writer, &e.frame_body()).await;
+                    break Err(e);
+                }
+                Ok(_) => {}
+                Err(e) => break Err(e.into()),
+            }
+
+            let params: Vec<&str> = line.trim().split(' ').collect();
+            debug!("PROTOCOL(V1): [{}] {params:?}", client.addr());
+
+            match self.exec(&mut client, &mut reader, &params).await {
+                Ok(resp) => {
+                    if let Err(e) = send_response(&mut writer, &resp).await {
+                        break Err(e);
+                    }
+                }
+                Err(e) => {
+                    error!("[{}] - {e}", client.addr());
+                    if let Err(e) = send_response(&mut writer, &e.frame_body()).await {
+                        break Err(e);
+                    }
+                    if e.is_fatal() {
+                        break Err(e);
+                    }
+                }
+            }
+        };
+
+        info!("PROTOCOL(V1): [{}] exiting ioloop", client.addr());
+
+        // 连接断开之后删除这个nsqd的所有注册信息
+        if let Some(peer_info) = &client.peer_info {
+            let db = self.nsqlookupd.db();
+            for r in db.lookup_registrations(&peer_info.id) {
+                if db.remove_producer(&r, &peer_info.id).0 {
+                    info!(
+                        "DB: client({}) UNREGISTER category:{} key:{} subkey:{}",
+                        client.addr(),
+                        r.category,
+                        r.key,
+                        r.sub_key
+                    );
+                }
+            }
+        }
+
+        result
+    }
+
+    async fn exec<R>(
+        &self,
+        client: &mut ClientV1,
+        reader: &mut BufReader<R>,
+        params: &[&str],
+    ) -> Result<Vec<u8>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        match params[0] {
+            "PING" => Ok(self.ping(client)),
+            "IDENTIFY" => self.identify(client, reader).await,
+            "REGISTER" => self.register(client, &params[1..]),
+            "UNREGISTER" => self.unregister(client, &params[1..]),
+            _ => Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                format!("invalid command {}", params[0]),
+            )),
+        }
+    }
+
+    fn ping(&self, client: &ClientV1) -> Vec<u8> {
+        // IDENTIFY之前也可能收到PING
+        if let Some(peer_info) = &client.peer_info {
+            debug!("CLIENT({}): pinged", peer_info.id);
+            peer_info.update();
+        }
+        OK_BYTES.to_vec()
+    }
+
+    async fn identify<R>(&self, client: &mut ClientV1, reader: &mut BufReader<R>) -> Result<Vec<u8>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if client.peer_info.is_some() {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::Invalid,
+                "cannot IDENTIFY again".to_owned(),
+            ));
+        }
+
+        let body_len = reader.read_u32().await.map_err(|_| {
+            NsqError::FatalClientErr(
+                ErrorCode::BadBody,
+                "IDENTIFY failed to read body size".to_owned(),
+            )
+        })?;
+        if body_len > MAX_BODY_SIZE {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadBody,
+                format!("IDENTIFY body too big {body_len} > {MAX_BODY_SIZE}"),
+            ));
+        }
+        let mut body = vec![0; body_len as usize];
+        reader.read_exact(&mut body).await.map_err(|_| {
+            NsqError::FatalClientErr(
+                ErrorCode::BadBody,
+                "IDENTIFY failed to read body".to_owned(),
+            )
+        })?;
+
+        let mut peer_info: PeerInfo = serde_json::from_slice(&body).map_err(|_| {
+            NsqError::FatalClientErr(
+                ErrorCode::BadBody,
+                "IDENTIFY failed to decode JSON body".to_owned(),
+            )
+        })?;
+        peer_info.id = client.addr();
+        peer_info.remote_address = client.addr();
+
+        if peer_info.broadcast_address.is_empty()
+            || peer_info.tcp_port == 0
+            || peer_info.http_port == 0
+            || peer_info.version.is_empty()
+        {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadBody,
+                "IDENTIFY missing fields".to_owned(),
+            ));
+        }
+
+        peer_info.update();
+        info!(
+            "CLIENT({}): IDENTIFY Address:{} TCP:{} HTTP:{} Version:{}",
+            client.addr(),
+            peer_info.broadcast_address,
+            peer_info.tcp_port,
+            peer_info.http_port,
+            peer_info.version
+        );
+
+        let peer_info = Arc::new(peer_info);
+        client.peer_info = Some(peer_info.clone());
+        let key = Registration::new(CATEGORY_CLIENT, "", "");
+        if self
+            .nsqlookupd
+            .db()
+            .add_producer(key, Producer::new(peer_info))
+        {
+            info!(
+                "DB: client({}) REGISTER category:{CATEGORY_CLIENT} key: subkey:",
+                client.addr()
+            );
+        }
+
+        let resp = serde_json::to_vec(&IdentifyResponse {
+            tcp_port: self.nsqlookupd.tcp_addr().port(),
+            http_port: self.nsqlookupd.http_addr().port(),
+            version: env!("CARGO_PKG_VERSION"),
+            broadcast_address: &self.nsqlookupd.get_opts().broadcast_addr,
+            hostname: hostname::get()
+                .map(|h| h.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        });
+        match resp {
+            Ok(resp) => Ok(resp),
+            Err(e) => {
+                error!("marshaling {e}");
+                Ok(OK_BYTES.to_vec())
+            }
+        }
+    }
+
+    fn register(&self, client: &ClientV1, params: &[&str]) -> Result<Vec<u8>> {
+        let peer_info = identified_peer(client)?;
+        let (topic, channel) = get_topic_chan("REGISTER", params)?;
+        let db = self.nsqlookupd.db();
+
+        if let Some(channel) = channel {
+            let key = Registration::new(CATEGORY_CHANNEL, topic, channel);
+            if db.add_producer(key, Producer::new(peer_info.clone())) {
+                info!(
+                    "DB: client({}) REGISTER category:{CATEGORY_CHANNEL} key:{topic} subkey:{channel}",
+                    client.addr()
+                );
+            }
+        }
+
+        let key = Registration::new(CATEGORY_TOPIC, topic, "");
+        if db.add_producer(key, Producer::new(peer_info.clone())) {
+            info!(
+                "DB: client({}) REGISTER category:{CATEGORY_TOPIC} key:{topic} subkey:",
+                client.addr()
+            );
+        }
+
+        Ok(OK_BYTES.to_vec())
+    }
+
+    fn unregister(&self, client: &ClientV1, params: &[&str]) -> Result<Vec<u8>> {
+        let peer_info = identified_peer(client)?;
+        let (topic, channel) = get_topic_chan("UNREGISTER", params)?;
+        let db = self.nsqlookupd.db();
+
+        if let Some(channel) = channel {
+            let key = Registration::new(CATEGORY_CHANNEL, topic, channel);
+            let (removed, left) = db.remove_producer(&key, &peer_info.id);
+            if removed {
+                info!(
+                    "DB: client({}) UNREGISTER category:{CATEGORY_CHANNEL} key:{topic} subkey:{channel}",
+                    client.addr()
+                );
+            }
+            // 临时channel没有producer之后直接删除
+            if left == 0 && is_ephemeral(channel) {
+                db.remove_registration(&key);
+            }
+            return Ok(OK_BYTES.to_vec());
+        }
+
+        // 删除topic时nsqd应该已经删除了所有的channel，这里还有channel说明出现了异常
+        for r in db.find_registrations(CATEGORY_CHANNEL, topic, "*") {
+            if db.remove_producer(&r, &peer_info.id).0 {
+                warn!(
+                    "client({}) unexpected UNREGISTER category:{CATEGORY_CHANNEL} key:{topic} subkey:{}",
+                    client.addr(),
+                    r.sub_key
+                );
+            }
+        }
+
+        let key = Registration::new(CATEGORY_TOPIC, topic, "");
+        let (removed, left) = db.remove_producer(&key, &peer_info.id);
+        if removed {
+            info!(
+                "DB: client({}) UNREGISTER category:{CATEGORY_TOPIC} key:{topic} subkey:",
+                client.addr()
+            );
+        }
+        if left == 0 && is_ephemeral(topic) {
+            db.remove_registration(&key);
+        }
+
+        Ok(OK_BYTES.to_vec())
+    }
+}
+
+fn identified_peer(client: &ClientV1) -> Result<&Arc<PeerInfo>> {
+    client.peer_info.as_ref().ok_or_else(|| {
+        NsqError::FatalClientErr(ErrorCode::Invalid, "client must IDENTIFY".to_owned())
+    })
+}
+
+fn get_topic_chan<'a>(cmd: &str, params: &[&'a str]) -> Result<(&'a str, Option<&'a str>)> {
+    let Some(&topic) = params.first() else {
+        return Err(NsqError::FatalClientErr(
+            ErrorCode::Invalid,
+            format!("{cmd} insufficient number of params"),
+        ));
+    };
+    let channel = params.get(1).copied().filter(|c| !c.is_empty());
+
+    if !is_valid_topic_name(topic) {
+        return Err(NsqError::FatalClientErr(
+            ErrorCode::BadTopic,
+            format!("{cmd} topic name {topic:?} is not valid"),
+        ));
+    }
+
+    if let Some(channel) = channel {
+        if !is_valid_channel_name(channel) {
+            return Err(NsqError::FatalClientErr(
+                ErrorCode::BadChannel,
+                format!("{cmd} channel name {channel:?} is not valid"),
+            ));
+        }
+    }
+
+    Ok((topic, channel))
+}
+
+// V1协议的响应没有帧类型，只有4字节的长度和数据
+pub(super) async fn send_response<W>(w: &mut W, data: &[u8]) -> Result<usize>
+where
+    W: AsyncWrite + Unpin,
+{
+    w.write_u32(data.len() as u32).await?;
+    w.write_all(data).await?;
+    w.flush().await?;
+    Ok(data.len() + 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{net::TcpStream, task::JoinHandle};
+
+    use super::*;
+    use crate::nsqlookupd::options::Options;
+
+    async fn start() -> (Arc<NSQLookupd>, JoinHandle<Result<()>>) {
+        let mut opts = Options::new();
+        opts.tcp_addr = "127.0.0.1:0".to_owned();
+        opts.http_addr = "127.0.0.1:0".to_owned();
+        let (nsqlookupd, _) = NSQLookupd::new(opts).await.unwrap();
+        let nsqlookupd = Arc::new(nsqlookupd);
+        let handle = tokio::spawn(nsqlookupd.clone().start());
+        (nsqlookupd, handle)
+    }
+
+    async fn connect(nsqlookupd: &NSQLookupd) -> TcpStream {
+        let mut conn = TcpStream::connect(nsqlookupd.tcp_addr()).await.unwrap();
+        conn.write_all(b"  V1").await.unwrap();
+        conn
+    }
+
+    // 连接关闭时返回None
+    async fn read_response(conn: &mut TcpStream) -> Option<Vec<u8>> {
+        let size = conn.read_u32().await.ok()?;
+        let mut data = vec![0; size as usize];
+        conn.read_exact(&mut data).await.ok()?;
+        Some(data)
+    }
+
+    #[tokio::test]
+    async fn identify_and_register() {
+        let (nsqlookupd, handle) = start().await;
+        let mut conn = connect(&nsqlookupd).await;
+
+        let body = br#"{"broadcast_address":"127.0.0.1","tcp_port":4150,"http_port":4151,"version":"1.0.0"}"#;
+        let mut buf = b"IDENTIFY\n".to_vec();
+        buf.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        buf.extend_from_slice(body);
+        conn.write_all(&buf).await.unwrap();
+        let resp: serde_json::Value =
+            serde_json::from_slice(&read_response(&mut conn).await.unwrap()).unwrap();
+        assert_eq!(resp["tcp_port"], nsqlookupd.tcp_addr().port());
+
+        conn.write_all(b"REGISTER topic channel\n").await.unwrap();
+        assert_eq!(read_response(&mut conn).await.unwrap(), OK_BYTES);
+        let producers = nsqlookupd
+            .db()
+            .find_producers(CATEGORY_CHANNEL, "topic", "channel");
+        assert_eq!(producers.len(), 1);
+
+        conn.write_all(b"UNREGISTER topic channel\n").await.unwrap();
+        assert_eq!(read_response(&mut conn).await.unwrap(), OK_BYTES);
+        let producers = nsqlookupd
+            .db()
+            .find_producers(CATEGORY_CHANNEL, "topic", "channel");
+        assert!(producers.is_empty());
+
+        nsqlookupd.exit();
+        handle.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn identify_body_too_big() {
+        let (nsqlookupd, handle) = start().await;
+        let mut conn = connect(&nsqlookupd).await;
+
+        // 不会按照客户端指定的长度分配内存
+        let mut buf = b"IDENTIFY\n".to_vec();
+        buf.extend_from_slice(&u32::MAX.to_be_bytes());
+        conn.write_all(&buf).await.unwrap();
+        let resp = read_response(&mut conn).await.unwrap();
+        assert!(resp.starts_with(b"E_BAD_BODY"));
+        assert!(read_response(&mut conn).await.is_none());
+
+        nsqlookupd.exit();
+        handle.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn command_too_long() {
+        let (nsqlookupd, handle) = start().await;
+        let mut conn = connect(&nsqlookupd).await;
+
+        conn.write_all(&vec![b'A'; MAX_LINE_LENGTH + 1])
+            .await
+            .unwrap();
+        let resp = read_response(&mut conn).await.unwrap();
+        assert!(resp.starts_with(b"E_INVALID"));
+        assert!(read_response(&mut conn).await.is_none());
+
+        nsqlookupd.exit();
+        handle.await.unwrap().unwrap();
+    }
+}
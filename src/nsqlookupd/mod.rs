@@ -0,0 +1,11 @@
+mod client_v1;
+mod http;
+mod lookup_protocol_v1;
+#[allow(clippy::module_inception)]
+mod nsqlookupd;
+mod options;
+mod registration_db;
+mod tcp_server;
+
+pub use nsqlookupd::NSQLookupd;
+pub use options::Options;
@@ -0,0 +1,90 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::warn;
+
+use super::{http, options::Options, registration_db::RegistrationDB, tcp_server};
+use crate::common::Result;
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct NSQLookupd {
+    tcp_listener: TcpListener,
+    http_listener: TcpListener,
+
+    exit_token: CancellationToken,
+
+    db: RegistrationDB,
+
+    opts: Options,
+}
+
+impl NSQLookupd {
+    pub async fn new(opts: Options) -> Result<(Self, CancellationToken)> {
+        let token = CancellationToken::new();
+
+        let tcp_listener = TcpListener::bind(&opts.tcp_addr).await?;
+        let http_listener = TcpListener::bind(&opts.http_addr).await?;
+
+        let nsqlookupd = NSQLookupd {
+            tcp_listener,
+            http_listener,
+            exit_token: token.clone(),
+            db: RegistrationDB::default(),
+            opts,
+        };
+
+        Ok((nsqlookupd, token))
+    }
+
+    pub async fn start(self: Arc<Self>) -> Result<()> {
+        let tracker = TaskTracker::new();
+        let (tx, _) = broadcast::channel(1);
+
+        tracker.spawn(tcp_server::serve(self.clone(), (&tx).into()));
+        tracker.spawn(http::serve(self.clone(), (&tx).into()));
+
+        // 等待退出信号
+        self.exit_token.cancelled().await;
+        warn!("NSQLookupd exiting");
+
+        // 通知所有组件退出
+        let _ = tx.send(());
+        tracker.close();
+
+        // 等待所有组件退出
+        tracker.wait().await;
+        Ok(())
+    }
+
+    pub fn exit(&self) {
+        self.exit_token.cancel();
+    }
+
+    pub fn get_opts(&self) -> &Options {
+        &self.opts
+    }
+
+    pub(super) fn db(&self) -> &RegistrationDB {
+        &self.db
+    }
+
+    pub fn tcp_addr(&self) -> SocketAddr {
+        self.tcp_listener.local_addr().unwrap()
+    }
+
+    pub(super) async fn tcp_accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.tcp_listener.accept().await
+    }
+
+    pub fn http_addr(&self) -> SocketAddr {
+        self.http_listener.local_addr().unwrap()
+    }
+
+    pub(super) async fn http_accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.http_listener.accept().await
+    }
+}
@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+pub struct Options {
+    pub tcp_addr: String,
+    pub http_addr: String,
+    pub broadcast_addr: String,
+
+    // 超过这个时间没有收到PING的producer不会出现在查询结果中
+    pub inactive_producer_timeout: Duration,
+    // producer被tombstone之后，在这段时间内不会出现在/lookup的结果中
+    pub tombstone_lifetime: Duration,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self {
+            tcp_addr: "0.0.0.0:4160".to_owned(),
+            http_addr: "0.0.0.0:4161".to_owned(),
+            broadcast_addr: hostname::get()
+                .map(|h| h.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+
+            inactive_producer_timeout: Duration::from_secs(300),
+            tombstone_lifetime: Duration::from_secs(45),
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
+}
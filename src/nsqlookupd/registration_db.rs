@@ -0,0 +1,211 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::unix_nanos;
+
+pub(super) const CATEGORY_CLIENT: &str = "client";
+pub(super) const CATEGORY_TOPIC: &str = "topic";
+pub(super) const CATEGORY_CHANNEL: &str = "channel";
+
+// key或者sub_key为*时匹配所有
+const WILDCARD: &str = "*";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct Registration {
+    pub category: &'static str,
+    pub key: String,
+    pub sub_key: String,
+}
+
+impl Registration {
+    pub fn new(category: &'static str, key: &str, sub_key: &str) -> Self {
+        Self {
+            category,
+            key: key.to_owned(),
+            sub_key: sub_key.to_owned(),
+        }
+    }
+
+    pub fn is_match(&self, category: &str, key: &str, sub_key: &str) -> bool {
+        self.category == category
+            && (key == WILDCARD || self.key == key)
+            && (sub_key == WILDCARD || self.sub_key == sub_key)
+    }
+}
+
+// nsqd在IDENTIFY时上报的节点信息
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(super) struct PeerInfo {
+    // 最近一次IDENTIFY或者PING的时间，单位纳秒
+    #[serde(skip)]
+    last_update: AtomicI64,
+    #[serde(skip)]
+    pub id: String,
+    pub remote_address: String,
+    pub hostname: String,
+    pub broadcast_address: String,
+    pub tcp_port: u16,
+    pub http_port: u16,
+    pub version: String,
+}
+
+impl PeerInfo {
+    pub fn last_update(&self) -> i64 {
+        self.last_update.load(Ordering::SeqCst)
+    }
+
+    pub fn update(&self) {
+        self.last_update.store(unix_nanos(), Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Producer {
+    pub peer_info: Arc<PeerInfo>,
+    tombstoned_at: Option<Instant>,
+}
+
+impl Producer {
+    pub fn new(peer_info: Arc<PeerInfo>) -> Self {
+        Self {
+            peer_info,
+            tombstoned_at: None,
+        }
+    }
+
+    pub fn is_tombstoned(&self, lifetime: Duration) -> bool {
+        self.tombstoned_at.is_some_and(|t| t.elapsed() < lifetime)
+    }
+}
+
+// 每个registration下的producer，以peer id为key
+type ProducerMap = HashMap<String, Producer>;
+
+#[derive(Default)]
+pub(super) struct RegistrationDB {
+    registration_map: RwLock<HashMap<Registration, ProducerMap>>,
+}
+
+impl RegistrationDB {
+    pub fn add_registration(&self, k: Registration) {
+        self.registration_map.write().unwrap().entry(k).or_default();
+    }
+
+    // producer已经存在时返回false
+    pub fn add_producer(&self, k: Registration, p: Producer) -> bool {
+        let mut registration_map = self.registration_map.write().unwrap();
+        let producers = registration_map.entry(k).or_default();
+        if producers.contains_key(&p.peer_info.id) {
+            return false;
+        }
+        producers.insert(p.peer_info.id.clone(), p);
+        true
+    }
+
+    // 返回是否删除成功以及剩余的producer数量。
+    // producer全部删除之后registration仍然保留
+    pub fn remove_producer(&self, k: &Registration, id: &str) -> (bool, usize) {
+        let mut registration_map = self.registration_map.write().unwrap();
+        let Some(producers) = registration_map.get_mut(k) else {
+            return (false, 0);
+        };
+        let removed = producers.remove(id).is_some();
+        (removed, producers.len())
+    }
+
+    // 下线某个nsqd上的topic时先tombstone，让消费者不再连接这个nsqd
+    pub fn tombstone_producer(&self, k: &Registration, id: &str) {
+        let mut registration_map = self.registration_map.write().unwrap();
+        if let Some(p) = registration_map
+            .get_mut(k)
+            .and_then(|producers| producers.get_mut(id))
+        {
+            p.tombstoned_at = Some(Instant::now());
+        }
+    }
+
+    pub fn remove_registration(&self, k: &Registration) {
+        self.registration_map.write().unwrap().remove(k);
+    }
+
+    pub fn find_registrations(
+        &self,
+        category: &'static str,
+        key: &str,
+        sub_key: &str,
+    ) -> Vec<Registration> {
+        let registration_map = self.registration_map.read().unwrap();
+        if !need_filter(key, sub_key) {
+            let k = Registration::new(category, key, sub_key);
+            if registration_map.contains_key(&k) {
+                return vec![k];
+            }
+            return Vec::new();
+        }
+
+        registration_map
+            .keys()
+            .filter(|k| k.is_match(category, key, sub_key))
+            .cloned()
+            .collect()
+    }
+
+    // 同一个producer可能出现在多个registration中，只返回一次
+    pub fn find_producers(&self, category: &str, key: &str, sub_key: &str) -> Vec<Producer> {
+        let registration_map = self.registration_map.read().unwrap();
+        let mut seen = HashSet::new();
+        registration_map
+            .iter()
+            .filter(|(k, _)| k.is_match(category, key, sub_key))
+            .flat_map(|(_, producers)| producers.values())
+            .filter(|p| seen.insert(p.peer_info.id.clone()))
+            .cloned()
+            .collect()
+    }
+
+    // 包含指定producer的所有registration
+    pub fn lookup_registrations(&self, id: &str) -> Vec<Registration> {
+        self.registration_map
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, producers)| producers.contains_key(id))
+            .map(|(k, _)| k.clone())
+            .collect()
+    }
+}
+
+fn need_filter(key: &str, sub_key: &str) -> bool {
+    key == WILDCARD || sub_key == WILDCARD
+}
+
+pub(super) fn keys(registrations: &[Registration]) -> Vec<String> {
+    registrations.iter().map(|r| r.key.clone()).collect()
+}
+
+pub(super) fn sub_keys(registrations: &[Registration]) -> Vec<String> {
+    registrations.iter().map(|r| r.sub_key.clone()).collect()
+}
+
+// 过滤掉超过inactivity_timeout没有PING的producer，以及还在tombstone_lifetime内的producer
+pub(super) fn filter_by_active(
+    producers: Vec<Producer>,
+    inactivity_timeout: Duration,
+    tombstone_lifetime: Duration,
+) -> Vec<Producer> {
+    let now = unix_nanos();
+    producers
+        .into_iter()
+        .filter(|p| now - p.peer_info.last_update() <= inactivity_timeout.as_nanos() as i64)
+        .filter(|p| !p.is_tombstoned(tombstone_lifetime))
+        .collect()
+}
@@ -0,0 +1,81 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{
+    io::{AsyncReadExt, BufReader},
+    net::TcpStream,
+    select,
+};
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, warn};
+
+use super::{
+    lookup_protocol_v1::{send_response, LookupProtocolV1},
+    nsqlookupd::NSQLookupd,
+};
+use crate::{errors::ErrorCode, shutdown::Shutdown};
+
+pub(super) async fn serve(nsqlookupd: Arc<NSQLookupd>, mut shutdown: Shutdown) {
+    info!("TCP: listening on {}", nsqlookupd.tcp_addr());
+
+    let tracker = TaskTracker::new();
+    loop {
+        select! {
+            res = nsqlookupd.tcp_accept() => {
+                let (conn, addr) = match res {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("TCP: accept error - {e}");
+                        continue;
+                    }
+                };
+                tracker.spawn(handle(nsqlookupd.clone(), conn, addr, shutdown.clone()));
+            },
+            _ = shutdown.recv() => {
+                info!("TCP: closing {}", nsqlookupd.tcp_addr());
+                break;
+            }
+        }
+    }
+
+    // 等待所有连接处理完
+    tracker.close();
+    tracker.wait().await;
+}
+
+async fn handle(
+    nsqlookupd: Arc<NSQLookupd>,
+    mut conn: TcpStream,
+    addr: SocketAddr,
+    shutdown: Shutdown,
+) {
+    info!("TCP: new client({addr})");
+
+    // 客户端连接后需要先发送4字节的magic，用于确定协议版本
+    let mut magic = [0u8; 4];
+    if let Err(e) = conn.read_exact(&mut magic).await {
+        error!("failed to read protocol version - {e}");
+        return;
+    }
+
+    match &magic {
+        b"  V1" => {
+            let (reader, writer) = conn.into_split();
+            let protocol = LookupProtocolV1::new(nsqlookupd);
+            if let Err(e) = protocol
+                .io_loop(BufReader::new(reader), writer, addr, shutdown)
+                .await
+            {
+                error!("client({addr}) - {e}");
+            }
+        }
+        _ => {
+            error!(
+                "client({addr}) bad protocol magic '{}'",
+                String::from_utf8_lossy(&magic)
+            );
+            let _ = send_response(&mut conn, ErrorCode::BadProtocol.as_str().as_bytes()).await;
+        }
+    }
+
+    info!("TCP: client({addr}) closed");
+}
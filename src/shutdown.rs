@@ -1,6 +1,6 @@
 use tokio::sync::broadcast::{self, Sender};
 
-pub(super) struct Shutdown {
+pub(crate) struct Shutdown {
     is_shutdown: bool,
     notify: broadcast::Receiver<()>,
 }
@@ -13,10 +13,6 @@ impl Shutdown {
         }
     }
 
-    pub fn is_shutdown(&self) -> bool {
-        self.is_shutdown
-    }
-
     pub async fn recv(&mut self) {
         if self.is_shutdown {
             return;